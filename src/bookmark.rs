@@ -1,54 +1,436 @@
+use serde::{Deserialize, Serialize};
 use std::{
+    ffi::OsString,
     fmt::{Display, Formatter},
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
     str,
 };
 
-#[derive(PartialEq, Eq, Hash, Ord, PartialOrd, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Ord, PartialOrd, Clone, Debug, Serialize, Deserialize)]
 pub struct Bookmark {
-    path: String,
+    #[serde(with = "path_serde")]
+    path: PathBuf,
+    /// ディレクトリかファイルか (以前のファイルにはないのでディレクトリとみなす)
+    #[serde(default, skip_serializing_if = "Kind::is_dir")]
+    kind: Kind,
+    /// `bm go`で使う別名 (プロファイル内で一意)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default)]
     tags: Vec<String>,
+    /// 使えるホストの名前 (空ならどのホストでも使える)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    hosts: Vec<String>,
+    /// 検索や`bm go`で選ばれた回数 (古い訪問ほど減らされる)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    visits: u32,
+    /// 最後に選ばれた時刻 (UNIXエポックからの秒数)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_visited: Option<u64>,
+    /// ディレクトリが移動された後に探し出すための手がかり
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<Fingerprint>,
+}
+
+/// ブックマークしたパスの種類
+#[derive(
+    PartialEq, Eq, Hash, Ord, PartialOrd, Clone, Copy, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Dir,
+    File,
+}
+
+impl Kind {
+    fn is_dir(&self) -> bool {
+        *self == Kind::Dir
+    }
+
+    /// 保存ファイルや`list --format`での表記
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Dir => "dir",
+            Kind::File => "file",
+        }
+    }
+}
+
+/// 登録時のディレクトリやファイルの特徴
+#[derive(PartialEq, Eq, Hash, Ord, PartialOrd, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// デバイス番号とiノード番号 (同じファイルシステム内での移動なら変わらない)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inode: Option<(u64, u64)>,
+    /// gitリポジトリのリモートのURL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_remote: Option<String>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
 }
 
 impl Bookmark {
-    pub fn new(path: &str, tags: Vec<String>) -> Self {
+    pub fn new<P: AsRef<Path>>(path: P, tags: Vec<String>) -> Self {
         Self {
-            path: path.to_string(),
-            tags: tags,
+            path: path.as_ref().to_path_buf(),
+            kind: Kind::Dir,
+            name: None,
+            tags,
+            hosts: Vec::new(),
+            visits: 0,
+            last_visited: None,
+            fingerprint: None,
         }
     }
 
-    pub fn get_path(&self) -> &str {
+    /// 別名を付ける
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// 訪問の記録を付ける (他のツールから取り込む場合など)
+    pub fn with_visits(mut self, visits: u32, last_visited: Option<u64>) -> Self {
+        self.visits = visits;
+        self.last_visited = last_visited;
+        self
+    }
+
+    /// ファイルのブックマークにする場合などに種類を指定する
+    pub fn with_kind(mut self, kind: Kind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// 使えるホストを限る (空ならどのホストでも使える)
+    pub fn with_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.hosts = hosts;
+        self
+    }
+
+    pub fn with_fingerprint(mut self, fingerprint: Option<Fingerprint>) -> Self {
+        self.fingerprint = fingerprint;
+        self
+    }
+
+    pub fn get_path(&self) -> &Path {
         &self.path
     }
 
+    pub fn get_kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// ディレクトリならそのパス、ファイルならそれを含むディレクトリ
+    pub fn get_dir(&self) -> &Path {
+        match self.kind {
+            Kind::Dir => &self.path,
+            Kind::File => self.path.parent().unwrap_or(&self.path),
+        }
+    }
+
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn set_tags(&mut self, tags: Vec<String>) {
+        self.tags = tags;
+    }
+
+    pub fn get_hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    pub fn get_fingerprint(&self) -> Option<&Fingerprint> {
+        self.fingerprint.as_ref()
+    }
+
+    /// 移動したディレクトリを指すようにする。別名やタグ、訪問の記録はそのまま残す
+    pub fn relocate(&mut self, path: PathBuf, fingerprint: Option<Fingerprint>) {
+        self.path = path;
+        self.fingerprint = fingerprint;
+    }
+
+    pub fn get_visits(&self) -> u32 {
+        self.visits
+    }
+
+    pub fn get_last_visited(&self) -> Option<u64> {
+        self.last_visited
+    }
+
+    /// 選ばれたことを記録する
+    pub fn visit(&mut self, now: u64) {
+        self.visits = self.visits.saturating_add(1);
+        self.last_visited = Some(now);
+    }
+
+    /// 別のブックマークの訪問の記録を引き継ぐ
+    pub fn inherit_visits(&mut self, other: &Bookmark) {
+        self.visits = other.visits;
+        self.last_visited = other.last_visited;
+    }
+
+    /// 同じ場所を指す別のブックマークをまとめる。タグと使えるホストは合わせ、訪問の記録は合計する。
+    /// 別名はこちらになければ引き継ぐ
+    pub fn merge(&mut self, other: &Bookmark) {
+        for tag in &other.tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+        // どちらかがどのホストでも使えるなら、まとめたものもどのホストでも使える
+        if self.hosts.is_empty() || other.hosts.is_empty() {
+            self.hosts.clear();
+        } else {
+            for host in &other.hosts {
+                if !self.hosts.contains(host) {
+                    self.hosts.push(host.clone());
+                }
+            }
+        }
+        if self.name.is_none() {
+            self.name = other.name.clone();
+        }
+        self.visits = self.visits.saturating_add(other.visits);
+        self.last_visited = self.last_visited.max(other.last_visited);
+    }
+
+    /// 訪問回数を`factor`倍する (端数は切り捨てる)
+    pub fn scale_visits(&mut self, factor: f64) {
+        self.visits = (self.visits as f64 * factor) as u32;
+    }
+
+    /// パスが存在しないか、ディレクトリとファイルが入れ替わっていれば壊れているとみなす
     pub fn is_broken(&self) -> Result<bool, std::io::Error> {
-        match std::fs::exists(&self.path) {
-            Ok(exists) => Ok(!exists),
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) => Ok(metadata.is_dir() != self.kind.is_dir()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(true),
             Err(e) => Err(e),
         }
     }
 
-    pub fn parse(s: String) -> Self {
+    /// `Display` で出力された1行をパースする
+    ///
+    /// パス、別名、タグは空白区切りのトークンで、各トークン内の空白・制御文字・
+    /// UTF-8として不正なバイトはバックスラッシュでエスケープされている。
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let mut tokens = split_tokens(s)?.into_iter();
+        let path = match tokens.next() {
+            Some(token) => bytes_to_path(token.bytes)?,
+            None => return Err(invalid_data("missing path")),
+        };
+        let mut name = None;
+        let mut tags = Vec::new();
+        for token in tokens {
+            match token.kind {
+                TokenKind::Tag => tags.push(
+                    String::from_utf8(token.bytes)
+                        .map_err(|_| invalid_data("tag is not valid UTF-8"))?,
+                ),
+                TokenKind::Name if name.is_none() => {
+                    name = Some(
+                        String::from_utf8(token.bytes)
+                            .map_err(|_| invalid_data("name is not valid UTF-8"))?,
+                    )
+                }
+                TokenKind::Name => return Err(invalid_data("multiple names")),
+                TokenKind::Plain => {
+                    return Err(invalid_data(&format!(
+                        "unexpected token (tags must start with '#'): {}",
+                        String::from_utf8_lossy(&token.bytes)
+                    )))
+                }
+            }
+        }
+        Ok(Self::new(path, tags).with_name(name))
+    }
+
+    /// エスケープが導入される前の形式 (空白区切り) の1行をパースする
+    pub fn parse_legacy(s: &str) -> Self {
         let mut parts = s.split_whitespace();
-        let path = parts.next().unwrap_or("").to_string();
+        let path = PathBuf::from(parts.next().unwrap_or(""));
         let tags = parts
             .filter(|part| part.starts_with('#'))
             .map(|tag| tag[1..].to_string())
             .collect();
-        Self { path, tags }
+        Self::new(path, tags)
     }
 }
 
 // NOTE: 選択肢の文字列のためにto_stringが実装されるようにする
+// パースで元に戻せるよう、パスとタグはエスケープして出力する
 impl Display for Bookmark {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         let mut parts: Vec<String> = Vec::new();
-        parts.push(self.path.clone());
-        parts.extend(self.tags.iter().map(|tag| format!("#{}", tag)));
+        parts.push(escape(self.path.as_os_str().as_encoded_bytes()));
+        parts.extend(
+            self.name
+                .iter()
+                .map(|name| format!("@{}", escape(name.as_bytes()))),
+        );
+        parts.extend(
+            self.tags
+                .iter()
+                .map(|tag| format!("#{}", escape(tag.as_bytes()))),
+        );
         write!(f, "{}", parts.join(" "))
     }
 }
 
+struct Token {
+    bytes: Vec<u8>,
+    kind: TokenKind,
+}
+
+#[derive(Clone, Copy)]
+enum TokenKind {
+    /// パス
+    Plain,
+    /// エスケープされていない`#`で始まるトークン
+    Tag,
+    /// 2番目以降の、エスケープされていない`@`で始まるトークン
+    Name,
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                ' ' => escaped.push_str("\\ "),
+                '\t' => escaped.push_str("\\t"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '#' if escaped.is_empty() => escaped.push_str("\\#"),
+                c if c.is_control() => {
+                    let mut buf = [0; 4];
+                    for b in c.encode_utf8(&mut buf).bytes() {
+                        escaped.push_str(&format!("\\x{:02x}", b));
+                    }
+                }
+                c => escaped.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    escaped
+}
+
+fn split_tokens(s: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut current: Option<Token> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == ' ' || c == '\t' {
+            tokens.extend(current.take());
+            continue;
+        }
+        let token = match current {
+            Some(ref mut token) => token,
+            None if c == '#' || (c == '@' && !tokens.is_empty()) => {
+                // タグや別名の先頭の記号は値に含めない
+                let kind = if c == '#' {
+                    TokenKind::Tag
+                } else {
+                    TokenKind::Name
+                };
+                current = Some(Token {
+                    bytes: Vec::new(),
+                    kind,
+                });
+                continue;
+            }
+            None => current.insert(Token {
+                bytes: Vec::new(),
+                kind: TokenKind::Plain,
+            }),
+        };
+        if c != '\\' {
+            let mut buf = [0; 4];
+            token
+                .bytes
+                .extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => token.bytes.push(b'\\'),
+            Some(' ') => token.bytes.push(b' '),
+            Some('#') => token.bytes.push(b'#'),
+            Some('t') => token.bytes.push(b'\t'),
+            Some('n') => token.bytes.push(b'\n'),
+            Some('r') => token.bytes.push(b'\r'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| invalid_data(&format!("invalid escape: \\x{}", hex)))?;
+                token.bytes.push(byte);
+            }
+            Some(other) => return Err(invalid_data(&format!("invalid escape: \\{}", other))),
+            None => return Err(invalid_data("trailing backslash")),
+        }
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+#[cfg(unix)]
+pub fn bytes_to_path(bytes: Vec<u8>) -> Result<PathBuf, Error> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+pub fn bytes_to_path(bytes: Vec<u8>) -> Result<PathBuf, Error> {
+    let s = String::from_utf8(bytes).map_err(|_| invalid_data("path is not valid UTF-8"))?;
+    Ok(PathBuf::from(OsString::from(s)))
+}
+
+/// パスはUTF-8として正しければ文字列、そうでなければバイト列として保存する
+mod path_serde {
+    use super::bytes_to_path;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::path::{Path, PathBuf};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawPath {
+        Str(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(s) => serializer.serialize_str(s),
+            None => serializer.serialize_bytes(path.as_os_str().as_encoded_bytes()),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        match RawPath::deserialize(deserializer)? {
+            RawPath::Str(s) => Ok(PathBuf::from(s)),
+            RawPath::Bytes(bytes) => bytes_to_path(bytes).map_err(de::Error::custom),
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,6 +439,9 @@ mod tests {
     #[rstest]
     #[case("path", vec!["tag1", "tag2"], "path #tag1 #tag2")]
     #[case("path", vec![], "path")]
+    #[case("/My Projects/a#b", vec!["my tag"], "/My\\ Projects/a#b #my\\ tag")]
+    #[case("#path\\x", vec!["#tag"], "\\#path\\\\x #\\#tag")]
+    #[case("/a\tb\nc", vec![], "/a\\tb\\nc")]
     fn test_to_string(#[case] path: &str, #[case] tags: Vec<&str>, #[case] expected: String) {
         let tags = tags.into_iter().map(|tag| tag.to_string()).collect();
         let bookmark = Bookmark::new(path, tags);
@@ -66,19 +451,193 @@ mod tests {
     #[rstest]
     #[case("path/to/sample #tag1 #tag2", "path/to/sample", vec!["tag1", "tag2"])]
     #[case("path/to/sample", "path/to/sample", vec![])]
+    #[case("/My\\ Projects  #a\\ b\t#c", "/My Projects", vec!["a b", "c"])]
+    #[case("\\#x\\x41 ##y", "#xA", vec!["#y"])]
     fn test_parse(
-        #[case] input: String,
-        #[case] expected_path: String,
+        #[case] input: &str,
+        #[case] expected_path: &str,
         #[case] expected_tags: Vec<&str>,
     ) {
-        let actual_bookmark = Bookmark::parse(input);
-        let expected_bookmark = Bookmark {
-            path: expected_path,
-            tags: expected_tags
+        let actual_bookmark = Bookmark::parse(input).unwrap();
+        let expected_bookmark = Bookmark::new(
+            expected_path,
+            expected_tags
                 .into_iter()
                 .map(|tag| tag.to_string())
                 .collect(),
-        };
+        );
         assert_eq!(actual_bookmark, expected_bookmark);
     }
+
+    #[rstest]
+    // 別名はパスの後に`@`を付けて出力され、元に戻せること
+    #[case(Bookmark::new("/path", vec!["tag".to_string()]).with_name(Some("api".to_string())), "/path @api #tag")]
+    // パスの先頭の`@`は別名として扱わないこと
+    #[case(Bookmark::new("@path", vec![]).with_name(Some("my api".to_string())), "@path @my\\ api")]
+    fn test_round_trip_name(#[case] bookmark: Bookmark, #[case] expected: &str) {
+        assert_eq!(bookmark.to_string(), expected);
+        assert_eq!(Bookmark::parse(expected).unwrap(), bookmark);
+    }
+
+    #[rstest]
+    // 空行
+    #[case("")]
+    // タグが`#`で始まっていない
+    #[case("/path tag")]
+    // 不正なエスケープ
+    #[case("/path\\q")]
+    #[case("/path\\x4")]
+    #[case("/path\\")]
+    // 別名が複数ある
+    #[case("/path @a @b")]
+    fn test_parse_invalid(#[case] input: &str) {
+        assert!(Bookmark::parse(input).is_err());
+    }
+
+    #[rstest]
+    #[case("path/to/sample #tag1 #tag2", "path/to/sample", vec!["tag1", "tag2"])]
+    #[case("path/to/sample ignored #tag1", "path/to/sample", vec!["tag1"])]
+    fn test_parse_legacy(
+        #[case] input: &str,
+        #[case] expected_path: &str,
+        #[case] expected_tags: Vec<&str>,
+    ) {
+        let tags = expected_tags.into_iter().map(|t| t.to_string()).collect();
+        assert_eq!(
+            Bookmark::parse_legacy(input),
+            Bookmark::new(expected_path, tags)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    // UTF-8として不正なバイトを含むパスも元に戻せること
+    fn test_round_trip_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"/tmp/\xff\xfe dir"));
+        let bookmark = Bookmark::new(path, vec!["tag".to_string()]);
+        assert_eq!(bookmark.to_string(), "/tmp/\\xff\\xfe\\ dir #tag");
+        assert_eq!(Bookmark::parse(&bookmark.to_string()).unwrap(), bookmark);
+
+        let json = serde_json::to_string(&bookmark).unwrap();
+        assert_eq!(
+            json,
+            r#"{"path":[47,116,109,112,47,255,254,32,100,105,114],"tags":["tag"]}"#
+        );
+        assert_eq!(serde_json::from_str::<Bookmark>(&json).unwrap(), bookmark);
+    }
+
+    #[test]
+    // JSONで保存されたブックマークを読み込めること (タグは省略可能)
+    fn test_deserialize() {
+        let bookmark: Bookmark = serde_json::from_str(r#"{"path":"/My Projects"}"#).unwrap();
+        assert_eq!(bookmark, Bookmark::new("/My Projects", vec![]));
+    }
+
+    #[test]
+    // 訪問の記録はある場合だけ保存されること
+    fn test_serialize_visits() {
+        let mut bookmark = Bookmark::new("/path", vec![]);
+        assert_eq!(
+            serde_json::to_string(&bookmark).unwrap(),
+            r#"{"path":"/path","tags":[]}"#
+        );
+
+        bookmark.visit(1_700_000_000);
+        let json = serde_json::to_string(&bookmark).unwrap();
+        assert_eq!(
+            json,
+            r#"{"path":"/path","tags":[],"visits":1,"last_visited":1700000000}"#
+        );
+        assert_eq!(serde_json::from_str::<Bookmark>(&json).unwrap(), bookmark);
+    }
+
+    #[test]
+    // 移動先を探す手がかりが保存されること
+    fn test_serialize_fingerprint() {
+        let bookmark = Bookmark::new("/path", vec![]).with_fingerprint(Some(Fingerprint {
+            inode: Some((1, 2)),
+            git_remote: None,
+        }));
+        let json = serde_json::to_string(&bookmark).unwrap();
+        assert_eq!(
+            json,
+            r#"{"path":"/path","tags":[],"fingerprint":{"inode":[1,2]}}"#
+        );
+        assert_eq!(serde_json::from_str::<Bookmark>(&json).unwrap(), bookmark);
+    }
+
+    #[test]
+    // タグは重複せずに合わさり、訪問の記録は合計され、別名は元のものが優先されること
+    fn test_merge() {
+        let mut bookmark = Bookmark::new("/srv/app", vec!["a".to_string(), "b".to_string()])
+            .with_visits(2, Some(100));
+        let other = Bookmark::new("/srv/app/", vec!["b".to_string(), "c".to_string()])
+            .with_name(Some("app".to_string()))
+            .with_visits(3, Some(200));
+
+        bookmark.merge(&other);
+        assert_eq!(bookmark.get_tags(), ["a", "b", "c"]);
+        assert_eq!(bookmark.get_name(), Some("app"));
+        assert_eq!(bookmark.get_visits(), 5);
+        assert_eq!(bookmark.get_last_visited(), Some(200));
+
+        bookmark.merge(&Bookmark::new("/srv/./app", vec![]).with_name(Some("x".to_string())));
+        assert_eq!(bookmark.get_name(), Some("app"));
+        assert_eq!(bookmark.get_last_visited(), Some(200));
+    }
+
+    #[rstest]
+    #[case(vec!["a"], vec!["b", "a"], vec!["a", "b"])]
+    // どちらかがどのホストでも使えるなら、どのホストでも使えること
+    #[case(vec!["a"], vec![], vec![])]
+    #[case(vec![], vec!["a"], vec![])]
+    fn test_merge_hosts(
+        #[case] hosts: Vec<&str>,
+        #[case] other: Vec<&str>,
+        #[case] expected: Vec<&str>,
+    ) {
+        let to_strings = |hosts: Vec<&str>| hosts.into_iter().map(String::from).collect();
+        let mut bookmark = Bookmark::new("/srv/app", vec![]).with_hosts(to_strings(hosts));
+        bookmark.merge(&Bookmark::new("/srv/app/", vec![]).with_hosts(to_strings(other)));
+        assert_eq!(bookmark.get_hosts(), expected);
+    }
+
+    #[test]
+    // 使えるホストは限られている場合だけ保存されること
+    fn test_serialize_hosts() {
+        let bookmark = Bookmark::new("/path", vec![]).with_hosts(vec!["devbox".to_string()]);
+        let json = serde_json::to_string(&bookmark).unwrap();
+        assert_eq!(json, r#"{"path":"/path","tags":[],"hosts":["devbox"]}"#);
+        assert_eq!(serde_json::from_str::<Bookmark>(&json).unwrap(), bookmark);
+        let json = serde_json::to_string(&Bookmark::new("/path", vec![])).unwrap();
+        assert_eq!(json, r#"{"path":"/path","tags":[]}"#);
+    }
+
+    #[test]
+    // ファイルのブックマークだけ種類が保存されること
+    fn test_serialize_kind() {
+        let bookmark = Bookmark::new("/path/notes.md", vec![]).with_kind(Kind::File);
+        let json = serde_json::to_string(&bookmark).unwrap();
+        assert_eq!(json, r#"{"path":"/path/notes.md","kind":"file","tags":[]}"#);
+        assert_eq!(serde_json::from_str::<Bookmark>(&json).unwrap(), bookmark);
+        assert_eq!(bookmark.get_dir(), Path::new("/path"));
+    }
+
+    #[rstest]
+    #[case(Kind::Dir, "dir", false)]
+    #[case(Kind::Dir, "file.txt", true)]
+    #[case(Kind::File, "file.txt", false)]
+    #[case(Kind::File, "dir", true)]
+    #[case(Kind::File, "missing", true)]
+    // 存在しないパスや、種類が変わったパスは壊れているとみなすこと
+    fn test_is_broken(#[case] kind: Kind, #[case] name: &str, #[case] expected: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("dir")).unwrap();
+        std::fs::write(dir.path().join("file.txt"), "").unwrap();
+
+        let bookmark = Bookmark::new(dir.path().join(name), vec![]).with_kind(kind);
+        assert_eq!(bookmark.is_broken().unwrap(), expected);
+    }
 }
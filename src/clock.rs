@@ -0,0 +1,27 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+pub trait Clock {
+    /// 現在時刻 (UNIXエポックからの秒数)
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock {}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,239 @@
+use clap::{Command, ValueEnum};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use std::ffi::OsString;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use crate::config::StoreKind;
+
+/// 補完の候補にする、保存されている値
+#[derive(Default, Clone, Debug)]
+pub struct Candidates {
+    /// ブックマークの名前
+    pub names: Vec<String>,
+    /// タグ (よく使われているものから)
+    pub tags: Vec<String>,
+    /// プロファイルの名前
+    pub profiles: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Kind {
+    Names,
+    Tags,
+    Profiles,
+}
+
+/// サブコマンドの経路と引数のIDから、どの値で補完するかを決める
+fn kind_of(path: &[&str], arg: &str) -> Option<Kind> {
+    match (path, arg) {
+        ([], "profile") => Some(Kind::Profiles),
+        (["go"], "name") => Some(Kind::Names),
+        (["delete"], "bookmarks") => Some(Kind::Names),
+        (["mv"], "bookmark") => Some(Kind::Names),
+        (["tag", _], "bookmark") => Some(Kind::Names),
+        (["tag", _], "old" | "from" | "into") => Some(Kind::Tags),
+        (["profile", "delete" | "default"], "name") => Some(Kind::Profiles),
+        (["profile", "rename"], "from") => Some(Kind::Profiles),
+        // `add --tags`、`tag add`などのタグ、絞り込みの`--tag`
+        (_, "tags") => Some(Kind::Tags),
+        _ => None,
+    }
+}
+
+impl Candidates {
+    fn values(&self, kind: Kind) -> &[String] {
+        match kind {
+            Kind::Names => &self.names,
+            Kind::Tags => &self.tags,
+            Kind::Profiles => &self.profiles,
+        }
+    }
+
+    /// 名前やタグを取る引数に、保存されている値を補完の候補として付ける
+    pub fn attach(&self, cmd: Command) -> Command {
+        self.attach_to(cmd, &mut Vec::new())
+    }
+
+    fn attach_to(&self, mut cmd: Command, path: &mut Vec<String>) -> Command {
+        let ids: Vec<String> = cmd
+            .get_arguments()
+            .map(|arg| arg.get_id().to_string())
+            .collect();
+        for id in ids {
+            let path: Vec<&str> = path.iter().map(String::as_str).collect();
+            let Some(kind) = kind_of(&path, &id) else {
+                continue;
+            };
+            let values = self.values(kind).to_vec();
+            cmd = cmd.mut_arg(id, |arg| {
+                arg.add(ArgValueCandidates::new(move || {
+                    values.iter().map(CompletionCandidate::new).collect()
+                }))
+            });
+        }
+        let names: Vec<String> = cmd
+            .get_subcommands()
+            .map(|sub| sub.get_name().to_string())
+            .collect();
+        for name in names {
+            cmd = cmd.mut_subcommand(&name, |sub| {
+                path.push(name.clone());
+                let sub = self.attach_to(sub, path);
+                path.pop();
+                sub
+            });
+        }
+        cmd
+    }
+}
+
+/// `args[index]`を補完し、候補を`値<TAB>説明`の形の行で返す
+pub fn complete(
+    mut cmd: Command,
+    args: Vec<OsString>,
+    index: usize,
+    current_dir: Option<&Path>,
+) -> Result<Vec<String>, Error> {
+    let candidates = clap_complete::engine::complete(&mut cmd, args, index, current_dir)?;
+    Ok(candidates
+        .into_iter()
+        .filter(|c| !c.is_hide_set())
+        .map(|c| {
+            let value = c.get_value().to_string_lossy().into_owned();
+            match c.get_help() {
+                Some(help) => format!("{}\t{}", value, help),
+                None => value,
+            }
+        })
+        .collect())
+}
+
+/// 補完中のコマンドラインで指定された、保存先とプロファイルを選ぶオプション
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct GlobalOptions {
+    pub store: Option<StoreKind>,
+    pub file: Option<PathBuf>,
+    pub profile: Option<String>,
+}
+
+impl GlobalOptions {
+    /// 補完する位置より前の、サブコマンドまでの引数から取り出す (最初の引数はコマンド名)
+    pub fn parse(args: &[OsString]) -> Self {
+        let mut options = Self::default();
+        let mut words = args
+            .iter()
+            .skip(1)
+            .map(|arg| arg.to_string_lossy().into_owned());
+        while let Some(word) = words.next() {
+            let (key, inline) = match word.split_once('=') {
+                Some((key, value)) if key.starts_with("--") => {
+                    (key.to_string(), Some(value.to_string()))
+                }
+                _ if word.starts_with("-p") && word.len() > 2 => {
+                    ("-p".to_string(), Some(word[2..].to_string()))
+                }
+                _ => (word, None),
+            };
+            // `add --file`のようにサブコマンドにも同じ名前のオプションがある
+            if !key.starts_with('-') {
+                break;
+            }
+            if !matches!(key.as_str(), "--store" | "--file" | "--profile" | "-p") {
+                continue;
+            }
+            let Some(value) = inline.or_else(|| words.next()) else {
+                break;
+            };
+            match key.as_str() {
+                "--store" => options.store = StoreKind::from_str(&value, true).ok(),
+                "--file" => options.file = Some(PathBuf::from(value)),
+                _ => options.profile = Some(value),
+            }
+        }
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+    use rstest::rstest;
+
+    fn command() -> Command {
+        Command::new("bm")
+            .arg(Arg::new("profile").long("profile").short('p'))
+            .subcommand(Command::new("go").arg(Arg::new("name")))
+            .subcommand(
+                Command::new("add").arg(
+                    Arg::new("tags")
+                        .long("tags")
+                        .value_delimiter(',')
+                        .action(ArgAction::Append),
+                ),
+            )
+            .subcommand(
+                Command::new("tag").subcommand(
+                    Command::new("remove")
+                        .arg(Arg::new("bookmark"))
+                        .arg(Arg::new("tags").num_args(1..)),
+                ),
+            )
+    }
+
+    fn candidates() -> Candidates {
+        Candidates {
+            names: vec!["api".to_string(), "web".to_string()],
+            tags: vec!["rust".to_string(), "work".to_string()],
+            profiles: vec!["default".to_string(), "work".to_string()],
+        }
+    }
+
+    fn args(line: &[&str]) -> Vec<OsString> {
+        line.iter().map(OsString::from).collect()
+    }
+
+    #[rstest]
+    // 説明のある候補は説明をタブで区切って続ける
+    #[case(&["bm", "go", ""], vec!["api", "web", "--help\tPrint help"])]
+    #[case(&["bm", "go", "w"], vec!["web"])]
+    #[case(&["bm", "tag", "remove", "a"], vec!["api"])]
+    #[case(&["bm", "tag", "remove", "api", "r"], vec!["rust"])]
+    // 区切り文字の後ろも補完する
+    #[case(&["bm", "add", "--tags", "rust,w"], vec!["rust,work"])]
+    #[case(&["bm", "--profile", ""], vec!["default", "work"])]
+    fn test_complete_values(#[case] line: &[&str], #[case] expected: Vec<&str>) {
+        let cmd = candidates().attach(command());
+
+        let result = complete(cmd, args(line), line.len() - 1, None).unwrap();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    // サブコマンドも補完されること
+    fn test_complete_subcommands() {
+        let cmd = candidates().attach(command());
+
+        let result = complete(cmd, args(&["bm", "t"]), 1, None).unwrap();
+        assert_eq!(result, vec!["tag"]);
+    }
+
+    #[rstest]
+    #[case(&["bm", "go"], GlobalOptions::default())]
+    #[case(&["bm", "--profile", "work", "go"], GlobalOptions { profile: Some("work".to_string()), ..Default::default() })]
+    #[case(&["bm", "-pwork", "go"], GlobalOptions { profile: Some("work".to_string()), ..Default::default() })]
+    #[case(
+        &["bm", "--store=sqlite", "--file", "/tmp/bm.db", "-p", "x"],
+        GlobalOptions {
+            store: Some(StoreKind::Sqlite),
+            file: Some(PathBuf::from("/tmp/bm.db")),
+            profile: Some("x".to_string()),
+        }
+    )]
+    // サブコマンドより後ろのオプションは対象にしない
+    #[case(&["bm", "add", "--file", "/tmp/notes.md"], GlobalOptions::default())]
+    fn test_parse_global_options(#[case] line: &[&str], #[case] expected: GlobalOptions) {
+        assert_eq!(GlobalOptions::parse(&args(line)), expected);
+    }
+}
@@ -0,0 +1,251 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::host::matches_host;
+
+/// ブックマークの保存先の種類
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    /// JSONファイル
+    #[default]
+    #[value(alias = "text")]
+    #[serde(alias = "text")]
+    Json,
+    /// SQLiteデータベース
+    Sqlite,
+}
+
+impl StoreKind {
+    /// `$XDG_DATA_HOME/bookmark-cli/`に置く場合のファイル名
+    pub fn filename(&self) -> &'static str {
+        match self {
+            StoreKind::Json => "bookmarks.json",
+            StoreKind::Sqlite => "bookmarks.db",
+        }
+    }
+
+    /// ホームディレクトリ直下に置く場合のファイル名
+    pub fn legacy_filename(&self) -> &'static str {
+        match self {
+            StoreKind::Json => ".bookmarks",
+            StoreKind::Sqlite => ".bookmarks.db",
+        }
+    }
+}
+
+/// 設定ファイル (`$XDG_CONFIG_HOME/bookmark-cli/config.toml`) の内容
+#[derive(Default, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// 保存先の種類
+    pub store: StoreKind,
+    /// `prune --relocate`の設定
+    pub relocate: RelocateConfig,
+    /// `prune`の設定
+    pub prune: PruneConfig,
+    /// 保存するパスの正規化の設定
+    pub paths: PathsConfig,
+    /// ホスト名ごとの設定
+    pub hosts: BTreeMap<String, HostConfig>,
+}
+
+/// 保存先を複数のマシンで共有するときの、ホストごとの設定
+#[derive(Default, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostConfig {
+    /// 保存されたパスの先頭と、このホストでのパスの先頭
+    pub paths: BTreeMap<PathBuf, PathBuf>,
+}
+
+/// ブックマークとして保存する前にパスをどう正規化するか
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// シンボリックリンクを解決し、リンク先のパスで保存する
+    pub resolve_symlinks: bool,
+    /// `~`や`$VAR`を展開する
+    pub expand_variables: bool,
+    /// `add --portable`で`$NAME/...`として保存するルート (このマシンでのパス)
+    pub roots: BTreeMap<String, PathBuf>,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            resolve_symlinks: true,
+            expand_variables: true,
+            roots: BTreeMap::new(),
+        }
+    }
+}
+
+impl PathsConfig {
+    /// `~/`を展開したルートの名前とパス
+    pub fn roots(&self, home: Option<&Path>) -> Vec<(String, PathBuf)> {
+        self.roots
+            .iter()
+            .filter_map(|(name, root)| match root.strip_prefix("~") {
+                Ok(rest) => home.map(|home| (name.clone(), home.join(rest))),
+                Err(_) => Some((name.clone(), root.clone())),
+            })
+            .collect()
+    }
+}
+
+/// `prune`の安全のための設定
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PruneConfig {
+    /// 一度に削除してよいブックマークの割合 (`--force`がなければこれを超える削除は中止する)
+    pub max_fraction: f64,
+}
+
+impl Default for PruneConfig {
+    fn default() -> Self {
+        Self { max_fraction: 0.5 }
+    }
+}
+
+/// `prune --relocate`で移動先を探す範囲
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelocateConfig {
+    /// 探すディレクトリ (`~/`で始まればホームディレクトリからのパス)。空ならホームディレクトリ
+    pub roots: Vec<PathBuf>,
+    /// 探す深さ
+    pub max_depth: usize,
+}
+
+impl Default for RelocateConfig {
+    fn default() -> Self {
+        Self {
+            roots: Vec::new(),
+            max_depth: 4,
+        }
+    }
+}
+
+impl RelocateConfig {
+    /// `~/`を展開した探す範囲
+    pub fn roots(&self, home: Option<&Path>) -> Vec<PathBuf> {
+        if self.roots.is_empty() {
+            return home.map(Path::to_path_buf).into_iter().collect();
+        }
+        self.roots
+            .iter()
+            .filter_map(|root| match root.strip_prefix("~") {
+                Ok(rest) => home.map(|home| home.join(rest)),
+                Err(_) => Some(root.clone()),
+            })
+            .collect()
+    }
+}
+
+impl Config {
+    /// 設定ファイルを読み込む。ファイルがなければデフォルトの設定を返す
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        Self::parse(&content)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// ホスト名に一致する`[hosts.<hostname>.paths]`の、保存されたパスの先頭とこのホストでのパスの先頭
+    pub fn host_rewrites(&self, hostname: &str) -> Vec<(PathBuf, PathBuf)> {
+        self.hosts
+            .iter()
+            .filter(|(host, _)| matches_host(host, hostname))
+            .flat_map(|(_, config)| config.paths.clone())
+            .collect()
+    }
+
+    fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("", StoreKind::Json)]
+    #[case("store = \"sqlite\"", StoreKind::Sqlite)]
+    #[case("store = \"text\"", StoreKind::Json)]
+    fn test_parse(#[case] content: &str, #[case] expected: StoreKind) {
+        assert_eq!(Config::parse(content).unwrap().store, expected);
+    }
+
+    #[test]
+    // 探す範囲の`~`が展開され、省略時はホームディレクトリになること
+    fn test_relocate_roots() {
+        let home = Path::new("/home/me");
+        let config = Config::parse("[relocate]\nroots = [\"~/src\", \"/work\", \"~\"]").unwrap();
+        assert_eq!(
+            config.relocate.roots(Some(home)),
+            vec![
+                PathBuf::from("/home/me/src"),
+                PathBuf::from("/work"),
+                PathBuf::from("/home/me")
+            ]
+        );
+        assert_eq!(config.relocate.max_depth, 4);
+        assert_eq!(config.prune.max_fraction, 0.5);
+        assert!(config.paths.resolve_symlinks);
+        assert_eq!(
+            Config::parse("").unwrap().relocate.roots(Some(home)),
+            vec![PathBuf::from("/home/me")]
+        );
+    }
+
+    #[test]
+    // 持ち運べるパスのルートの`~`が展開されること
+    fn test_paths_roots() {
+        let config =
+            Config::parse("[paths.roots]\nWORKSPACE = \"/work\"\nNOTES = \"~/notes\"").unwrap();
+        assert_eq!(
+            config.paths.roots(Some(Path::new("/home/me"))),
+            vec![
+                ("NOTES".to_string(), PathBuf::from("/home/me/notes")),
+                ("WORKSPACE".to_string(), PathBuf::from("/work")),
+            ]
+        );
+    }
+
+    #[test]
+    // ホスト名に一致するホストのパスの書き換えだけが使われること
+    fn test_host_rewrites() {
+        let config = Config::parse(
+            "[hosts.macbook.paths]\n\"/home/alice\" = \"/Users/alice\"\n\
+             [hosts.devbox.paths]\n\"/Users/alice\" = \"/home/alice\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.host_rewrites("MacBook.local"),
+            vec![(PathBuf::from("/home/alice"), PathBuf::from("/Users/alice"))]
+        );
+        assert!(config.host_rewrites("server").is_empty());
+    }
+
+    #[rstest]
+    #[case("store = \"csv\"")]
+    #[case("unknown = 1")]
+    #[case("[relocate]\nroot = \"~\"")]
+    #[case("[paths]\nresolve_symlinks = \"yes\"")]
+    #[case("[hosts.macbook]\nroot = \"/Users/alice\"")]
+    fn test_parse_invalid(#[case] content: &str) {
+        assert!(Config::parse(content).is_err());
+    }
+}
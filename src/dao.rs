@@ -1,14 +1,117 @@
 use crate::bookmark::Bookmark;
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
+use crate::migration;
+use crate::tag_query::TagQuery;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
 use std::{io::Error, path::PathBuf};
+
+/// 保存ファイルのスキーマのバージョン
+///
+/// フィールドの追加は`#[serde(default)]`で古いファイルとの互換性を保ち、
+/// 互換性のない変更をするときだけ上げる
+///
+/// - 1: `bookmarks`に全てのブックマークを持つ
+/// - 2: `profiles`にプロファイルごとのブックマークを持つ
+const SCHEMA_VERSION: u32 = 2;
+
+/// 最初から存在するプロファイル
+pub const DEFAULT_PROFILE: &str = "default";
+
 pub trait IBookmarkDao {
-    /// ブックマークを保存する
-    fn save(&mut self, bookmark: &Bookmark) -> Result<(), Error>;
-    /// ブックマークを削除する
-    fn delete(&mut self, path: &str) -> Result<(), Error>;
+    /// ブックマークをまとめて削除する (存在しないパスは無視する)
+    fn delete(&mut self, profile: &str, paths: &[PathBuf]) -> Result<(), Error>;
     /// 全てのブックマークを取得する
-    fn find_all(&mut self) -> Result<Vec<Bookmark>, Error>;
+    fn find_all(&mut self, profile: &str) -> Result<Vec<Bookmark>, Error>;
+    /// 全てのブックマークを1つの操作として書き換える (`f`が失敗したら何も変更しない)
+    fn update_all(
+        &mut self,
+        profile: &str,
+        f: &mut dyn FnMut(&mut Vec<Bookmark>) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+    /// タグの条件に合うブックマークを取得する (索引がなければ全て読み込んで絞り込む)
+    fn find_by_tags(&mut self, profile: &str, query: &TagQuery) -> Result<Vec<Bookmark>, Error> {
+        let mut bookmarks = self.find_all(profile)?;
+        bookmarks.retain(|b| query.matches(b));
+        Ok(bookmarks)
+    }
+    /// `visible`なブックマークについて、タグごとにそのタグを持つブックマークの数を数える (順序は問わない)
+    fn count_tags(
+        &mut self,
+        profile: &str,
+        visible: &dyn Fn(&Bookmark) -> bool,
+    ) -> Result<Vec<(String, usize)>, Error> {
+        let mut counts = BTreeMap::new();
+        for bookmark in self.find_all(profile)?.iter().filter(|b| visible(b)) {
+            count_tags_of(&mut counts, bookmark);
+        }
+        Ok(counts.into_iter().collect())
+    }
+    /// 全てのプロファイルの名前を名前順に取得する
+    fn find_profiles(&mut self) -> Result<Vec<String>, Error>;
+    /// 空のプロファイルを作成する
+    fn create_profile(&mut self, name: &str) -> Result<(), Error>;
+    /// プロファイルをブックマークごと削除する
+    fn delete_profile(&mut self, name: &str) -> Result<(), Error>;
+    /// プロファイルの名前を変更する
+    fn rename_profile(&mut self, from: &str, to: &str) -> Result<(), Error>;
+    /// デフォルトのプロファイルを取得する
+    fn get_default_profile(&mut self) -> Result<String, Error>;
+    /// デフォルトのプロファイルを設定する
+    fn set_default_profile(&mut self, name: &str) -> Result<(), Error>;
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoreDocument {
+    version: u32,
+    default_profile: String,
+    profiles: BTreeMap<String, ProfileDocument>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct ProfileDocument {
+    #[serde(default)]
+    bookmarks: Vec<Bookmark>,
+}
+
+impl StoreDocument {
+    fn new(bookmarks: Vec<Bookmark>) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            default_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::from([(
+                DEFAULT_PROFILE.to_string(),
+                ProfileDocument { bookmarks },
+            )]),
+        }
+    }
+
+    fn profile(&self, name: &str) -> Result<&ProfileDocument, Error> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| profile_not_found(name))
+    }
+
+    fn profile_mut(&mut self, name: &str) -> Result<&mut ProfileDocument, Error> {
+        self.profiles
+            .get_mut(name)
+            .ok_or_else(|| profile_not_found(name))
+    }
+}
+
+/// スキーマバージョン1の文書
+#[derive(Deserialize)]
+struct StoreDocumentV1 {
+    #[serde(default)]
+    bookmarks: Vec<Bookmark>,
+}
+
+/// 本体を読む前にバージョンだけを確認するための構造体
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: u32,
 }
 
 pub struct BookmarkDao {
@@ -19,80 +122,579 @@ impl BookmarkDao {
     pub fn new(storage: PathBuf) -> Self {
         Self { storage }
     }
+
+    /// 他のプロセスと排他するためのロックを取得する。返されたファイルを閉じると解放される
+    ///
+    /// 書き込みはファイルの置き換えで行うため、保存ファイル自体ではなく別のファイルをロックする
+    fn lock(&self) -> Result<File, Error> {
+        lock_sibling(&self.storage)
+    }
+
+    /// ファイルを読み込む。テキスト形式のファイルはバックアップを残してJSON形式に変換する
+    fn load(&self) -> Result<StoreDocument, Error> {
+        let content = match fs::read_to_string(&self.storage) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(StoreDocument::new(Vec::new())),
+            Err(e) => return Err(e),
+        };
+        if migration::is_plain_text(&content) {
+            let bookmarks = migration::parse_plain_text(&content).map_err(|e| self.error(e))?;
+            fs::copy(&self.storage, sibling_path(&self.storage, ".bak"))?;
+            let document = StoreDocument::new(bookmarks);
+            self.store(&document)?;
+            return Ok(document);
+        }
+        if content.trim().is_empty() {
+            return Ok(StoreDocument::new(Vec::new()));
+        }
+
+        let probe: VersionProbe = serde_json::from_str(&content).map_err(|e| self.error(e))?;
+        match probe.version {
+            1 => {
+                let document: StoreDocumentV1 =
+                    serde_json::from_str(&content).map_err(|e| self.error(e))?;
+                Ok(StoreDocument::new(document.bookmarks))
+            }
+            SCHEMA_VERSION => serde_json::from_str(&content).map_err(|e| self.error(e)),
+            version => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} was written by a newer version of bm (schema version {}, supported up to {}); please upgrade bm",
+                    self.storage.display(),
+                    version,
+                    SCHEMA_VERSION
+                ),
+            )),
+        }
+    }
+
+    /// ファイル全体を書き直す
+    fn store(&self, document: &StoreDocument) -> Result<(), Error> {
+        let mut content = serde_json::to_string_pretty(document)?;
+        content.push('\n');
+        write_atomically(&self.storage, content.as_bytes())
+    }
+
+    /// ロックを取って読み込む
+    fn read(&self) -> Result<StoreDocument, Error> {
+        // 旧形式からの変換で書き込むことがあるため、読み込みでもロックを取る
+        let _lock = self.lock()?;
+        self.load()
+    }
+
+    /// ロックを取ったまま読み込み、変更して書き戻す
+    fn update<T>(
+        &self,
+        f: impl FnOnce(&mut StoreDocument) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let _lock = self.lock()?;
+        let mut document = self.load()?;
+        let result = f(&mut document)?;
+        self.store(&document)?;
+        Ok(result)
+    }
+
+    fn error<E: std::fmt::Display>(&self, e: E) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: {}", self.storage.display(), e),
+        )
+    }
 }
 
 impl IBookmarkDao for BookmarkDao {
-    fn save(&mut self, bookmark: &Bookmark) -> Result<(), Error> {
-        let mut bookmarks = self.find_all()?;
-        let bookmark_exists = bookmarks
-            .iter()
-            .any(|b| b.get_path() == bookmark.get_path());
-        if !bookmark_exists {
-            let mut file: File = OpenOptions::new().append(true).open(&self.storage)?;
-            writeln!(file, "{}", bookmark.to_string())?;
-        } else {
-            bookmarks.retain(|b| b.get_path() != bookmark.get_path());
-            bookmarks.push(bookmark.clone());
-            let mut file = File::create(&self.storage)?;
-            for bm in bookmarks {
-                writeln!(file, "{}", bm.to_string())?;
+    fn delete(&mut self, profile: &str, paths: &[PathBuf]) -> Result<(), Error> {
+        self.update(|document| {
+            let bookmarks = &mut document.profile_mut(profile)?.bookmarks;
+            bookmarks.retain(|b| !paths.iter().any(|p| p == b.get_path()));
+            Ok(())
+        })
+    }
+
+    fn find_all(&mut self, profile: &str) -> Result<Vec<Bookmark>, Error> {
+        Ok(self.read()?.profile(profile)?.bookmarks.clone())
+    }
+
+    fn update_all(
+        &mut self,
+        profile: &str,
+        f: &mut dyn FnMut(&mut Vec<Bookmark>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.update(|document| f(&mut document.profile_mut(profile)?.bookmarks))
+    }
+
+    fn find_profiles(&mut self) -> Result<Vec<String>, Error> {
+        Ok(self.read()?.profiles.into_keys().collect())
+    }
+
+    fn create_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.update(|document| {
+            if document.profiles.contains_key(name) {
+                return Err(profile_already_exists(name));
             }
-        }
-        Ok(())
+            document
+                .profiles
+                .insert(name.to_string(), ProfileDocument::default());
+            Ok(())
+        })
     }
 
-    fn delete(&mut self, path: &str) -> Result<(), Error> {
-        let bookmarks = self.find_all()?;
-        let filtered_bookmarks: Vec<Bookmark> = bookmarks
-            .into_iter()
-            .filter(|b| b.get_path() != path)
-            .collect();
-        let mut file = File::create(&self.storage)?;
-        for bm in filtered_bookmarks {
-            writeln!(file, "{}", bm.to_string())?;
-        }
-        Ok(())
+    fn delete_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.update(|document| {
+            document
+                .profiles
+                .remove(name)
+                .map(|_| ())
+                .ok_or_else(|| profile_not_found(name))
+        })
+    }
+
+    fn rename_profile(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        self.update(|document| {
+            if document.profiles.contains_key(to) {
+                return Err(profile_already_exists(to));
+            }
+            let profile = document
+                .profiles
+                .remove(from)
+                .ok_or_else(|| profile_not_found(from))?;
+            document.profiles.insert(to.to_string(), profile);
+            if document.default_profile == from {
+                document.default_profile = to.to_string();
+            }
+            Ok(())
+        })
+    }
+
+    fn get_default_profile(&mut self) -> Result<String, Error> {
+        Ok(self.read()?.default_profile)
+    }
+
+    fn set_default_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.update(|document| {
+            document.profile(name)?;
+            document.default_profile = name.to_string();
+            Ok(())
+        })
     }
+}
+
+pub fn profile_not_found(name: &str) -> Error {
+    Error::new(ErrorKind::NotFound, format!("profile not found: {}", name))
+}
 
-    fn find_all(&mut self) -> Result<Vec<Bookmark>, Error> {
-        let file = OpenOptions::new().read(true).open(&self.storage)?;
-        let reader = BufReader::new(file);
-        let mut bookmarks = Vec::new();
-        for line in reader.lines() {
-            let bookmark: Bookmark = Bookmark::parse(line?);
-            bookmarks.push(bookmark);
+pub fn profile_already_exists(name: &str) -> Error {
+    Error::new(
+        ErrorKind::AlreadyExists,
+        format!("profile already exists: {}", name),
+    )
+}
+
+/// 一時ファイルに書き込んでから置き換えることで、途中で中断されても元の内容か新しい内容の
+/// どちらかが残るようにする
+pub fn write_atomically(path: &Path, content: &[u8]) -> Result<(), Error> {
+    let tmp = sibling_path(path, &format!(".tmp{}", std::process::id()));
+    let result = (|| {
+        let mut file = File::create(&tmp)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
         }
-        Ok(bookmarks)
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result?;
+    sync_parent_dir(path)
+}
+
+/// 置き換えたファイルのディレクトリエントリを永続化する
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// `path`の隣の`.lock`ファイルをロックする。返されたファイルを閉じると解放される
+pub fn lock_sibling(path: &Path) -> Result<File, Error> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(sibling_path(path, ".lock"))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// `path`の末尾に`suffix`を付けたパス
+pub fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(suffix);
+    PathBuf::from(sibling)
+}
+
+/// ブックマークのタグをそれぞれ1つ数える
+pub fn count_tags_of(counts: &mut BTreeMap<String, usize>, bookmark: &Bookmark) {
+    for tag in bookmark.get_tags() {
+        *counts.entry(tag.clone()).or_default() += 1;
+    }
+}
+
+/// 同じパスのブックマークがあれば同じ位置のまま置き換え、なければ末尾に加える
+pub fn replace_or_push(bookmarks: &mut Vec<Bookmark>, bookmark: &Bookmark) {
+    match bookmarks
+        .iter_mut()
+        .find(|b| b.get_path() == bookmark.get_path())
+    {
+        Some(existing) => *existing = bookmark.clone(),
+        None => bookmarks.push(bookmark.clone()),
     }
 }
 
+impl<T: IBookmarkDao + ?Sized> IBookmarkDao for Box<T> {
+    fn delete(&mut self, profile: &str, paths: &[PathBuf]) -> Result<(), Error> {
+        (**self).delete(profile, paths)
+    }
+
+    fn find_all(&mut self, profile: &str) -> Result<Vec<Bookmark>, Error> {
+        (**self).find_all(profile)
+    }
+
+    fn update_all(
+        &mut self,
+        profile: &str,
+        f: &mut dyn FnMut(&mut Vec<Bookmark>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        (**self).update_all(profile, f)
+    }
+
+    fn find_by_tags(&mut self, profile: &str, query: &TagQuery) -> Result<Vec<Bookmark>, Error> {
+        (**self).find_by_tags(profile, query)
+    }
+
+    fn count_tags(
+        &mut self,
+        profile: &str,
+        visible: &dyn Fn(&Bookmark) -> bool,
+    ) -> Result<Vec<(String, usize)>, Error> {
+        (**self).count_tags(profile, visible)
+    }
+
+    fn find_profiles(&mut self) -> Result<Vec<String>, Error> {
+        (**self).find_profiles()
+    }
+
+    fn create_profile(&mut self, name: &str) -> Result<(), Error> {
+        (**self).create_profile(name)
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<(), Error> {
+        (**self).delete_profile(name)
+    }
+
+    fn rename_profile(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        (**self).rename_profile(from, to)
+    }
+
+    fn get_default_profile(&mut self) -> Result<String, Error> {
+        (**self).get_default_profile()
+    }
+
+    fn set_default_profile(&mut self, name: &str) -> Result<(), Error> {
+        (**self).set_default_profile(name)
+    }
+}
+
+/// ブックマークを1件保存する
+#[cfg(test)]
+pub fn save_bookmark(
+    dao: &mut dyn IBookmarkDao,
+    profile: &str,
+    bookmark: &Bookmark,
+) -> Result<(), Error> {
+    dao.update_all(profile, &mut |bookmarks| {
+        replace_or_push(bookmarks, bookmark);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
 pub struct MockBookmarkDao {
-    bookmarks: Vec<Bookmark>,
+    profiles: BTreeMap<String, Vec<Bookmark>>,
+    default_profile: String,
 }
 
 #[cfg(test)]
 impl MockBookmarkDao {
-    pub fn new(bookmarks: &Vec<Bookmark>) -> Self {
+    pub fn new(bookmarks: &[Bookmark]) -> Self {
         Self {
-            bookmarks: bookmarks.clone(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), bookmarks.to_vec())]),
+            default_profile: DEFAULT_PROFILE.to_string(),
         }
     }
+
+    fn profile_mut(&mut self, name: &str) -> Result<&mut Vec<Bookmark>, Error> {
+        self.profiles
+            .get_mut(name)
+            .ok_or_else(|| profile_not_found(name))
+    }
 }
 
+#[cfg(test)]
 impl IBookmarkDao for MockBookmarkDao {
-    fn save(&mut self, bookmark: &Bookmark) -> Result<(), Error> {
-        if !self.bookmarks.contains(bookmark) {
-            self.bookmarks.push(bookmark.clone());
+    fn delete(&mut self, profile: &str, paths: &[PathBuf]) -> Result<(), Error> {
+        self.profile_mut(profile)?
+            .retain(|b| !paths.iter().any(|p| p == b.get_path()));
+        Ok(())
+    }
+
+    fn find_all(&mut self, profile: &str) -> Result<Vec<Bookmark>, Error> {
+        Ok(self.profile_mut(profile)?.clone())
+    }
+
+    fn update_all(
+        &mut self,
+        profile: &str,
+        f: &mut dyn FnMut(&mut Vec<Bookmark>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let bookmarks = self.profile_mut(profile)?;
+        let mut updated = bookmarks.clone();
+        f(&mut updated)?;
+        *bookmarks = updated;
+        Ok(())
+    }
+
+    fn find_profiles(&mut self) -> Result<Vec<String>, Error> {
+        Ok(self.profiles.keys().cloned().collect())
+    }
+
+    fn create_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.profiles.insert(name.to_string(), Vec::new());
+        Ok(())
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.profiles.remove(name);
+        Ok(())
+    }
+
+    fn rename_profile(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        let bookmarks = self.profiles.remove(from).unwrap_or_default();
+        self.profiles.insert(to.to_string(), bookmarks);
+        if self.default_profile == from {
+            self.default_profile = to.to_string();
         }
         Ok(())
     }
 
-    fn delete(&mut self, path: &str) -> Result<(), Error> {
-        self.bookmarks.retain(|b| b.get_path() != path);
+    fn get_default_profile(&mut self) -> Result<String, Error> {
+        Ok(self.default_profile.clone())
+    }
+
+    fn set_default_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.default_profile = name.to_string();
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const D: &str = DEFAULT_PROFILE;
+
+    fn setup(content: &str) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = dir.path().join(".bookmarks");
+        fs::write(&storage, content).unwrap();
+        (dir, storage)
+    }
+
+    #[test]
+    // 保存したブックマークが読み込めること
+    fn test_save_and_find_all() {
+        let (_dir, storage) = setup("");
+        let mut dao = BookmarkDao::new(storage.clone());
+        let bookmark = Bookmark::new("/My Projects", vec!["tag".to_string()]);
+
+        save_bookmark(&mut dao, D, &bookmark).unwrap();
+        assert_eq!(dao.find_all(D).unwrap(), vec![bookmark]);
+
+        let content = fs::read_to_string(&storage).unwrap();
+        assert!(content.contains("\"version\": 2"));
+    }
+
+    #[test]
+    // ファイルがなければ空として扱い、保存時に作成されること
+    fn test_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = dir.path().join(".bookmarks");
+        let mut dao = BookmarkDao::new(storage.clone());
+
+        assert!(dao.find_all(D).unwrap().is_empty());
+        save_bookmark(&mut dao, D, &Bookmark::new("/path", vec![])).unwrap();
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![Bookmark::new("/path", vec![])]
+        );
+        assert!(!sibling_path(&storage, &format!(".tmp{}", std::process::id())).exists());
+    }
+
+    #[test]
+    // 既存のブックマークは同じ位置のまま更新されること
+    fn test_save_existing() {
+        let (_dir, storage) = setup("");
+        let mut dao = BookmarkDao::new(storage);
+        save_bookmark(&mut dao, D, &Bookmark::new("/path/a", vec![])).unwrap();
+        save_bookmark(&mut dao, D, &Bookmark::new("/path/b", vec![])).unwrap();
+        save_bookmark(
+            &mut dao,
+            D,
+            &Bookmark::new("/path/a", vec!["tag".to_string()]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![
+                Bookmark::new("/path/a", vec!["tag".to_string()]),
+                Bookmark::new("/path/b", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    // まとめて書き換えられ、途中で失敗した場合は何も変更されないこと
+    fn test_update_all() {
+        let (_dir, storage) = setup("");
+        let mut dao = BookmarkDao::new(storage);
+        save_bookmark(
+            &mut dao,
+            D,
+            &Bookmark::new("/path/a", vec!["old".to_string()]),
+        )
+        .unwrap();
+        save_bookmark(&mut dao, D, &Bookmark::new("/path/b", vec![])).unwrap();
+
+        let result = dao.update_all(D, &mut |bookmarks| {
+            bookmarks.clear();
+            Err(Error::other("failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(dao.find_all(D).unwrap().len(), 2);
+
+        dao.update_all(D, &mut |bookmarks| {
+            bookmarks[0].set_tags(vec!["new".to_string()]);
+            bookmarks.remove(1);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![Bookmark::new("/path/a", vec!["new".to_string()])]
+        );
+    }
+
+    #[test]
+    // テキスト形式のファイルがバックアップを残してJSON形式に変換されること
+    fn test_migrate_plain_text() {
+        let legacy = "/path/a #tag1\n/path/b\n";
+        let (_dir, storage) = setup(legacy);
+        let mut dao = BookmarkDao::new(storage.clone());
+
+        let expected = vec![
+            Bookmark::new("/path/a", vec!["tag1".to_string()]),
+            Bookmark::new("/path/b", vec![]),
+        ];
+        assert_eq!(dao.find_all(D).unwrap(), expected);
+        assert_eq!(
+            fs::read_to_string(sibling_path(&storage, ".bak")).unwrap(),
+            legacy
+        );
+        let content = fs::read_to_string(&storage).unwrap();
+        assert!(!migration::is_plain_text(&content));
+        assert_eq!(dao.find_all(D).unwrap(), expected);
+    }
+
+    #[test]
+    // 新しいスキーマのファイルは変更せずにエラーとすること
+    fn test_newer_schema_version() {
+        let content = "{\"version\": 999, \"bookmarks\": [], \"unknown\": 1}";
+        let (_dir, storage) = setup(content);
+        let mut dao = BookmarkDao::new(storage.clone());
+
+        let err = save_bookmark(&mut dao, D, &Bookmark::new("/path", vec![])).unwrap_err();
+        assert!(err.to_string().contains("newer version"));
+        assert_eq!(fs::read_to_string(&storage).unwrap(), content);
+    }
+
+    #[test]
+    // スキーマバージョン1のファイルはデフォルトのプロファイルとして読み込まれること
+    fn test_migrate_schema_version_1() {
+        let content = r#"{"version": 1, "bookmarks": [{"path": "/path/a", "tags": ["t"]}]}"#;
+        let (_dir, storage) = setup(content);
+        let mut dao = BookmarkDao::new(storage);
+
+        assert_eq!(dao.find_profiles().unwrap(), vec![D]);
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![Bookmark::new("/path/a", vec!["t".to_string()])]
+        );
+    }
+
+    #[test]
+    // プロファイルごとにブックマークが分かれて保存されること
+    fn test_profiles() {
+        let (_dir, storage) = setup("");
+        let mut dao = BookmarkDao::new(storage);
+        dao.create_profile("work").unwrap();
+        save_bookmark(&mut dao, D, &Bookmark::new("/path/a", vec![])).unwrap();
+        save_bookmark(&mut dao, "work", &Bookmark::new("/path/b", vec![])).unwrap();
+
+        assert_eq!(dao.find_profiles().unwrap(), vec![D, "work"]);
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![Bookmark::new("/path/a", vec![])]
+        );
+        assert_eq!(
+            dao.find_all("work").unwrap(),
+            vec![Bookmark::new("/path/b", vec![])]
+        );
+        assert!(dao.create_profile("work").is_err());
+        assert!(dao.find_all("unknown").is_err());
+        assert!(save_bookmark(&mut dao, "unknown", &Bookmark::new("/path/c", vec![])).is_err());
+    }
+
+    #[test]
+    // デフォルトのプロファイルの名前を変更すると、デフォルトの設定も追従すること
+    fn test_rename_default_profile() {
+        let (_dir, storage) = setup("");
+        let mut dao = BookmarkDao::new(storage);
+        save_bookmark(&mut dao, D, &Bookmark::new("/path/a", vec![])).unwrap();
+        dao.rename_profile(D, "personal").unwrap();
+
+        assert_eq!(dao.get_default_profile().unwrap(), "personal");
+        assert_eq!(dao.find_profiles().unwrap(), vec!["personal"]);
+        assert_eq!(
+            dao.find_all("personal").unwrap(),
+            vec![Bookmark::new("/path/a", vec![])]
+        );
 
-    fn find_all(&mut self) -> Result<Vec<Bookmark>, Error> {
-        Ok(self.bookmarks.clone())
+        dao.create_profile("work").unwrap();
+        dao.set_default_profile("work").unwrap();
+        assert_eq!(dao.get_default_profile().unwrap(), "work");
+        dao.delete_profile("personal").unwrap();
+        assert_eq!(dao.find_profiles().unwrap(), vec!["work"]);
+        assert!(dao.set_default_profile("personal").is_err());
     }
 }
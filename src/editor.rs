@@ -0,0 +1,79 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+pub trait Editor {
+    /// テキストを編集させ、編集後のテキストを返す
+    fn edit(&self, text: &str) -> Result<String, Error>;
+    /// ファイルを開き、エディタが終了するまで待つ
+    fn open(&self, path: &Path) -> Result<(), Error>;
+}
+
+/// `$VISUAL`や`$EDITOR`のエディタで一時ファイルを開く
+pub struct ExternalEditor {
+    command: String,
+}
+
+impl ExternalEditor {
+    pub fn from_env() -> Self {
+        let command = ["VISUAL", "EDITOR"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|command| !command.trim().is_empty())
+            .unwrap_or_else(|| "vi".to_string());
+        Self { command }
+    }
+
+    /// エディタでファイルを開く。
+    /// `code --wait`のように引数を含むエディタも使えるよう、シェル経由で起動する
+    fn run(&self, path: &Path) -> Result<(), Error> {
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", self.command))
+            .arg("sh")
+            .arg(path)
+            .status();
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(Error::other(format!(
+                "editor '{}' exited with {}",
+                self.command, status
+            ))),
+            Err(e) => Err(Error::new(
+                e.kind(),
+                format!("failed to run editor '{}': {}", self.command, e),
+            )),
+        }
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("bm-edit-{}.txt", std::process::id()))
+    }
+}
+
+impl Editor for ExternalEditor {
+    fn edit(&self, text: &str) -> Result<String, Error> {
+        let path = Self::temp_path();
+        // 前回異常終了した際のファイルが残っていれば作り直す
+        let _ = fs::remove_file(&path);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        file.write_all(text.as_bytes())?;
+        drop(file);
+
+        let result = self.run(&path).and_then(|_| fs::read_to_string(&path));
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    fn open(&self, path: &Path) -> Result<(), Error> {
+        self.run(path)
+    }
+}
@@ -0,0 +1,387 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::{Error, Write};
+
+use crate::bookmark::{Bookmark, Kind};
+use crate::format::{self, Format};
+use crate::import::Skipped;
+
+/// `bm export --to`で選べる形式
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Target {
+    /// All data of the bookmarks, which `bm import --from json` reads back
+    Json,
+    /// The same as `bm list --format csv`
+    Csv,
+    /// A script of `alias <name>='cd <path>'` for the named bookmarks
+    /// (`'$EDITOR <path>'` for files)
+    ShellAliases,
+    /// A script that sets CDPATH to the bookmarked directories
+    Cdpath,
+    /// The bookmarks file of GTK file choosers and Nautilus
+    Gtk,
+    /// The data file of z, which `zoxide import --from z` reads
+    Zoxide,
+    /// Markdown tables grouped by tag
+    Markdown,
+}
+
+/// `--to json`の形式のバージョン (互換性のない変更をするときだけ上げる)
+pub const DOCUMENT_VERSION: u32 = 1;
+
+/// `--to json`で書き出す内容。ブックマークは保存ファイルと同じ形で持つ
+#[derive(Serialize, Deserialize)]
+pub struct Document {
+    pub version: u32,
+    pub bookmarks: Vec<Bookmark>,
+}
+
+/// ブックマークを指定した形式で書き出し、その形式で表せなかったものを理由とともに返す
+///
+/// `now`は訪問したことがないブックマークの時刻としてzの形式に書き出す
+pub fn export(
+    out: &mut dyn Write,
+    target: Target,
+    bookmarks: &[Bookmark],
+    now: u64,
+) -> Result<Vec<Skipped>, Error> {
+    let mut skipped = Vec::new();
+    let mut skip = |bookmark: &Bookmark, reason: &str| {
+        skipped.push(Skipped {
+            item: bookmark.get_path().display().to_string(),
+            reason: reason.to_string(),
+        })
+    };
+    match target {
+        Target::Json => {
+            let document = Document {
+                version: DOCUMENT_VERSION,
+                bookmarks: bookmarks.to_vec(),
+            };
+            serde_json::to_writer_pretty(&mut *out, &document)?;
+            writeln!(out)?;
+        }
+        Target::Csv => {
+            let rows: Vec<_> = bookmarks.iter().map(|b| ("", b)).collect();
+            skipped = format::write_bookmarks(out, Format::Csv, format::DEFAULT_FIELDS, &rows)?;
+        }
+        Target::ShellAliases => {
+            for bookmark in bookmarks {
+                let Some(name) = bookmark.get_name() else {
+                    continue;
+                };
+                if !is_alias_name(name) {
+                    skip(bookmark, "the name cannot be used as an alias");
+                    continue;
+                }
+                let mut command = match bookmark.get_kind() {
+                    Kind::Dir => b"cd -- ".to_vec(),
+                    Kind::File => b"\"${EDITOR:-vi}\" -- ".to_vec(),
+                };
+                command.extend(shell_quote(path_bytes(bookmark)));
+                out.write_all(format!("alias {}=", name).as_bytes())?;
+                out.write_all(&shell_quote(&command))?;
+                writeln!(out)?;
+            }
+        }
+        Target::Cdpath => {
+            let mut value = b".".to_vec();
+            for bookmark in bookmarks {
+                if bookmark.get_kind() == Kind::File {
+                    skip(bookmark, "not a directory");
+                    continue;
+                }
+                if path_bytes(bookmark).contains(&b':') {
+                    skip(bookmark, "CDPATH cannot contain a path with ':'");
+                    continue;
+                }
+                value.push(b':');
+                value.extend(path_bytes(bookmark));
+            }
+            out.write_all(b"export CDPATH=")?;
+            out.write_all(&shell_quote(&value))?;
+            writeln!(out)?;
+        }
+        Target::Gtk => {
+            for bookmark in bookmarks {
+                if bookmark.get_kind() == Kind::File {
+                    skip(bookmark, "not a directory");
+                    continue;
+                }
+                write!(out, "file://{}", percent_encode(path_bytes(bookmark)))?;
+                if let Some(name) = bookmark.get_name() {
+                    write!(out, " {}", name)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Target::Zoxide => {
+            for bookmark in bookmarks {
+                if bookmark.get_kind() == Kind::File {
+                    skip(bookmark, "not a directory");
+                    continue;
+                }
+                if path_bytes(bookmark).contains(&b'\n') {
+                    skip(bookmark, "the path contains a newline");
+                    continue;
+                }
+                out.write_all(path_bytes(bookmark))?;
+                writeln!(
+                    out,
+                    "|{}|{}",
+                    bookmark.get_visits().max(1),
+                    bookmark.get_last_visited().unwrap_or(now)
+                )?;
+            }
+        }
+        Target::Markdown => write_markdown(out, bookmarks)?,
+    }
+    Ok(skipped)
+}
+
+/// タグごとに見出しと表を書く (タグのないブックマークは最後にまとめる)
+fn write_markdown(out: &mut dyn Write, bookmarks: &[Bookmark]) -> Result<(), Error> {
+    let tags: BTreeSet<&str> = bookmarks
+        .iter()
+        .flat_map(|b| b.get_tags().iter().map(String::as_str))
+        .collect();
+    let groups = tags
+        .into_iter()
+        .map(|tag| {
+            let members: Vec<&Bookmark> = bookmarks
+                .iter()
+                .filter(|b| b.get_tags().iter().any(|t| t == tag))
+                .collect();
+            (escape_markdown(tag), members)
+        })
+        .chain(std::iter::once((
+            "Untagged".to_string(),
+            bookmarks
+                .iter()
+                .filter(|b| b.get_tags().is_empty())
+                .collect(),
+        )));
+    writeln!(out, "# Bookmarks")?;
+    for (heading, members) in groups {
+        if members.is_empty() {
+            continue;
+        }
+        writeln!(out, "\n## {}\n\n| Name | Path |\n| --- | --- |", heading)?;
+        for bookmark in members {
+            writeln!(
+                out,
+                "| {} | {} |",
+                escape_markdown(bookmark.get_name().unwrap_or_default()),
+                escape_markdown(&bookmark.get_path().to_string_lossy())
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn path_bytes(bookmark: &Bookmark) -> &[u8] {
+    bookmark.get_path().as_os_str().as_encoded_bytes()
+}
+
+/// 全てのシェルでエイリアスの名前として使える文字だけでできているか
+fn is_alias_name(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// シングルクォートで囲む (中の`'`は`'\''`とする)
+fn shell_quote(bytes: &[u8]) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &b in bytes {
+        if b == b'\'' {
+            quoted.extend(b"'\\''");
+        } else {
+            quoted.push(b);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// URIのパスとして使えない文字を`%XX`にする
+fn percent_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for &b in bytes {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+/// Markdownの書式として解釈される文字をエスケープし、改行は`\n`と書き表す
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\\' | '|' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookmark::Fingerprint;
+    use crate::import::{self, Source};
+    use rstest::rstest;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn bookmarks() -> Vec<Bookmark> {
+        let mut api = Bookmark::new("/src/api", vec!["rust".to_string(), "work".to_string()])
+            .with_name(Some("api".to_string()));
+        api.visit(NOW - 60);
+        vec![
+            api,
+            Bookmark::new("/home/me/it's mine", vec![]).with_name(Some("mine".to_string())),
+            Bookmark::new("/srv/a:b|c", vec!["work".to_string()])
+                .with_name(Some("my_site!".to_string())),
+        ]
+    }
+
+    fn render(target: Target) -> (String, Vec<Skipped>) {
+        let mut out = Vec::new();
+        let skipped = export(&mut out, target, &bookmarks(), NOW).unwrap();
+        (String::from_utf8(out).unwrap(), skipped)
+    }
+
+    fn skipped(path: &str, reason: &str) -> Skipped {
+        Skipped {
+            item: path.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    #[rstest]
+    #[case(
+        Target::ShellAliases,
+        concat!(
+            "alias api='cd -- '\\''/src/api'\\'''\n",
+            "alias mine='cd -- '\\''/home/me/it'\\''\\'\\'''\\''s mine'\\'''\n",
+        ),
+        vec![skipped("/srv/a:b|c", "the name cannot be used as an alias")]
+    )]
+    #[case(
+        Target::Cdpath,
+        "export CDPATH='.:/src/api:/home/me/it'\\''s mine'\n",
+        vec![skipped("/srv/a:b|c", "CDPATH cannot contain a path with ':'")]
+    )]
+    #[case(
+        Target::Gtk,
+        concat!(
+            "file:///src/api api\n",
+            "file:///home/me/it%27s%20mine mine\n",
+            "file:///srv/a%3Ab%7Cc my_site!\n",
+        ),
+        vec![]
+    )]
+    #[case(
+        Target::Zoxide,
+        concat!(
+            "/src/api|1|1699999940\n",
+            "/home/me/it's mine|1|1700000000\n",
+            "/srv/a:b|c|1|1700000000\n",
+        ),
+        vec![]
+    )]
+    #[case(
+        Target::Markdown,
+        concat!(
+            "# Bookmarks\n",
+            "\n## rust\n\n| Name | Path |\n| --- | --- |\n",
+            "| api | /src/api |\n",
+            "\n## work\n\n| Name | Path |\n| --- | --- |\n",
+            "| api | /src/api |\n",
+            "| my\\_site! | /srv/a:b\\|c |\n",
+            "\n## Untagged\n\n| Name | Path |\n| --- | --- |\n",
+            "| mine | /home/me/it's mine |\n",
+        ),
+        vec![]
+    )]
+    fn test_export(
+        #[case] target: Target,
+        #[case] expected: &str,
+        #[case] expected_skipped: Vec<Skipped>,
+    ) {
+        let (output, skipped) = render(target);
+        assert_eq!(output, expected);
+        assert_eq!(skipped, expected_skipped);
+    }
+
+    #[test]
+    // CSVは`list --format csv`と同じ項目を書き出すこと
+    fn test_export_csv() {
+        let (output, _) = render(Target::Csv);
+        assert_eq!(
+            output.lines().take(2).collect::<Vec<_>>(),
+            vec![
+                "path,name,tags,visits,last_visited",
+                "/src/api,api,\"rust,work\",1,1699999940"
+            ]
+        );
+    }
+
+    #[test]
+    // ファイルのブックマークは、エイリアスではエディタで開き、ディレクトリだけの形式では除かれること
+    fn test_export_files() {
+        let bookmarks = vec![Bookmark::new("/notes/todo.md", vec![])
+            .with_kind(Kind::File)
+            .with_name(Some("todo".to_string()))];
+        let export = |target| {
+            let mut out = Vec::new();
+            let skipped = export(&mut out, target, &bookmarks, NOW).unwrap();
+            (String::from_utf8(out).unwrap(), skipped)
+        };
+
+        assert_eq!(
+            export(Target::ShellAliases),
+            (
+                "alias todo='\"${EDITOR:-vi}\" -- '\\''/notes/todo.md'\\'''\n".to_string(),
+                vec![]
+            )
+        );
+        for target in [Target::Cdpath, Target::Gtk, Target::Zoxide] {
+            let (_, result) = export(target);
+            assert_eq!(result, vec![skipped("/notes/todo.md", "not a directory")]);
+        }
+    }
+
+    #[test]
+    // JSONに書き出したものを読み込むと、全ての情報が元に戻ること
+    fn test_export_json_round_trip() {
+        let mut bookmarks = bookmarks();
+        bookmarks.push(
+            Bookmark::new("/moved", vec![]).with_fingerprint(Some(Fingerprint {
+                inode: Some((1, 2)),
+                git_remote: Some("git@example.com:me/moved.git".to_string()),
+            })),
+        );
+        #[cfg(unix)]
+        bookmarks.push(Bookmark::new(
+            crate::bookmark::bytes_to_path(b"/bad\xffname".to_vec()).unwrap(),
+            vec![],
+        ));
+
+        let mut out = Vec::new();
+        let skipped = export(&mut out, Target::Json, &bookmarks, NOW).unwrap();
+        assert_eq!(skipped, vec![]);
+        let parsed = import::parse(Source::Json, &out, None).unwrap();
+        let imported: Vec<Bookmark> = parsed.entries.into_iter().map(|e| e.bookmark).collect();
+        assert_eq!(imported, bookmarks);
+    }
+}
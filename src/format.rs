@@ -0,0 +1,369 @@
+use clap::ValueEnum;
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use std::io::{Error, Write};
+
+use crate::bookmark::Bookmark;
+use crate::import::Skipped;
+
+/// `list --format`で選べる出力形式
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Format {
+    /// A JSON array of objects
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// Comma-separated values with a header line
+    Csv,
+    /// Tab-separated values with a header line
+    Tsv,
+    /// Aligned columns for reading
+    Table,
+    /// Tab-separated values without a header, for scripts
+    Porcelain,
+}
+
+/// 出力する項目。名前と値の形は、スクリプトから使われるため変えない
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Field {
+    Profile,
+    Path,
+    Kind,
+    Name,
+    Tags,
+    Hosts,
+    Visits,
+    LastVisited,
+}
+
+/// `--fields`を省略した場合の項目 (全てのプロファイルを出力する場合は先頭にプロファイルを加える)
+pub const DEFAULT_FIELDS: &[Field] = &[
+    Field::Path,
+    Field::Name,
+    Field::Tags,
+    Field::Visits,
+    Field::LastVisited,
+];
+
+impl Field {
+    fn key(self) -> &'static str {
+        match self {
+            Field::Profile => "profile",
+            Field::Path => "path",
+            Field::Kind => "kind",
+            Field::Name => "name",
+            Field::Tags => "tags",
+            Field::Hosts => "hosts",
+            Field::Visits => "visits",
+            Field::LastVisited => "last_visited",
+        }
+    }
+
+    /// 表形式での値 (値がなければ空文字列、タグとホストはカンマ区切り)
+    fn text(self, row: &Row) -> String {
+        let bookmark = row.bookmark;
+        match self {
+            Field::Profile => row.profile.to_string(),
+            Field::Path => bookmark.get_path().to_string_lossy().into_owned(),
+            Field::Kind => bookmark.get_kind().as_str().to_string(),
+            Field::Name => bookmark.get_name().unwrap_or_default().to_string(),
+            Field::Tags => bookmark.get_tags().join(","),
+            Field::Hosts => bookmark.get_hosts().join(","),
+            Field::Visits => bookmark.get_visits().to_string(),
+            Field::LastVisited => bookmark
+                .get_last_visited()
+                .map(|t| t.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// 出力する1件分
+struct Row<'a> {
+    fields: &'a [Field],
+    profile: &'a str,
+    bookmark: &'a Bookmark,
+}
+
+/// 選ばれた項目を、その順にJSONのオブジェクトとして書き出す
+impl Serialize for Row<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bookmark = self.bookmark;
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+        for field in self.fields {
+            let key = field.key();
+            match field {
+                Field::Profile => map.serialize_entry(key, self.profile)?,
+                Field::Path => map.serialize_entry(key, &bookmark.get_path().to_string_lossy())?,
+                Field::Kind => map.serialize_entry(key, bookmark.get_kind().as_str())?,
+                Field::Name => map.serialize_entry(key, &bookmark.get_name())?,
+                Field::Tags => map.serialize_entry(key, bookmark.get_tags())?,
+                Field::Hosts => map.serialize_entry(key, bookmark.get_hosts())?,
+                Field::Visits => map.serialize_entry(key, &bookmark.get_visits())?,
+                Field::LastVisited => map.serialize_entry(key, &bookmark.get_last_visited())?,
+            }
+        }
+        map.end()
+    }
+}
+
+/// プロファイル名とブックマークの組を、指定した形式で書き出し、書き出さなかったものを理由とともに返す
+///
+/// パスを出力する場合、UTF-8として不正なパスは別のパスとして読まれないよう書き出さない
+pub fn write_bookmarks(
+    out: &mut dyn Write,
+    format: Format,
+    fields: &[Field],
+    bookmarks: &[(&str, &Bookmark)],
+) -> Result<Vec<Skipped>, Error> {
+    let mut skipped = Vec::new();
+    let rows: Vec<Row> = bookmarks
+        .iter()
+        .filter(|(_, bookmark)| {
+            let path = bookmark.get_path();
+            if fields.contains(&Field::Path) && path.to_str().is_none() {
+                skipped.push(Skipped {
+                    item: path.display().to_string(),
+                    reason: "the path is not valid UTF-8".to_string(),
+                });
+                return false;
+            }
+            true
+        })
+        .map(|(profile, bookmark)| Row {
+            fields,
+            profile,
+            bookmark,
+        })
+        .collect();
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, &rows)?;
+            writeln!(out)
+        }
+        Format::Jsonl => rows.iter().try_for_each(|row| {
+            serde_json::to_writer(&mut *out, row)?;
+            writeln!(out)
+        }),
+        Format::Csv => write_separated(out, fields, &rows, ",", escape_csv, true),
+        Format::Tsv => write_separated(out, fields, &rows, "\t", escape_tsv, true),
+        Format::Porcelain => write_separated(out, fields, &rows, "\t", escape_tsv, false),
+        Format::Table => write_table(out, fields, &rows),
+    }?;
+    Ok(skipped)
+}
+
+/// 値をエスケープし、区切り文字で区切って書き出す
+fn write_separated(
+    out: &mut dyn Write,
+    fields: &[Field],
+    rows: &[Row],
+    separator: &str,
+    escape: fn(&str) -> String,
+    header: bool,
+) -> Result<(), Error> {
+    if header {
+        let keys: Vec<String> = fields.iter().map(|f| escape(f.key())).collect();
+        writeln!(out, "{}", keys.join(separator))?;
+    }
+    for row in rows {
+        let values: Vec<String> = fields.iter().map(|f| escape(&f.text(row))).collect();
+        writeln!(out, "{}", values.join(separator))?;
+    }
+    Ok(())
+}
+
+/// 見出しを付け、列の幅をそろえて書き出す
+fn write_table(out: &mut dyn Write, fields: &[Field], rows: &[Row]) -> Result<(), Error> {
+    let header: Vec<String> = fields.iter().map(|f| f.key().to_uppercase()).collect();
+    let lines: Vec<Vec<String>> = std::iter::once(header)
+        .chain(
+            rows.iter()
+                .map(|row| fields.iter().map(|f| escape_tsv(&f.text(row))).collect()),
+        )
+        .collect();
+    let widths: Vec<usize> = (0..fields.len())
+        .map(|i| {
+            lines
+                .iter()
+                .map(|line| line[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for line in lines {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+/// 区切り文字や改行、引用符を含む値を引用符で囲む (RFC 4180)
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// タブや改行を`\t`や`\n`と書き表す (`\`自身は`\\`とする)
+fn escape_tsv(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn bookmarks() -> Vec<Bookmark> {
+        let mut visited = Bookmark::new(
+            "/src/my project",
+            vec!["rust".to_string(), "work".to_string()],
+        )
+        .with_name(Some("proj".to_string()));
+        visited.visit(1_700_000_000);
+        vec![visited, Bookmark::new("/notes,\"old\"\tdir", vec![])]
+    }
+
+    fn render(format: Format, fields: &[Field]) -> String {
+        let bookmarks = bookmarks();
+        let rows: Vec<(&str, &Bookmark)> = bookmarks.iter().map(|b| ("default", b)).collect();
+        let mut out = Vec::new();
+        write_bookmarks(&mut out, format, fields, &rows).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[rstest]
+    #[case(
+        Format::Jsonl,
+        concat!(
+            r#"{"path":"/src/my project","name":"proj","tags":["rust","work"],"visits":1,"last_visited":1700000000}"#, "\n",
+            r#"{"path":"/notes,\"old\"\tdir","name":null,"tags":[],"visits":0,"last_visited":null}"#, "\n",
+        )
+    )]
+    #[case(
+        Format::Csv,
+        concat!(
+            "path,name,tags,visits,last_visited\n",
+            "/src/my project,proj,\"rust,work\",1,1700000000\n",
+            "\"/notes,\"\"old\"\"\tdir\",,,0,\n",
+        )
+    )]
+    #[case(
+        Format::Tsv,
+        concat!(
+            "path\tname\ttags\tvisits\tlast_visited\n",
+            "/src/my project\tproj\trust,work\t1\t1700000000\n",
+            "/notes,\"old\"\\tdir\t\t\t0\t\n",
+        )
+    )]
+    #[case(
+        Format::Porcelain,
+        concat!(
+            "/src/my project\tproj\trust,work\t1\t1700000000\n",
+            "/notes,\"old\"\\tdir\t\t\t0\t\n",
+        )
+    )]
+    #[case(
+        Format::Table,
+        concat!(
+            "PATH               NAME  TAGS       VISITS  LAST_VISITED\n",
+            "/src/my project    proj  rust,work  1       1700000000\n",
+            "/notes,\"old\"\\tdir                   0\n",
+        )
+    )]
+    fn test_write_bookmarks(#[case] format: Format, #[case] expected: &str) {
+        assert_eq!(render(format, DEFAULT_FIELDS), expected);
+    }
+
+    #[test]
+    // JSONは配列で、選んだ項目だけが選んだ順に並ぶこと
+    fn test_write_json_with_fields() {
+        let json = render(Format::Json, &[Field::Name, Field::Profile]);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([
+                {"name": "proj", "profile": "default"},
+                {"name": null, "profile": "default"},
+            ])
+        );
+        assert!(json.find("\"name\"").unwrap() < json.find("\"profile\"").unwrap());
+    }
+
+    #[test]
+    // 種類は選んだ場合だけ、dirかfileとして出力されること
+    fn test_write_kind() {
+        let file = Bookmark::new("/src/notes.md", vec![]).with_kind(crate::bookmark::Kind::File);
+        let rows = vec![("default", &file)];
+        let mut out = Vec::new();
+        write_bookmarks(
+            &mut out,
+            Format::Porcelain,
+            &[Field::Kind, Field::Path],
+            &rows,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "file\t/src/notes.md\n");
+        assert_eq!(render(Format::Porcelain, &[Field::Kind]), "dir\ndir\n");
+    }
+
+    #[test]
+    // UTF-8として不正なパスは、パスを出力する場合だけ書き出さずに報告されること
+    fn test_write_non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::OsStr::from_bytes(b"/src/caf\xe9");
+        let invalid = Bookmark::new(path, vec![]).with_name(Some("cafe".to_string()));
+        let valid = Bookmark::new("/src/api", vec![]);
+        let rows = vec![("default", &invalid), ("default", &valid)];
+
+        let mut out = Vec::new();
+        let skipped = write_bookmarks(&mut out, Format::Csv, &[Field::Path], &rows).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "path\n/src/api\n");
+        assert_eq!(
+            skipped,
+            vec![Skipped {
+                item: "/src/caf\u{FFFD}".to_string(),
+                reason: "the path is not valid UTF-8".to_string(),
+            }]
+        );
+
+        let mut out = Vec::new();
+        let skipped = write_bookmarks(&mut out, Format::Porcelain, &[Field::Name], &rows).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "cafe\n\n");
+        assert_eq!(skipped, vec![]);
+    }
+
+    #[test]
+    // 使えるホストは表形式ではカンマ区切り、JSONでは配列として出力されること
+    fn test_write_hosts() {
+        let hosts = vec!["laptop".to_string(), "devbox".to_string()];
+        let bookmark = Bookmark::new("/src", vec![]).with_hosts(hosts);
+        let rows = vec![("default", &bookmark)];
+        let mut out = Vec::new();
+        write_bookmarks(&mut out, Format::Porcelain, &[Field::Hosts], &rows).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "laptop,devbox\n");
+        let mut out = Vec::new();
+        write_bookmarks(&mut out, Format::Jsonl, &[Field::Hosts], &rows).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"hosts\":[\"laptop\",\"devbox\"]}\n"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use crate::bookmark::Bookmark;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// 1つのプロファイルの訪問回数の合計の上限。超えたら全体を縮めて古い訪問の重みを下げる
+pub const MAX_TOTAL_VISITS: u32 = 1000;
+
+/// zoxideと同様に、訪問回数に最後の訪問からの経過時間に応じた係数を掛けた値
+pub fn score(bookmark: &Bookmark, now: u64) -> f64 {
+    let last_visited = match bookmark.get_last_visited() {
+        Some(t) => t,
+        None => return 0.0,
+    };
+    let elapsed = now.saturating_sub(last_visited);
+    let factor = if elapsed < HOUR {
+        4.0
+    } else if elapsed < DAY {
+        2.0
+    } else if elapsed < WEEK {
+        0.5
+    } else {
+        0.25
+    };
+    bookmark.get_visits() as f64 * factor
+}
+
+/// スコアの高い順に並べる (同点なら元の順序を保つ)
+pub fn sort_by<T>(items: &mut [T], now: u64, bookmark: impl Fn(&T) -> &Bookmark) {
+    items.sort_by(|a, b| score(bookmark(b), now).total_cmp(&score(bookmark(a), now)));
+}
+
+/// 訪問回数の合計が上限を超えていれば、合計が上限の9割になるよう全体を縮める
+///
+/// 縮めた場合は`true`を返す
+pub fn age(bookmarks: &mut [Bookmark]) -> bool {
+    let total: u64 = bookmarks.iter().map(|b| b.get_visits() as u64).sum();
+    if total <= MAX_TOTAL_VISITS as u64 {
+        return false;
+    }
+    let factor = 0.9 * MAX_TOTAL_VISITS as f64 / total as f64;
+    for bookmark in bookmarks {
+        bookmark.scale_visits(factor);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn visited(path: &str, visits: u32, elapsed: u64) -> Bookmark {
+        let mut bookmark = Bookmark::new(path, vec![]);
+        for _ in 0..visits {
+            bookmark.visit(NOW - elapsed);
+        }
+        bookmark
+    }
+
+    #[rstest]
+    #[case(visited("/a", 3, 10), 12.0)]
+    #[case(visited("/a", 3, HOUR), 6.0)]
+    #[case(visited("/a", 3, DAY), 1.5)]
+    #[case(visited("/a", 3, WEEK), 0.75)]
+    // 訪問したことがない
+    #[case(Bookmark::new("/a", vec![]), 0.0)]
+    fn test_score(#[case] bookmark: Bookmark, #[case] expected: f64) {
+        assert_eq!(score(&bookmark, NOW), expected);
+    }
+
+    #[test]
+    // 訪問回数が多くても、長く訪問していないものは後ろに下がること
+    fn test_sort_by() {
+        let mut bookmarks = vec![
+            visited("/stale", 10, 2 * WEEK),
+            Bookmark::new("/never", vec![]),
+            visited("/recent", 1, 10),
+            visited("/daily", 3, 2 * HOUR),
+        ];
+        sort_by(&mut bookmarks, NOW, |b| b);
+        let paths: Vec<_> = bookmarks.iter().map(|b| b.get_path()).collect();
+        assert_eq!(
+            paths,
+            ["/daily", "/recent", "/stale", "/never"].map(std::path::Path::new)
+        );
+    }
+
+    #[test]
+    // 合計が上限を超えたら全体が縮められること
+    fn test_age() {
+        let mut bookmarks = vec![visited("/a", 900, 0), visited("/b", 100, 0)];
+        assert!(!age(&mut bookmarks));
+
+        bookmarks[1].visit(NOW);
+        assert!(age(&mut bookmarks));
+        let visits: Vec<u32> = bookmarks.iter().map(|b| b.get_visits()).collect();
+        assert_eq!(visits, vec![809, 90]);
+    }
+}
@@ -0,0 +1,149 @@
+/// 1文字一致するごとの得点
+const SCORE_MATCH: i64 = 16;
+/// 直前の文字に続けて一致した場合の加点
+const BONUS_CONSECUTIVE: i64 = 8;
+/// 単語の先頭 (区切り文字の直後) で一致した場合の加点
+const BONUS_BOUNDARY: i64 = 10;
+/// 一致した文字の間に隙間がある場合の減点
+const PENALTY_GAP_START: i64 = 3;
+/// 隙間の2文字目以降1つごとの減点
+const PENALTY_GAP_EXTENSION: i64 = 1;
+
+/// クエリが候補に部分列として含まれていれば、その一致の良さを返す
+///
+/// 空白区切りの各語がそれぞれ一致する必要がある。クエリに大文字が含まれる場合だけ
+/// 大文字と小文字を区別する。
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let candidate: Vec<char> = if case_sensitive {
+        candidate.chars().collect()
+    } else {
+        candidate.chars().flat_map(char::to_lowercase).collect()
+    };
+    let mut total = 0;
+    for term in query.split_whitespace() {
+        let term: Vec<char> = term.chars().collect();
+        total += score_term(&term, &candidate)?;
+    }
+    Some(total)
+}
+
+/// 候補をクエリとの一致の良さの順に並べ、一致しないものを除く (同点なら短い候補を優先する)
+pub fn rank<T>(query: &str, items: Vec<T>, text: impl Fn(&T) -> String) -> Vec<T> {
+    let mut scored: Vec<(i64, usize, usize, T)> = items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, item)| {
+            let text = text(&item);
+            score(query, &text).map(|s| (s, text.chars().count(), i, item))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    scored.into_iter().map(|(_, _, _, item)| item).collect()
+}
+
+/// 語の最初の一致位置から前方に探して一致の終わりを決め、そこから後方に探し直すことで
+/// できるだけ短い範囲の一致を採点する
+fn score_term(term: &[char], candidate: &[char]) -> Option<i64> {
+    let mut end = None;
+    let mut t = 0;
+    for (i, c) in candidate.iter().enumerate() {
+        if t < term.len() && *c == term[t] {
+            t += 1;
+            if t == term.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    let mut positions = Vec::with_capacity(term.len());
+    let mut t = term.len();
+    for i in (0..=end).rev() {
+        if t > 0 && candidate[i] == term[t - 1] {
+            t -= 1;
+            positions.push(i);
+        }
+    }
+    positions.reverse();
+
+    let mut score = 0;
+    // 連続した一致には、その先頭の文字の加点を引き継ぐ
+    let mut chunk_bonus = 0;
+    for (k, &pos) in positions.iter().enumerate() {
+        score += SCORE_MATCH;
+        let boundary = if pos == 0 || is_separator(candidate[pos - 1]) {
+            BONUS_BOUNDARY
+        } else {
+            0
+        };
+        let gap = if k == 0 {
+            None
+        } else {
+            Some((pos - positions[k - 1] - 1) as i64)
+        };
+        match gap {
+            Some(0) => {
+                chunk_bonus = chunk_bonus.max(boundary).max(BONUS_CONSECUTIVE);
+                score += chunk_bonus;
+            }
+            Some(gap) => {
+                score -= PENALTY_GAP_START + (gap - 1) * PENALTY_GAP_EXTENSION;
+                chunk_bonus = boundary;
+                score += boundary;
+            }
+            None => {
+                chunk_bonus = boundary;
+                score += boundary;
+            }
+        }
+    }
+    Some(score)
+}
+
+fn is_separator(c: char) -> bool {
+    matches!(c, '/' | '\\' | '-' | '_' | '.' | ' ' | '#' | '@')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("api", "/home/me/src/api", true)]
+    #[case("sapi", "/home/me/src/api", true)]
+    // 空白区切りの語は全て一致する必要がある
+    #[case("src api", "/home/me/src/api", true)]
+    #[case("src web", "/home/me/src/api", false)]
+    #[case("ipa", "/home/me/src/api", false)]
+    // 小文字のクエリは大文字と小文字を区別しない
+    #[case("proj", "/My Projects", true)]
+    #[case("Proj", "/My Projects", true)]
+    #[case("PROJ", "/My Projects", false)]
+    // 空のクエリは全てに一致する
+    #[case("", "/path", true)]
+    fn test_score_matches(#[case] query: &str, #[case] candidate: &str, #[case] expected: bool) {
+        assert_eq!(score(query, candidate).is_some(), expected);
+    }
+
+    #[rstest]
+    // 連続した一致が優先されること
+    #[case("api", "/src/api", "/src/a/p/i")]
+    // 単語の先頭での一致が優先されること
+    #[case("web", "/src/web", "/src/cobweb")]
+    fn test_score_order(#[case] query: &str, #[case] better: &str, #[case] worse: &str) {
+        assert!(score(query, better).unwrap() > score(query, worse).unwrap());
+    }
+
+    #[test]
+    // 一致の良い順、同点なら短い順に並び、一致しないものは除かれること
+    fn test_rank() {
+        let items = vec!["/src/cobweb", "/var/www/web", "/src/web", "/tmp"];
+        assert_eq!(
+            rank("web", items, |s| s.to_string()),
+            vec!["/src/web", "/var/www/web", "/src/cobweb"]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::bookmark::Bookmark;
+
+/// このマシンのホスト名。`BM_HOSTNAME`があればそれを使い、分からなければ`None`
+pub fn hostname() -> Option<String> {
+    let name = std::env::var("BM_HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| {
+            let output = Command::new("hostname").output().ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+        })?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// ホスト名が一致するか。大文字と小文字は区別せず、`laptop.local`は`laptop`とも一致する
+pub fn matches_host(pattern: &str, hostname: &str) -> bool {
+    let short = hostname.split('.').next().unwrap_or(hostname);
+    pattern.eq_ignore_ascii_case(hostname) || pattern.eq_ignore_ascii_case(short)
+}
+
+/// 保存先のブックマークをこのマシンから見た形にする
+///
+/// 保存されたパスの先頭を`rewrites`に従ってこのマシンでのパスに書き換え (書き込むときは元に戻す)、
+/// `hosts`で他のホストに限られたブックマークを隠す。ホスト名が分からなければ何も隠さない
+#[derive(Clone, Default, Debug)]
+pub struct HostView {
+    hostname: Option<String>,
+    /// 保存されたパスの先頭と、このマシンでのパスの先頭
+    rewrites: Vec<(PathBuf, PathBuf)>,
+}
+
+impl HostView {
+    pub fn new(hostname: Option<String>) -> Self {
+        Self {
+            hostname,
+            rewrites: Vec::new(),
+        }
+    }
+
+    pub fn with_rewrites(mut self, rewrites: Vec<(PathBuf, PathBuf)>) -> Self {
+        self.rewrites = rewrites;
+        self
+    }
+
+    /// 書き換えも隠すこともしないか
+    pub fn is_identity(&self) -> bool {
+        self.hostname.is_none() && self.rewrites.is_empty()
+    }
+
+    /// このマシンで使えるブックマークか
+    pub fn is_visible(&self, bookmark: &Bookmark) -> bool {
+        match &self.hostname {
+            Some(hostname) => {
+                bookmark.get_hosts().is_empty()
+                    || bookmark
+                        .get_hosts()
+                        .iter()
+                        .any(|host| matches_host(host, hostname))
+            }
+            None => true,
+        }
+    }
+
+    /// 保存されたブックマークを、このマシンでのパスにする
+    pub fn to_local(&self, bookmark: Bookmark) -> Bookmark {
+        let rewrites = self.rewrites.iter().map(|(stored, local)| (stored, local));
+        rewrite(bookmark, rewrites)
+    }
+
+    /// このマシンでのパスのブックマークを、保存する形に戻す
+    pub fn to_stored(&self, bookmark: Bookmark) -> Bookmark {
+        let rewrites = self.rewrites.iter().map(|(stored, local)| (local, stored));
+        rewrite(bookmark, rewrites)
+    }
+
+    /// 保存されたブックマークを、このマシンで使えるもの (このマシンでのパスにしたもの) と、
+    /// 隠すもの (元の位置を添えたもの) に分ける
+    pub fn split(&self, bookmarks: Vec<Bookmark>) -> (Vec<Bookmark>, Vec<(usize, Bookmark)>) {
+        let mut visible = Vec::new();
+        let mut hidden = Vec::new();
+        for (i, bookmark) in bookmarks.into_iter().enumerate() {
+            if self.is_visible(&bookmark) {
+                visible.push(self.to_local(bookmark));
+            } else {
+                hidden.push((i, bookmark));
+            }
+        }
+        (visible, hidden)
+    }
+
+    /// `split`で分けたブックマークを保存する形に戻し、隠したものを元の位置に戻す
+    pub fn join(&self, visible: Vec<Bookmark>, hidden: Vec<(usize, Bookmark)>) -> Vec<Bookmark> {
+        let mut bookmarks: Vec<Bookmark> = visible
+            .into_iter()
+            .map(|bookmark| self.to_stored(bookmark))
+            .collect();
+        for (i, bookmark) in hidden {
+            bookmarks.insert(i.min(bookmarks.len()), bookmark);
+        }
+        bookmarks
+    }
+}
+
+/// パスの先頭を書き換える。一致するものが複数あれば最も長いものを使う
+fn rewrite<'a>(
+    mut bookmark: Bookmark,
+    rewrites: impl Iterator<Item = (&'a PathBuf, &'a PathBuf)>,
+) -> Bookmark {
+    let path = bookmark.get_path();
+    let rewritten = rewrites
+        .filter_map(|(from, to)| {
+            let rest = path.strip_prefix(from).ok()?;
+            Some((from.components().count(), to, rest))
+        })
+        .min_by_key(|(len, _, _)| std::cmp::Reverse(*len))
+        .map(|(_, to, rest)| join(to, rest));
+    if let Some(path) = rewritten {
+        let fingerprint = bookmark.get_fingerprint().cloned();
+        bookmark.relocate(path, fingerprint);
+    }
+    bookmark
+}
+
+/// `rest`が空なら`base`そのものにする (`join`は末尾に`/`を付けてしまう)
+fn join(base: &Path, rest: &Path) -> PathBuf {
+    if rest.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn view() -> HostView {
+        HostView::new(Some("macbook.local".to_string())).with_rewrites(vec![
+            (PathBuf::from("/home/alice"), PathBuf::from("/Users/alice")),
+            (
+                PathBuf::from("/home/alice/work"),
+                PathBuf::from("/Volumes/work"),
+            ),
+        ])
+    }
+
+    #[rstest]
+    #[case("macbook", "macbook", true)]
+    #[case("MacBook", "macbook", true)]
+    // ドメインを除いたホスト名とも一致すること
+    #[case("macbook", "macbook.local", true)]
+    #[case("macbook.local", "macbook.local", true)]
+    #[case("macbook.lan", "macbook.local", false)]
+    #[case("devbox", "macbook", false)]
+    fn test_matches_host(#[case] pattern: &str, #[case] hostname: &str, #[case] expected: bool) {
+        assert_eq!(matches_host(pattern, hostname), expected);
+    }
+
+    #[rstest]
+    #[case("/home/alice/src", "/Users/alice/src")]
+    #[case("/home/alice", "/Users/alice")]
+    // 最も長く一致するものが使われること
+    #[case("/home/alice/work/api", "/Volumes/work/api")]
+    // 先頭がコンポーネント単位で一致しなければ書き換えないこと
+    #[case("/home/alicia", "/home/alicia")]
+    #[case("/srv/app", "/srv/app")]
+    fn test_to_local(#[case] stored: &str, #[case] local: &str) {
+        let bookmark = view().to_local(Bookmark::new(stored, vec![]));
+        assert_eq!(bookmark.get_path().as_os_str(), local);
+        // 書き込むときは元のパスに戻ること
+        let bookmark = view().to_stored(bookmark);
+        assert_eq!(bookmark.get_path().as_os_str(), stored);
+    }
+
+    #[rstest]
+    #[case(vec![], true)]
+    #[case(vec!["macbook"], true)]
+    #[case(vec!["devbox", "MACBOOK"], true)]
+    #[case(vec!["devbox"], false)]
+    fn test_is_visible(#[case] hosts: Vec<&str>, #[case] expected: bool) {
+        let hosts = hosts.into_iter().map(String::from).collect();
+        let bookmark = Bookmark::new("/srv/app", vec![]).with_hosts(hosts);
+        assert_eq!(view().is_visible(&bookmark), expected);
+        // ホスト名が分からなければ隠さないこと
+        assert!(HostView::default().is_visible(&bookmark));
+    }
+
+    #[test]
+    // 隠したブックマークは元の位置に戻され、他は保存する形に戻されること
+    fn test_split_and_join() {
+        let devbox = vec!["devbox".to_string()];
+        let bookmarks = vec![
+            Bookmark::new("/home/alice/a", vec![]),
+            Bookmark::new("/home/alice/b", vec![]).with_hosts(devbox.clone()),
+            Bookmark::new("/home/alice/c", vec![]),
+            Bookmark::new("/home/alice/d", vec![]).with_hosts(devbox),
+        ];
+        let (mut visible, hidden) = view().split(bookmarks.clone());
+        assert_eq!(
+            visible,
+            vec![
+                Bookmark::new("/Users/alice/a", vec![]),
+                Bookmark::new("/Users/alice/c", vec![]),
+            ]
+        );
+        assert_eq!(view().join(visible.clone(), hidden.clone()), bookmarks);
+
+        // 追加されたブックマークは末尾に保存されること
+        visible.push(Bookmark::new("/Users/alice/e", vec![]));
+        let joined = view().join(visible, hidden);
+        let paths: Vec<_> = joined
+            .iter()
+            .map(|b| b.get_path().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "/home/alice/a",
+                "/home/alice/b",
+                "/home/alice/c",
+                "/home/alice/d",
+                "/home/alice/e"
+            ]
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use clap::ValueEnum;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::bookmark::{bytes_to_path, Bookmark};
+use crate::export::{Document, DOCUMENT_VERSION};
+
+/// `bm import --from`で選べる取り込み元のツール
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Source {
+    /// A file written by `bm export --to json`
+    Json,
+    /// The database of zoxide (db.zo), or the output of `zoxide query --list --score`
+    Zoxide,
+    /// autojump.txt
+    Autojump,
+    /// The data file of z (~/.z)
+    Z,
+    /// The data file of fasd (~/.fasd)
+    Fasd,
+    /// The bookmark file of bashmarks (~/.sdirs)
+    Bashmarks,
+    /// The bookmark file of fzf-marks (~/.fzf-marks)
+    FzfMarks,
+    /// The bookmarks of GTK file choosers (~/.config/gtk-3.0/bookmarks)
+    Gtk,
+}
+
+/// 他のツールに保存されていた1件
+#[derive(Clone, PartialEq, Debug)]
+pub struct Entry {
+    /// 取り込むブックマーク (`bm export`で書き出したものはタグや訪問の記録も持つ)
+    pub bookmark: Bookmark,
+    /// ツール独自のスコア (zoxideのrank、autojumpのweightなど)
+    pub score: Option<f64>,
+    /// 最後に使われた時刻 (UNIXエポックからの秒数)
+    pub last_accessed: Option<u64>,
+}
+
+impl Entry {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            bookmark: Bookmark::new(path, vec![]),
+            score: None,
+            last_accessed: None,
+        }
+    }
+
+    fn with_name(mut self, name: Option<String>) -> Self {
+        self.bookmark = self.bookmark.with_name(name);
+        self
+    }
+
+    fn with_score(mut self, score: f64, last_accessed: Option<u64>) -> Self {
+        self.score = Some(score);
+        self.last_accessed = last_accessed;
+        self
+    }
+}
+
+/// 取り込まなかった項目と、その理由
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Skipped {
+    pub item: String,
+    pub reason: String,
+}
+
+/// 読み取った項目と、読み取れなかった行
+#[derive(Default, PartialEq, Debug)]
+pub struct Parsed {
+    pub entries: Vec<Entry>,
+    pub skipped: Vec<Skipped>,
+}
+
+/// zoxideのデータベースの形式のバージョン
+const ZOXIDE_VERSION: u32 = 3;
+
+/// ツールのファイルの内容を読み取る。`~`や`$HOME`で始まるパスは`home`に展開する
+///
+/// 読み取れない行は`skipped`に入れ、ファイル全体の形式が違う場合はエラーにする
+pub fn parse(source: Source, content: &[u8], home: Option<&Path>) -> Result<Parsed, Error> {
+    let parse_line = match source {
+        Source::Json => return parse_document(content),
+        Source::Zoxide if content.starts_with(&ZOXIDE_VERSION.to_le_bytes()) => {
+            return parse_zoxide_database(content);
+        }
+        Source::Zoxide => parse_zoxide_line,
+        Source::Autojump => parse_autojump_line,
+        Source::Z | Source::Fasd => parse_z_line,
+        Source::Bashmarks => parse_bashmarks_line,
+        Source::FzfMarks => parse_fzf_marks_line,
+        Source::Gtk => parse_gtk_line,
+    };
+    let mut parsed = Parsed::default();
+    for (i, line) in content.split(|&b| b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.trim_ascii().is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        match parse_line(line, home) {
+            Ok(entry) => parsed.entries.push(entry),
+            Err(reason) => parsed.skipped.push(Skipped {
+                item: format!("line {}", i + 1),
+                reason,
+            }),
+        }
+    }
+    Ok(parsed)
+}
+
+/// `bm export --to json`で書き出したファイル (タグや訪問の記録もそのまま読む)
+fn parse_document(content: &[u8]) -> Result<Parsed, Error> {
+    let document: Document =
+        serde_json::from_slice(content).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    if document.version > DOCUMENT_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported format version: {}", document.version),
+        ));
+    }
+    let entries = document
+        .bookmarks
+        .into_iter()
+        .map(|bookmark| Entry {
+            bookmark,
+            score: None,
+            last_accessed: None,
+        })
+        .collect();
+    Ok(Parsed {
+        entries,
+        skipped: Vec::new(),
+    })
+}
+
+/// bincodeで書かれたzoxideのデータベース (バージョン、件数、各項目のパス・rank・最終アクセス時刻)
+fn parse_zoxide_database(content: &[u8]) -> Result<Parsed, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "not a zoxide database");
+    let mut reader = Reader(&content[4..]);
+    let count = reader.u64().ok_or_else(invalid)?;
+    let mut parsed = Parsed::default();
+    for _ in 0..count {
+        let len = reader.u64().and_then(|n| usize::try_from(n).ok());
+        let path = len.and_then(|n| reader.take(n)).ok_or_else(invalid)?;
+        let rank = reader.u64().map(f64::from_bits).ok_or_else(invalid)?;
+        let last_accessed = reader.u64().ok_or_else(invalid)?;
+        parsed
+            .entries
+            .push(Entry::new(bytes_to_path(path.to_vec())?).with_score(rank, Some(last_accessed)));
+    }
+    if !reader.0.is_empty() {
+        return Err(invalid());
+    }
+    Ok(parsed)
+}
+
+/// リトルエンディアンのバイト列を先頭から読む
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+/// `zoxide query --list --score`の出力 (`  12.5 /path`)
+fn parse_zoxide_line(line: &[u8], _home: Option<&Path>) -> Result<Entry, String> {
+    let line = line.trim_ascii_start();
+    let (score, path) = split_once(line, b' ').ok_or("expected a score and a path")?;
+    Ok(Entry::new(to_path(path.trim_ascii_start().to_vec())?)
+        .with_score(parse_number(score)?, None))
+}
+
+/// autojump.txt (`weight<TAB>path`)
+fn parse_autojump_line(line: &[u8], _home: Option<&Path>) -> Result<Entry, String> {
+    let (weight, path) = split_once(line, b'\t').ok_or("expected a weight and a path")?;
+    Ok(Entry::new(to_path(path.to_vec())?).with_score(parse_number(weight)?, None))
+}
+
+/// zとfasdのデータファイル (`path|rank|time`)
+fn parse_z_line(line: &[u8], _home: Option<&Path>) -> Result<Entry, String> {
+    let mut fields = line.rsplitn(3, |&b| b == b'|');
+    let (Some(time), Some(rank), Some(path)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err("expected a path, a rank and a time separated by '|'".to_string());
+    };
+    Ok(Entry::new(to_path(path.to_vec())?)
+        .with_score(parse_number(rank)?, Some(parse_number(time)?)))
+}
+
+/// bashmarksの`export DIR_name="path"`
+fn parse_bashmarks_line(line: &[u8], home: Option<&Path>) -> Result<Entry, String> {
+    let rest = line
+        .trim_ascii()
+        .strip_prefix(b"export DIR_")
+        .ok_or("expected 'export DIR_<name>=\"<path>\"'")?;
+    let (name, value) = split_once(rest, b'=').ok_or("expected 'export DIR_<name>=\"<path>\"'")?;
+    let value = value
+        .strip_prefix(b"\"")
+        .and_then(|v| v.strip_suffix(b"\""))
+        .unwrap_or(value);
+    Ok(Entry::new(to_path(expand_home(value, home)?)?).with_name(Some(to_name(name)?)))
+}
+
+/// fzf-marksの`name : path`
+fn parse_fzf_marks_line(line: &[u8], home: Option<&Path>) -> Result<Entry, String> {
+    let separator = b" : ";
+    let at = line
+        .windows(separator.len())
+        .position(|w| w == separator)
+        .ok_or("expected '<name> : <path>'")?;
+    let name = line[..at].trim_ascii();
+    let path = line[at + separator.len()..].trim_ascii();
+    Ok(Entry::new(to_path(expand_home(path, home)?)?).with_name(Some(to_name(name)?)))
+}
+
+/// GTKのブックマーク (`file:///path%20with%20spaces label`)
+fn parse_gtk_line(line: &[u8], _home: Option<&Path>) -> Result<Entry, String> {
+    let (uri, label) = match split_once(line, b' ') {
+        Some((uri, label)) => (uri, Some(label.trim_ascii())),
+        None => (line, None),
+    };
+    let path = uri
+        .strip_prefix(b"file://")
+        .filter(|p| p.starts_with(b"/"))
+        .ok_or_else(|| format!("not a local directory: {}", String::from_utf8_lossy(uri)))?;
+    let name = label.filter(|l| !l.is_empty()).map(to_name).transpose()?;
+    Ok(Entry::new(to_path(percent_decode(path)?)?).with_name(name))
+}
+
+fn split_once(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let at = bytes.iter().position(|&b| b == separator)?;
+    Some((&bytes[..at], &bytes[at + 1..]))
+}
+
+fn parse_number<T: std::str::FromStr>(bytes: &[u8]) -> Result<T, String> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or_else(|| format!("invalid number: {}", String::from_utf8_lossy(bytes)))
+}
+
+fn to_path(bytes: Vec<u8>) -> Result<PathBuf, String> {
+    if bytes.is_empty() {
+        return Err("empty path".to_string());
+    }
+    bytes_to_path(bytes).map_err(|e| e.to_string())
+}
+
+fn to_name(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| "name is not valid UTF-8".to_string())
+}
+
+/// 先頭の`~`や`$HOME`をホームディレクトリに置き換える
+fn expand_home(path: &[u8], home: Option<&Path>) -> Result<Vec<u8>, String> {
+    let rest = [b"~".as_slice(), b"$HOME", b"${HOME}"]
+        .iter()
+        .find_map(|prefix| {
+            path.strip_prefix(*prefix)
+                .filter(|rest| rest.is_empty() || rest.starts_with(b"/"))
+        });
+    match rest {
+        Some(rest) => {
+            let home = home.ok_or("HOME is not set")?;
+            let mut expanded = home.as_os_str().as_encoded_bytes().to_vec();
+            expanded.extend_from_slice(rest);
+            Ok(expanded)
+        }
+        None => Ok(path.to_vec()),
+    }
+}
+
+/// URIの`%XX`をバイトに戻す
+fn percent_decode(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        if b != b'%' {
+            decoded.push(b);
+            continue;
+        }
+        let hex = [iter.next(), iter.next()];
+        let byte = match hex {
+            [Some(&h), Some(&l)] => std::str::from_utf8(&[h, l])
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok()),
+            _ => None,
+        };
+        decoded.push(byte.ok_or("invalid percent-encoding")?);
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn entry(path: &str, name: Option<&str>, score: Option<f64>, last: Option<u64>) -> Entry {
+        Entry {
+            bookmark: Bookmark::new(path, vec![]).with_name(name.map(String::from)),
+            score,
+            last_accessed: last,
+        }
+    }
+
+    fn skipped(line: usize, reason: &str) -> Skipped {
+        Skipped {
+            item: format!("line {}", line),
+            reason: reason.to_string(),
+        }
+    }
+
+    #[rstest]
+    #[case(
+        Source::Zoxide,
+        include_bytes!("../tests/fixtures/import/db.zo").as_slice(),
+        vec![
+            entry("/home/user/src/bm", None, Some(12.5), Some(1_700_000_000)),
+            entry("/home/user/My Documents", None, Some(1.0), Some(1_690_000_000)),
+        ],
+        vec![]
+    )]
+    #[case(
+        Source::Zoxide,
+        include_bytes!("../tests/fixtures/import/zoxide-query.txt").as_slice(),
+        vec![
+            entry("/home/user/src/bm", None, Some(12.5), None),
+            entry("/home/user/My Documents", None, Some(1.0), None),
+        ],
+        vec![]
+    )]
+    #[case(
+        Source::Autojump,
+        include_bytes!("../tests/fixtures/import/autojump.txt").as_slice(),
+        vec![
+            entry("/home/user/src/bm", None, Some(22.360679775), None),
+            entry("/home/user/My Documents", None, Some(10.0), None),
+        ],
+        vec![skipped(3, "expected a weight and a path")]
+    )]
+    #[case(
+        Source::Z,
+        include_bytes!("../tests/fixtures/import/z").as_slice(),
+        vec![
+            entry("/home/user/src/bm", None, Some(40.0), Some(1_700_000_000)),
+            entry("/home/user/a|b", None, Some(2.5), Some(1_690_000_000)),
+        ],
+        vec![skipped(3, "invalid number: soon")]
+    )]
+    #[case(
+        Source::Fasd,
+        include_bytes!("../tests/fixtures/import/fasd").as_slice(),
+        vec![
+            entry("/home/user/src/bm", None, Some(8.2), Some(1_700_000_000)),
+            entry("/home/user/notes.txt", None, Some(1.0), Some(1_690_000_000)),
+        ],
+        vec![]
+    )]
+    #[case(
+        Source::Bashmarks,
+        include_bytes!("../tests/fixtures/import/sdirs").as_slice(),
+        vec![
+            entry("/home/user/src/bm", Some("bm"), None, None),
+            entry("/srv/www", Some("www"), None, None),
+        ],
+        vec![skipped(3, "expected 'export DIR_<name>=\"<path>\"'")]
+    )]
+    #[case(
+        Source::FzfMarks,
+        include_bytes!("../tests/fixtures/import/fzf-marks").as_slice(),
+        vec![
+            entry("/home/user/src/bm", Some("bm"), None, None),
+            entry("/home/user/My Documents", Some("my docs"), None, None),
+        ],
+        vec![]
+    )]
+    #[case(
+        Source::Gtk,
+        include_bytes!("../tests/fixtures/import/gtk-bookmarks").as_slice(),
+        vec![
+            entry("/home/user/src/bm", None, None, None),
+            entry("/home/user/My Documents", Some("Docs"), None, None),
+        ],
+        vec![skipped(3, "not a local directory: sftp://example.com/srv")]
+    )]
+    fn test_parse(
+        #[case] source: Source,
+        #[case] content: &[u8],
+        #[case] entries: Vec<Entry>,
+        #[case] skipped: Vec<Skipped>,
+    ) {
+        let parsed = parse(source, content, Some(Path::new("/home/user"))).unwrap();
+        assert_eq!(parsed, Parsed { entries, skipped });
+    }
+
+    #[rstest]
+    // 途中で切れたデータベース
+    #[case(&include_bytes!("../tests/fixtures/import/db.zo")[..20])]
+    // 後ろに余分なデータがある
+    #[case(&[include_bytes!("../tests/fixtures/import/db.zo").as_slice(), b"x"].concat())]
+    fn test_parse_broken_zoxide_database(#[case] content: &[u8]) {
+        let err = parse(Source::Zoxide, content, None).unwrap_err();
+        assert_eq!(err.to_string(), "not a zoxide database");
+    }
+
+    #[test]
+    // HOMEが設定されていなければ`~`で始まるパスは読み取れないこと
+    fn test_parse_without_home() {
+        let parsed = parse(Source::FzfMarks, b"bm : ~/src/bm\nroot : /\n", None).unwrap();
+        assert_eq!(parsed.entries, vec![entry("/", Some("root"), None, None)]);
+        assert_eq!(parsed.skipped, vec![skipped(1, "HOME is not set")]);
+    }
+}
@@ -0,0 +1,249 @@
+use clap::ValueEnum;
+
+/// `bm init`が対応するシェル
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Nushell,
+    Powershell,
+}
+
+/// ブックマークに移動する関数と、`bm`とその関数の補完。
+/// 補完の候補は`bm complete`が`値<TAB>説明`の形で出力する (関数は`bm go`と同じく名前で補完する)
+const BASH: &str = r#"{cmd}() {
+    local dir
+    dir="$(command bm search --dir "$@")" || return
+    [ -n "$dir" ] || return 1
+    builtin cd -- "$dir"
+}
+
+_bm_complete() {
+    local IFS=$'\n'
+    local words=("${COMP_WORDS[@]}")
+    words[COMP_CWORD]="$2"
+    COMPREPLY=($(command bm complete --index "$COMP_CWORD" -- "${words[@]}" 2>/dev/null | cut -f1))
+    if [[ ${#COMPREPLY[@]} -eq 1 && ${COMPREPLY[0]} == */ ]]; then
+        compopt -o nospace
+    fi
+}
+complete -F _bm_complete bm
+
+_bm_{cmd}_complete() {
+    local IFS=$'\n'
+    COMPREPLY=($(command bm complete --index 2 -- bm go "$2" 2>/dev/null | cut -f1 | grep -v '^-'))
+}
+complete -F _bm_{cmd}_complete {cmd}
+"#;
+
+/// Ctrl-Gでブックマークを選んで移動する
+const BASH_KEY_BINDINGS: &str = r#"
+if [[ $- == *i* ]]; then
+    bind -x '"\C-g": {cmd}'
+fi
+"#;
+
+const ZSH: &str = r#"{cmd}() {
+    local dir
+    dir="$(command bm search --dir "$@")" || return
+    [[ -n $dir ]] || return 1
+    builtin cd -- "$dir"
+}
+
+_bm_complete() {
+    local -a values
+    values=(${(f)"$(command bm complete --index $((CURRENT - 1)) -- "${words[@]}" 2>/dev/null | cut -f1)"})
+    # ディレクトリの後ろには空白を入れず、続けて補完できるようにする
+    compadd -S '' -a -- ${(M)values:#*/}
+    compadd -a -- ${values:#*/}
+}
+
+_bm_{cmd}_complete() {
+    local -a names
+    names=(${(f)"$(command bm complete --index 2 -- bm go "$PREFIX" 2>/dev/null | cut -f1 | grep -v '^-')"})
+    compadd -a names
+}
+
+if (( $+functions[compdef] )); then
+    compdef _bm_complete bm
+    compdef _bm_{cmd}_complete {cmd}
+fi
+"#;
+
+const ZSH_KEY_BINDINGS: &str = r#"
+_bm_{cmd}_widget() {
+    {cmd} </dev/tty
+    zle reset-prompt
+}
+zle -N _bm_{cmd}_widget
+bindkey '^G' _bm_{cmd}_widget
+"#;
+
+const FISH: &str = r#"function {cmd} --description 'Jump to a bookmark'
+    set -l dir (command bm search --dir $argv)
+    or return
+    test -n "$dir"; or return 1
+    cd -- $dir
+end
+
+complete -c bm -f -a '(command bm complete --index (count (commandline -opc)) -- (commandline -opc) (commandline -ct) 2>/dev/null)'
+complete -c {cmd} -f -a '(command bm complete --index 2 -- bm go (commandline -ct) 2>/dev/null | string match -v -- "-*")'
+"#;
+
+const FISH_KEY_BINDINGS: &str = r#"
+bind \cg '{cmd}; commandline -f repaint'
+bind -M insert \cg '{cmd}; commandline -f repaint'
+"#;
+
+const NUSHELL: &str = r#"def "nu-complete bm" [spans: list<string>] {
+    ^bm complete --index (($spans | length) - 1) -- ...$spans
+    | lines
+    | each {|line|
+        let parts = ($line | split row "\t")
+        {value: $parts.0, description: ($parts.1? | default "")}
+    }
+}
+
+def "nu-complete bm names" [] {
+    nu-complete bm [bm go ""] | where {|c| not ($c.value | str starts-with "-") }
+}
+
+# 既に外部コマンドの補完が設定されていれば、bm以外はそちらに任せる
+let bm_fallback_completer = $env.config.completions.external.completer?
+$env.config.completions.external.enable = true
+$env.config.completions.external.completer = {|spans|
+    if $spans.0 == "bm" {
+        nu-complete bm $spans
+    } else if $bm_fallback_completer != null {
+        do $bm_fallback_completer $spans
+    }
+}
+
+# Jump to a bookmark
+def --env {cmd} [query?: string@"nu-complete bm names"] {
+    let dir = (if $query == null { ^bm search --dir } else { ^bm search --dir $query }) | str trim --right
+    if ($dir | is-not-empty) {
+        cd $dir
+    }
+}
+"#;
+
+const NUSHELL_KEY_BINDINGS: &str = r#"
+$env.config.keybindings = ($env.config.keybindings | append {
+    name: bm_{cmd}
+    modifier: control
+    keycode: char_g
+    mode: [emacs vi_normal vi_insert]
+    event: { send: executehostcommand cmd: "{cmd}" }
+})
+"#;
+
+const POWERSHELL: &str = r#"function global:{cmd} {
+    param([string]$Query)
+    $dir = if ($Query) { bm search --dir $Query } else { bm search --dir }
+    if ($LASTEXITCODE -eq 0 -and $dir) {
+        Set-Location -LiteralPath $dir
+    }
+}
+
+Register-ArgumentCompleter -Native -CommandName bm -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements | ForEach-Object { $_.ToString() })
+    if ($wordToComplete -eq '') {
+        $words += ''
+    }
+    bm complete --index ($words.Count - 1) -- @words 2>$null | ForEach-Object {
+        $value, $help = $_ -split "`t", 2
+        if (-not $help) {
+            $help = $value
+        }
+        [System.Management.Automation.CompletionResult]::new($value, $value, 'ParameterValue', $help)
+    }
+}
+
+Register-ArgumentCompleter -CommandName {cmd} -ParameterName Query -ScriptBlock {
+    param($commandName, $parameterName, $wordToComplete)
+    bm complete --index 2 -- bm go $wordToComplete 2>$null |
+        ForEach-Object { ($_ -split "`t")[0] } |
+        Where-Object { -not $_.StartsWith('-') }
+}
+"#;
+
+const POWERSHELL_KEY_BINDINGS: &str = r#"
+Set-PSReadLineKeyHandler -Chord Ctrl+g -ScriptBlock {
+    {cmd}
+    [Microsoft.PowerShell.PSConsoleReadLine]::InvokePrompt()
+}
+"#;
+
+/// シェルの設定ファイルで評価するスクリプトを生成する
+pub fn init_script(shell: Shell, cmd: &str, key_bindings: bool) -> String {
+    let (script, bindings) = match shell {
+        Shell::Bash => (BASH, BASH_KEY_BINDINGS),
+        Shell::Zsh => (ZSH, ZSH_KEY_BINDINGS),
+        Shell::Fish => (FISH, FISH_KEY_BINDINGS),
+        Shell::Nushell => (NUSHELL, NUSHELL_KEY_BINDINGS),
+        Shell::Powershell => (POWERSHELL, POWERSHELL_KEY_BINDINGS),
+    };
+    let mut script = script.to_string();
+    if key_bindings {
+        script.push_str(bindings);
+    }
+    script.replace("{cmd}", cmd)
+}
+
+/// 関数名として全てのシェルで使える名前か確かめる (clapの`value_parser`で使う)
+pub fn parse_command_name(name: &str) -> Result<String, String> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err("must start with a letter or '_' and contain only letters, digits, '_' and '-'".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Shell::Bash)]
+    #[case(Shell::Zsh)]
+    #[case(Shell::Fish)]
+    #[case(Shell::Nushell)]
+    #[case(Shell::Powershell)]
+    fn test_init_script(#[case] shell: Shell) {
+        let name = format!("{:?}", shell).to_lowercase();
+        insta::assert_snapshot!(name, init_script(shell, "cb", true));
+    }
+
+    #[test]
+    // コマンド名が置き換えられ、キー割り当てを省けること
+    fn test_init_script_options() {
+        let script = init_script(Shell::Zsh, "j", false);
+        assert!(script.starts_with("j() {"));
+        assert!(script.contains("compdef _bm_j_complete j"));
+        assert!(script.contains("compdef _bm_complete bm"));
+        assert!(!script.contains("{cmd}"));
+        assert!(!script.contains("bindkey"));
+    }
+
+    #[rstest]
+    #[case("cb", true)]
+    #[case("_jump-to", true)]
+    #[case("", false)]
+    #[case("-cb", false)]
+    #[case("1cb", false)]
+    // シェルの構文として解釈される文字は使えない
+    #[case("cb;rm", false)]
+    #[case("c b", false)]
+    fn test_parse_command_name(#[case] name: &str, #[case] valid: bool) {
+        assert_eq!(parse_command_name(name).is_ok(), valid);
+    }
+}
@@ -0,0 +1,768 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::bookmark::Bookmark;
+use crate::dao::{lock_sibling, replace_or_push, sibling_path};
+use crate::repository::IBookmarkRepository;
+use crate::tag_query::TagQuery;
+
+#[cfg(test)]
+use mockall::automock;
+
+/// プロファイル内の位置と、その位置にあるブックマーク
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Placed {
+    pub index: usize,
+    pub bookmark: Bookmark,
+}
+
+/// 1つの操作による変更
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    /// ブックマークの追加 (`before`なし)、削除 (`after`なし)、変更
+    Bookmark {
+        profile: String,
+        before: Option<Box<Placed>>,
+        after: Option<Box<Placed>>,
+    },
+    CreateProfile {
+        name: String,
+    },
+    DeleteProfile {
+        name: String,
+        bookmarks: Vec<Bookmark>,
+    },
+    RenameProfile {
+        from: String,
+        to: String,
+    },
+    SetDefaultProfile {
+        before: String,
+        after: String,
+    },
+}
+
+/// 記録された操作
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Operation {
+    pub id: u64,
+    /// 操作した時刻 (UNIXエポックからの秒数)
+    pub time: u64,
+    /// 操作したコマンド (例: `delete --tag rust`)
+    pub command: String,
+    pub changes: Vec<Change>,
+}
+
+impl Operation {
+    /// 変更の概要 (例: `+1 ~2 -3`、`profile work created`)
+    pub fn summary(&self) -> String {
+        let (mut added, mut updated, mut deleted) = (0, 0, 0);
+        let mut parts = Vec::new();
+        for change in &self.changes {
+            match change {
+                Change::Bookmark {
+                    before: None,
+                    after: Some(_),
+                    ..
+                } => added += 1,
+                Change::Bookmark {
+                    before: Some(_),
+                    after: None,
+                    ..
+                } => deleted += 1,
+                Change::Bookmark { .. } => updated += 1,
+                Change::CreateProfile { name } => parts.push(format!("profile {} created", name)),
+                Change::DeleteProfile { name, .. } => {
+                    parts.push(format!("profile {} deleted", name))
+                }
+                Change::RenameProfile { from, to } => {
+                    parts.push(format!("profile {} renamed to {}", from, to))
+                }
+                Change::SetDefaultProfile { after, .. } => {
+                    parts.push(format!("default profile set to {}", after))
+                }
+            }
+        }
+        if added + updated + deleted > 0 {
+            parts.insert(0, format!("+{} ~{} -{}", added, updated, deleted));
+        }
+        parts.join(", ")
+    }
+}
+
+/// 操作の記録ファイルの1行
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Operation(Operation),
+    Undo { id: u64 },
+    Redo { id: u64 },
+}
+
+/// 操作の記録。追記のみで、取り消しややり直しも記録として追加する
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    /// 保存先のファイルの隣に記録する
+    pub fn for_store(store: &Path) -> Self {
+        Self {
+            path: sibling_path(store, ".journal"),
+        }
+    }
+
+    fn lock(&self) -> Result<File, Error> {
+        lock_sibling(&self.path)
+    }
+
+    fn read(&self) -> Result<Vec<Record>, Error> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{}:{}: {}", self.path.display(), i + 1, e),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn append(&self, record: &Record) -> Result<(), Error> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())
+    }
+
+    /// 操作を記録する
+    fn record(&self, time: u64, command: &str, changes: Vec<Change>) -> Result<(), Error> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let _lock = self.lock()?;
+        let id = self
+            .read()?
+            .iter()
+            .filter_map(|record| match record {
+                Record::Operation(operation) => Some(operation.id),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+            + 1;
+        self.append(&Record::Operation(Operation {
+            id,
+            time,
+            command: command.to_string(),
+            changes,
+        }))
+    }
+}
+
+/// 記録を順に再生した結果
+struct State {
+    /// 全ての操作と、取り消されているかどうか
+    operations: Vec<(Operation, bool)>,
+    /// 取り消せる操作のID (最後が最新)
+    undoable: Vec<u64>,
+    /// やり直せる操作のID (最後が最後に取り消したもの)
+    redoable: Vec<u64>,
+}
+
+impl State {
+    fn replay(records: Vec<Record>) -> Self {
+        let mut state = State {
+            operations: Vec::new(),
+            undoable: Vec::new(),
+            redoable: Vec::new(),
+        };
+        for record in records {
+            match record {
+                Record::Operation(operation) => {
+                    state.undoable.push(operation.id);
+                    state.redoable.clear();
+                    state.operations.push((operation, false));
+                }
+                Record::Undo { id } => {
+                    state.undoable.retain(|i| *i != id);
+                    state.redoable.push(id);
+                    state.set_undone(id, true);
+                }
+                Record::Redo { id } => {
+                    state.redoable.retain(|i| *i != id);
+                    state.undoable.push(id);
+                    state.set_undone(id, false);
+                }
+            }
+        }
+        state
+    }
+
+    fn set_undone(&mut self, id: u64, undone: bool) {
+        if let Some((_, u)) = self.operations.iter_mut().find(|(o, _)| o.id == id) {
+            *u = undone;
+        }
+    }
+
+    fn operation(&self, id: u64) -> Operation {
+        self.operations
+            .iter()
+            .find(|(o, _)| o.id == id)
+            .map(|(o, _)| o.clone())
+            .expect("recorded operation")
+    }
+}
+
+/// 操作の取り消しとやり直し
+#[cfg_attr(test, automock)]
+pub trait History {
+    /// 最後の操作を取り消す。取り消せる操作がなければ`None`
+    fn undo(&mut self) -> Result<Option<Operation>, Error>;
+    /// 最後に取り消した操作をやり直す。やり直せる操作がなければ`None`
+    fn redo(&mut self) -> Result<Option<Operation>, Error>;
+    /// 全ての操作と、取り消されているかどうかを古い順に取得する
+    fn history(&mut self) -> Result<Vec<(Operation, bool)>, Error>;
+}
+
+/// 変更を操作の記録に残すリポジトリ
+///
+/// 訪問の記録だけの変更は取り消す対象にならないため記録しない。
+pub struct JournaledRepository<R: IBookmarkRepository> {
+    inner: R,
+    journal: Journal,
+    command: String,
+    now: u64,
+}
+
+impl<R: IBookmarkRepository> JournaledRepository<R> {
+    pub fn new(inner: R, journal: Journal, command: String, now: u64) -> Self {
+        Self {
+            inner,
+            journal,
+            command,
+            now,
+        }
+    }
+
+    fn record(&self, changes: Vec<Change>) -> Result<(), Error> {
+        self.journal.record(self.now, &self.command, changes)
+    }
+
+    /// 指定したプロファイルに切り替えて処理し、元のプロファイルに戻す
+    fn in_profile<T>(
+        &mut self,
+        profile: &str,
+        f: impl FnOnce(&mut R) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let current = self.inner.current_profile()?;
+        self.inner.select_profile(profile)?;
+        let result = f(&mut self.inner);
+        // 元のプロファイルが削除や名前の変更で消えていれば、デフォルトのプロファイルのままにする
+        if current != profile && self.inner.find_profiles()?.contains(&current) {
+            self.inner.select_profile(&current)?;
+        }
+        result
+    }
+
+    /// 変更を適用する (`undo`なら逆向きに適用する)
+    fn apply(&mut self, changes: &[Change], undo: bool) -> Result<(), Error> {
+        let ordered: Vec<&Change> = if undo {
+            changes.iter().rev().collect()
+        } else {
+            changes.iter().collect()
+        };
+        let mut i = 0;
+        while i < ordered.len() {
+            match ordered[i] {
+                Change::Bookmark { profile, .. } => {
+                    // 同じプロファイルへの連続した変更はまとめて適用する
+                    let mut group = Vec::new();
+                    while let Some(Change::Bookmark {
+                        profile: p,
+                        before,
+                        after,
+                    }) = ordered.get(i)
+                    {
+                        if p != profile {
+                            break;
+                        }
+                        let (before, after) = (before.as_deref(), after.as_deref());
+                        group.push(if undo {
+                            (after, before)
+                        } else {
+                            (before, after)
+                        });
+                        i += 1;
+                    }
+                    self.in_profile(profile, |inner| {
+                        inner.update_all(&mut |bookmarks| replace_placed(bookmarks, &group))
+                    })?;
+                    continue;
+                }
+                Change::CreateProfile { name } if undo => {
+                    let is_empty = self.in_profile(name, |inner| inner.find_all())?.is_empty();
+                    if !is_empty {
+                        return Err(Error::other(format!(
+                            "profile {} has bookmarks; delete them first",
+                            name
+                        )));
+                    }
+                    self.inner.delete_profile(name)?;
+                }
+                Change::CreateProfile { name } => self.inner.create_profile(name)?,
+                Change::DeleteProfile { name, bookmarks } if undo => {
+                    self.inner.create_profile(name)?;
+                    self.inner.replace_stored(name, bookmarks)?;
+                }
+                Change::DeleteProfile { name, .. } => self.inner.delete_profile(name)?,
+                Change::RenameProfile { from, to } if undo => {
+                    self.inner.rename_profile(to, from)?
+                }
+                Change::RenameProfile { from, to } => self.inner.rename_profile(from, to)?,
+                Change::SetDefaultProfile { before, after } => {
+                    let name = if undo { before } else { after };
+                    self.inner.set_default_profile(name)?;
+                }
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+}
+
+/// `from`の位置のブックマークを取り除き、`to`をその位置に入れる。
+/// 取り除くものが見つからないか`from`と (訪問の記録を除いて) 異なるか、入れるものが既にあれば、
+/// 後から変更されたとみなしてエラーにする
+fn replace_placed(
+    bookmarks: &mut Vec<Bookmark>,
+    group: &[(Option<&Placed>, Option<&Placed>)],
+) -> Result<(), Error> {
+    let conflict = |bookmark: &Bookmark| {
+        Error::other(format!(
+            "{} has been changed since; undo or redo it by hand",
+            bookmark.get_path().display()
+        ))
+    };
+    for placed in group.iter().filter_map(|(from, _)| *from) {
+        let path = placed.bookmark.get_path();
+        let i = bookmarks
+            .iter()
+            .position(|b| b.get_path() == path && same_except_visits(b, &placed.bookmark))
+            .ok_or_else(|| conflict(&placed.bookmark))?;
+        bookmarks.remove(i);
+    }
+    let mut inserted: Vec<&Placed> = group.iter().filter_map(|(_, to)| *to).collect();
+    inserted.sort_by_key(|placed| placed.index);
+    for placed in inserted {
+        if bookmarks
+            .iter()
+            .any(|b| b.get_path() == placed.bookmark.get_path())
+        {
+            return Err(conflict(&placed.bookmark));
+        }
+        let index = placed.index.min(bookmarks.len());
+        bookmarks.insert(index, placed.bookmark.clone());
+    }
+    Ok(())
+}
+
+/// 2つの並びの差分を、位置を含めた変更として求める
+fn diff(profile: &str, before: &[Bookmark], after: &[Bookmark]) -> Vec<Change> {
+    let before_index: HashMap<&Path, usize> = before
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (b.get_path(), i))
+        .collect();
+    let after_index: HashMap<&Path, usize> = after
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (b.get_path(), i))
+        .collect();
+    let placed = |index: usize, bookmark: &Bookmark| {
+        Box::new(Placed {
+            index,
+            bookmark: bookmark.clone(),
+        })
+    };
+    let mut changes = Vec::new();
+    for (i, old) in before.iter().enumerate() {
+        match after_index.get(old.get_path()) {
+            None => changes.push(Change::Bookmark {
+                profile: profile.to_string(),
+                before: Some(placed(i, old)),
+                after: None,
+            }),
+            Some(&j) if !same_except_visits(old, &after[j]) => changes.push(Change::Bookmark {
+                profile: profile.to_string(),
+                before: Some(placed(i, old)),
+                after: Some(placed(j, &after[j])),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (j, new) in after.iter().enumerate() {
+        if !before_index.contains_key(new.get_path()) {
+            changes.push(Change::Bookmark {
+                profile: profile.to_string(),
+                before: None,
+                after: Some(placed(j, new)),
+            });
+        }
+    }
+    changes
+}
+
+fn same_except_visits(a: &Bookmark, b: &Bookmark) -> bool {
+    let mut a = a.clone();
+    a.inherit_visits(b);
+    a == *b
+}
+
+impl<R: IBookmarkRepository> IBookmarkRepository for JournaledRepository<R> {
+    fn save(&mut self, bookmark: &Bookmark) -> Result<(), Error> {
+        self.update_all(&mut |bookmarks| {
+            replace_or_push(bookmarks, bookmark);
+            Ok(())
+        })
+    }
+
+    fn delete(&mut self, paths: &[PathBuf]) -> Result<(), Error> {
+        self.update_all(&mut |bookmarks| {
+            bookmarks.retain(|b| !paths.iter().any(|p| p == b.get_path()));
+            Ok(())
+        })
+    }
+
+    fn find_all(&mut self) -> Result<Vec<Bookmark>, Error> {
+        self.inner.find_all()
+    }
+
+    fn update_all(
+        &mut self,
+        f: &mut dyn FnMut(&mut Vec<Bookmark>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let profile = self.inner.current_profile()?;
+        let mut changes = Vec::new();
+        self.inner.update_all(&mut |bookmarks| {
+            let before = bookmarks.clone();
+            f(bookmarks)?;
+            changes = diff(&profile, &before, bookmarks);
+            Ok(())
+        })?;
+        self.record(changes)
+    }
+
+    fn find_by_tags(&mut self, query: &TagQuery) -> Result<Vec<Bookmark>, Error> {
+        self.inner.find_by_tags(query)
+    }
+
+    fn count_tags(&mut self) -> Result<Vec<(String, usize)>, Error> {
+        self.inner.count_tags()
+    }
+
+    fn find_stored(&mut self, profile: &str) -> Result<Vec<Bookmark>, Error> {
+        self.inner.find_stored(profile)
+    }
+
+    fn replace_stored(&mut self, profile: &str, bookmarks: &[Bookmark]) -> Result<(), Error> {
+        self.inner.replace_stored(profile, bookmarks)
+    }
+
+    fn select_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.inner.select_profile(name)
+    }
+
+    fn current_profile(&mut self) -> Result<String, Error> {
+        self.inner.current_profile()
+    }
+
+    fn find_profiles(&mut self) -> Result<Vec<String>, Error> {
+        self.inner.find_profiles()
+    }
+
+    fn create_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.inner.create_profile(name)?;
+        self.record(vec![Change::CreateProfile {
+            name: name.to_string(),
+        }])
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<(), Error> {
+        // 他のホストに限られたものも含め、保存されたままの形で残す
+        let bookmarks = self.inner.find_stored(name)?;
+        self.inner.delete_profile(name)?;
+        self.record(vec![Change::DeleteProfile {
+            name: name.to_string(),
+            bookmarks,
+        }])
+    }
+
+    fn rename_profile(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        self.inner.rename_profile(from, to)?;
+        self.record(vec![Change::RenameProfile {
+            from: from.to_string(),
+            to: to.to_string(),
+        }])
+    }
+
+    fn get_default_profile(&mut self) -> Result<String, Error> {
+        self.inner.get_default_profile()
+    }
+
+    fn set_default_profile(&mut self, name: &str) -> Result<(), Error> {
+        let before = self.inner.get_default_profile()?;
+        self.inner.set_default_profile(name)?;
+        if before == name {
+            return Ok(());
+        }
+        self.record(vec![Change::SetDefaultProfile {
+            before,
+            after: name.to_string(),
+        }])
+    }
+}
+
+impl<R: IBookmarkRepository> History for JournaledRepository<R> {
+    fn undo(&mut self) -> Result<Option<Operation>, Error> {
+        let _lock = self.journal.lock()?;
+        let state = State::replay(self.journal.read()?);
+        let Some(&id) = state.undoable.last() else {
+            return Ok(None);
+        };
+        let operation = state.operation(id);
+        self.apply(&operation.changes, true)?;
+        self.journal.append(&Record::Undo { id })?;
+        Ok(Some(operation))
+    }
+
+    fn redo(&mut self) -> Result<Option<Operation>, Error> {
+        let _lock = self.journal.lock()?;
+        let state = State::replay(self.journal.read()?);
+        let Some(&id) = state.redoable.last() else {
+            return Ok(None);
+        };
+        let operation = state.operation(id);
+        self.apply(&operation.changes, false)?;
+        self.journal.append(&Record::Redo { id })?;
+        Ok(Some(operation))
+    }
+
+    fn history(&mut self) -> Result<Vec<(Operation, bool)>, Error> {
+        Ok(State::replay(self.journal.read()?).operations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dao::BookmarkDao;
+    use crate::host::HostView;
+    use crate::mount::MockMountTable;
+    use crate::path::MockPathOps;
+    use crate::repository::BookmarkRepository;
+    use crate::selector::MockBookmarkSelector;
+    use crate::usecase::{prune_bookmarks, PruneOptions};
+    use tempfile::TempDir;
+
+    type Repo = JournaledRepository<BookmarkRepository<BookmarkDao>>;
+
+    fn setup() -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = dir.path().join("bookmarks.json");
+        (dir, storage)
+    }
+
+    fn open(storage: &Path, command: &str) -> Repo {
+        let repo = BookmarkRepository::new(BookmarkDao::new(storage.to_path_buf()));
+        JournaledRepository::new(repo, Journal::for_store(storage), command.to_string(), 0)
+    }
+
+    #[test]
+    // pruneを取り消すとファイルが元通りになり、やり直すと再び削除されること
+    fn test_undo_prune_restores_file() {
+        let (dir, storage) = setup();
+        let existing = dir.path().join("existing");
+        fs::create_dir(&existing).unwrap();
+        let mut repo = open(&storage, "add");
+        for path in [
+            dir.path().join("broken1"),
+            existing.clone(),
+            dir.path().join("broken2"),
+        ] {
+            let mut bookmark = Bookmark::new(path, vec!["tag".to_string()]);
+            bookmark.visit(1);
+            repo.save(&bookmark).unwrap();
+        }
+        let original = fs::read(&storage).unwrap();
+
+        let mut repo = open(&storage, "prune");
+        let mut mounts = MockMountTable::new();
+        mounts.expect_unmounted_mount_point().return_const(None);
+        let options = PruneOptions {
+            dry_run: false,
+            interactive: false,
+            max_fraction: 1.0,
+            force: false,
+        };
+        let report = prune_bookmarks(
+            &mut repo,
+            &MockPathOps::new(),
+            &mounts,
+            &MockBookmarkSelector::new(),
+            options,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.deleted.len(), 2);
+        let pruned = fs::read(&storage).unwrap();
+
+        let mut repo = open(&storage, "undo");
+        assert_eq!(repo.undo().unwrap().unwrap().command, "prune");
+        assert_eq!(fs::read(&storage).unwrap(), original);
+
+        assert_eq!(repo.redo().unwrap().unwrap().command, "prune");
+        assert_eq!(fs::read(&storage).unwrap(), pruned);
+        assert!(repo.redo().unwrap().is_none());
+    }
+
+    #[test]
+    // 取り消しは新しい順に行われ、新しい操作をするとやり直せなくなること
+    fn test_undo_order_and_redo_cleared() {
+        let (_dir, storage) = setup();
+        let mut repo = open(&storage, "cmd");
+        repo.save(&Bookmark::new("/a", vec![])).unwrap();
+        repo.save(&Bookmark::new("/b", vec![])).unwrap();
+        repo.save(&Bookmark::new("/a", vec!["tag".to_string()]))
+            .unwrap();
+
+        repo.undo().unwrap();
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![Bookmark::new("/a", vec![]), Bookmark::new("/b", vec![])]
+        );
+        repo.undo().unwrap();
+        assert_eq!(repo.find_all().unwrap(), vec![Bookmark::new("/a", vec![])]);
+
+        repo.save(&Bookmark::new("/c", vec![])).unwrap();
+        assert!(repo.redo().unwrap().is_none());
+        let undone: Vec<bool> = repo.history().unwrap().iter().map(|(_, u)| *u).collect();
+        assert_eq!(undone, vec![false, true, true, false]);
+    }
+
+    #[test]
+    // 訪問の記録だけの変更は記録されないこと
+    fn test_visits_not_recorded() {
+        let (_dir, storage) = setup();
+        let mut repo = open(&storage, "add");
+        let mut bookmark = Bookmark::new("/a", vec![]);
+        repo.save(&bookmark).unwrap();
+        bookmark.visit(1);
+        repo.save(&bookmark).unwrap();
+
+        assert_eq!(repo.history().unwrap().len(), 1);
+    }
+
+    #[test]
+    // 後から変更されたブックマークは取り消さずにエラーとすること
+    fn test_undo_conflict() {
+        let (_dir, storage) = setup();
+        let mut repo = open(&storage, "add");
+        repo.save(&Bookmark::new("/a", vec![])).unwrap();
+        // 記録されない変更
+        BookmarkRepository::new(BookmarkDao::new(storage.clone()))
+            .delete(&[PathBuf::from("/a")])
+            .unwrap();
+
+        assert!(repo.undo().is_err());
+        assert!(!repo.history().unwrap()[0].1);
+    }
+
+    #[test]
+    // 後からタグなどが変更されたブックマークは取り消さず、訪問の記録だけの変更なら取り消すこと
+    fn test_undo_conflict_with_edited_bookmark() {
+        let (_dir, storage) = setup();
+        let mut repo = open(&storage, "tag");
+        repo.save(&Bookmark::new("/a", vec![])).unwrap();
+        repo.save(&Bookmark::new("/a", vec!["old".to_string()]))
+            .unwrap();
+        // 記録されない変更
+        BookmarkRepository::new(BookmarkDao::new(storage.clone()))
+            .save(&Bookmark::new("/a", vec!["new".to_string()]))
+            .unwrap();
+
+        assert!(repo.undo().is_err());
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![Bookmark::new("/a", vec!["new".to_string()])]
+        );
+
+        let mut visited = Bookmark::new("/a", vec!["old".to_string()]);
+        visited.visit(1);
+        BookmarkRepository::new(BookmarkDao::new(storage.clone()))
+            .save(&visited)
+            .unwrap();
+        repo.undo().unwrap();
+        assert_eq!(repo.find_all().unwrap(), vec![Bookmark::new("/a", vec![])]);
+    }
+
+    #[test]
+    // プロファイルの削除を取り消すと、ブックマークごと元に戻ること
+    fn test_undo_delete_profile() {
+        let (_dir, storage) = setup();
+        let mut repo = open(&storage, "profile");
+        repo.create_profile("work").unwrap();
+        repo.select_profile("work").unwrap();
+        repo.save(&Bookmark::new("/a", vec![])).unwrap();
+        repo.select_profile("default").unwrap();
+        repo.delete_profile("work").unwrap();
+        assert_eq!(repo.find_profiles().unwrap(), vec!["default"]);
+
+        repo.undo().unwrap();
+        repo.select_profile("work").unwrap();
+        assert_eq!(repo.find_all().unwrap(), vec![Bookmark::new("/a", vec![])]);
+    }
+
+    #[test]
+    // このホストで隠したものや書き換えたパスも、プロファイルの削除を取り消すと保存されたまま戻ること
+    fn test_undo_delete_profile_keeps_stored_bookmarks() {
+        let (_dir, storage) = setup();
+        let mut stored = BookmarkRepository::new(BookmarkDao::new(storage.clone()));
+        stored.create_profile("work").unwrap();
+        stored.select_profile("work").unwrap();
+        stored
+            .save(&Bookmark::new("/home/alice/a", vec![]))
+            .unwrap();
+        stored
+            .save(&Bookmark::new("/home/alice/b", vec![]).with_hosts(vec!["devbox".to_string()]))
+            .unwrap();
+        let original = fs::read(&storage).unwrap();
+
+        let host = HostView::new(Some("macbook".to_string())).with_rewrites(vec![(
+            PathBuf::from("/home/alice"),
+            PathBuf::from("/Users/alice"),
+        )]);
+        let repo = BookmarkRepository::new(BookmarkDao::new(storage.clone())).with_host(host);
+        let mut repo =
+            JournaledRepository::new(repo, Journal::for_store(&storage), "profile".to_string(), 0);
+        repo.delete_profile("work").unwrap();
+        repo.undo().unwrap();
+        assert_eq!(fs::read(&storage).unwrap(), original);
+    }
+}
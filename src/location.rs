@@ -0,0 +1,267 @@
+use crate::config::StoreKind;
+use crate::dao::{lock_sibling, sibling_path, write_atomically};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// 保存先や設定ファイルの場所を決める環境変数 (空の値は未設定として扱う)
+#[derive(Default, Clone, Debug)]
+pub struct Env {
+    pub home: Option<PathBuf>,
+    pub xdg_config_home: Option<PathBuf>,
+    pub xdg_data_home: Option<PathBuf>,
+    pub bm_file: Option<PathBuf>,
+}
+
+impl Env {
+    pub fn from_process() -> Self {
+        fn var(name: &str) -> Option<PathBuf> {
+            std::env::var_os(name)
+                .filter(|v| !v.is_empty())
+                .map(PathBuf::from)
+        }
+        Self {
+            home: var("HOME"),
+            xdg_config_home: var("XDG_CONFIG_HOME"),
+            xdg_data_home: var("XDG_DATA_HOME"),
+            bm_file: var("BM_FILE"),
+        }
+    }
+}
+
+/// 保存先と一緒に移動する、保存先の隣のファイル (操作の記録、移行前のバックアップ、SQLiteのジャーナル)。
+/// ロック用のファイルは中身がないため移動しない
+const SIBLING_SUFFIXES: &[&str] = &[".journal", ".bak", "-journal", "-wal"];
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct StoreLocation {
+    pub path: PathBuf,
+    /// 以前のバージョンの保存先 (存在すれば`path`に移動する)
+    pub legacy: Option<PathBuf>,
+}
+
+impl StoreLocation {
+    /// 保存先のディレクトリを作成し、以前のバージョンの保存先にしかファイルがなければ移動する
+    ///
+    /// 移動した場合は移動元のパスを返す
+    pub fn prepare(&self) -> Result<Option<&Path>, Error> {
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let legacy = match &self.legacy {
+            Some(legacy) if legacy.exists() && !self.path.exists() => legacy,
+            _ => return Ok(None),
+        };
+        // 同時に起動した他のプロセスと排他し、ロックを取ってから確かめ直す
+        let _lock = lock_sibling(legacy)?;
+        if !legacy.exists() || self.path.exists() {
+            return Ok(None);
+        }
+        // 保存先そのものは最後に移動し、途中で失敗しても次に起動したときに続きから移動する
+        for suffix in SIBLING_SUFFIXES {
+            let from = sibling_path(legacy, suffix);
+            if from.exists() {
+                move_file(&from, &sibling_path(&self.path, suffix))?;
+            }
+        }
+        move_file(legacy, &self.path)?;
+        Ok(Some(legacy))
+    }
+}
+
+/// ファイルを移動する。別のファイルシステムへは、書きかけの内容が見えないよう一時ファイルを経て書き込む
+fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            write_atomically(to, &fs::read(from)?)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// 設定ファイルのパス (`$XDG_CONFIG_HOME/bookmark-cli/config.toml`)
+pub fn config_path(env: &Env) -> Option<PathBuf> {
+    let config_home = match (&env.xdg_config_home, &env.home) {
+        (Some(dir), _) => dir.clone(),
+        (None, Some(home)) => home.join(".config"),
+        (None, None) => return None,
+    };
+    Some(config_home.join("bookmark-cli").join("config.toml"))
+}
+
+/// 保存先を決める
+///
+/// `--file`、`BM_FILE`、`$XDG_DATA_HOME/bookmark-cli/`、`$HOME`直下の順に優先する
+pub fn store_location(
+    file: Option<&Path>,
+    store: StoreKind,
+    env: &Env,
+) -> Result<StoreLocation, Error> {
+    if let Some(file) = file.or(env.bm_file.as_deref()) {
+        return Ok(StoreLocation {
+            path: file.to_path_buf(),
+            legacy: None,
+        });
+    }
+    let legacy = env
+        .home
+        .as_ref()
+        .map(|home| home.join(store.legacy_filename()));
+    match &env.xdg_data_home {
+        Some(data_home) => Ok(StoreLocation {
+            path: data_home.join("bookmark-cli").join(store.filename()),
+            legacy,
+        }),
+        None => match legacy {
+            Some(path) => Ok(StoreLocation { path, legacy: None }),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                "HOME is not set (use --file or BM_FILE to specify the bookmark file)",
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn env(home: bool, xdg_data_home: bool, bm_file: bool) -> Env {
+        Env {
+            home: home.then(|| PathBuf::from("/home/me")),
+            xdg_config_home: None,
+            xdg_data_home: xdg_data_home.then(|| PathBuf::from("/data")),
+            bm_file: bm_file.then(|| PathBuf::from("/env/bookmarks")),
+        }
+    }
+
+    #[rstest]
+    // --fileが最優先
+    #[case(
+        Some("/flag/bookmarks"),
+        env(true, true, true),
+        "/flag/bookmarks",
+        None
+    )]
+    // 次にBM_FILE
+    #[case(None, env(true, true, true), "/env/bookmarks", None)]
+    // 次に$XDG_DATA_HOME (旧来の保存先から移行する)
+    #[case(
+        None,
+        env(true, true, false),
+        "/data/bookmark-cli/bookmarks.json",
+        Some("/home/me/.bookmarks")
+    )]
+    #[case(
+        None,
+        env(false, true, false),
+        "/data/bookmark-cli/bookmarks.json",
+        None
+    )]
+    // 最後に$HOME直下
+    #[case(None, env(true, false, false), "/home/me/.bookmarks", None)]
+    fn test_store_location(
+        #[case] file: Option<&str>,
+        #[case] env: Env,
+        #[case] expected_path: &str,
+        #[case] expected_legacy: Option<&str>,
+    ) {
+        let location = store_location(file.map(Path::new), StoreKind::Json, &env).unwrap();
+        assert_eq!(
+            location,
+            StoreLocation {
+                path: PathBuf::from(expected_path),
+                legacy: expected_legacy.map(PathBuf::from),
+            }
+        );
+    }
+
+    #[test]
+    // 保存先を決められない場合はエラー
+    fn test_store_location_without_home() {
+        assert!(store_location(None, StoreKind::Json, &env(false, false, false)).is_err());
+    }
+
+    #[rstest]
+    #[case(
+        Some("/config"),
+        Some("/home/me"),
+        Some("/config/bookmark-cli/config.toml")
+    )]
+    #[case(
+        None,
+        Some("/home/me"),
+        Some("/home/me/.config/bookmark-cli/config.toml")
+    )]
+    #[case(None, None, None)]
+    fn test_config_path(
+        #[case] xdg_config_home: Option<&str>,
+        #[case] home: Option<&str>,
+        #[case] expected: Option<&str>,
+    ) {
+        let env = Env {
+            home: home.map(PathBuf::from),
+            xdg_config_home: xdg_config_home.map(PathBuf::from),
+            ..Env::default()
+        };
+        assert_eq!(config_path(&env), expected.map(PathBuf::from));
+    }
+
+    #[test]
+    // 旧来の保存先のファイルが、操作の記録やバックアップとともに新しい保存先に移動されること
+    fn test_prepare_moves_legacy_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join(".bookmarks");
+        fs::write(&legacy, "{}").unwrap();
+        fs::write(sibling_path(&legacy, ".journal"), "journal").unwrap();
+        fs::write(sibling_path(&legacy, ".bak"), "backup").unwrap();
+        let location = StoreLocation {
+            path: dir.path().join("data/bookmark-cli/bookmarks.json"),
+            legacy: Some(legacy.clone()),
+        };
+
+        assert_eq!(location.prepare().unwrap(), Some(legacy.as_path()));
+        assert!(!legacy.exists());
+        assert_eq!(fs::read_to_string(&location.path).unwrap(), "{}");
+        for (suffix, content) in [(".journal", "journal"), (".bak", "backup")] {
+            assert!(!sibling_path(&legacy, suffix).exists());
+            let moved = sibling_path(&location.path, suffix);
+            assert_eq!(fs::read_to_string(moved).unwrap(), content);
+        }
+
+        // 新しい保存先に既にファイルがあれば何もしない
+        fs::write(&legacy, "legacy").unwrap();
+        assert_eq!(location.prepare().unwrap(), None);
+        assert_eq!(fs::read_to_string(&location.path).unwrap(), "{}");
+    }
+
+    #[test]
+    // 同時に移動しようとしても1回だけ移動され、内容が失われないこと
+    fn test_prepare_concurrently() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join(".bookmarks");
+        fs::write(&legacy, "{}").unwrap();
+        let location = StoreLocation {
+            path: dir.path().join("data/bookmark-cli/bookmarks.json"),
+            legacy: Some(legacy.clone()),
+        };
+
+        let moved = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| s.spawn(|| location.prepare().unwrap().is_some()))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(|moved| *moved)
+                .count()
+        });
+        assert_eq!(moved, 1);
+        assert!(!legacy.exists());
+        assert_eq!(fs::read_to_string(&location.path).unwrap(), "{}");
+    }
+}
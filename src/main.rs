@@ -1,22 +1,77 @@
 mod bookmark;
+mod clock;
+mod complete;
+mod config;
 mod dao;
+mod editor;
+mod export;
+mod format;
+mod frecency;
+mod fuzzy;
+mod host;
+mod import;
+mod init;
+mod journal;
+mod location;
+mod migration;
+mod mount;
 mod path;
+mod relocate;
 mod repository;
 mod selector;
+mod sqlite_dao;
+mod tag_query;
 mod usecase;
 
-use clap::{Parser, Subcommand};
-use dao::BookmarkDao;
-use path::DefaultPathOps;
-use repository::BookmarkRepository;
+use bookmark::{Bookmark, Kind};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clock::{Clock, SystemClock};
+use complete::{Candidates, GlobalOptions};
+use config::{Config, StoreKind};
+use dao::{BookmarkDao, IBookmarkDao};
+use editor::ExternalEditor;
+use export::Target;
+use format::{Field, Format};
+use host::HostView;
+use import::Source;
+use init::Shell;
+use journal::{Journal, JournaledRepository};
+use location::Env;
+use mount::SystemMountTable;
+use path::{DefaultPathOps, PathOps};
+use relocate::DirScanner;
+use repository::{BookmarkRepository, IBookmarkRepository};
 use selector::FuzzyBookmarkSelector;
-use std::fs::File;
-use std::path::PathBuf;
-use usecase::{add_bookmark, delete_bookmark, list_bookmarks, prune_bookmarks, search_bookmark};
+use sqlite_dao::SqliteBookmarkDao;
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use tag_query::TagQuery;
+use usecase::{
+    add_bookmark, add_tags, bookmark_names, bookmark_stats, count_tags, create_profile,
+    current_profile, dedupe_bookmarks, default_profile, delete_bookmark,
+    delete_bookmarks_by_target, delete_profile, edit_bookmarks, expand_bookmark,
+    find_bookmarks_by_query, find_bookmarks_in_all_profiles_by_query, go_bookmark,
+    import_bookmarks, list_bookmarks, list_bookmarks_in_all_profiles, list_history, list_profiles,
+    merge_tags, migrate_store, move_bookmark, open_bookmark, prune_bookmarks, redo_operation,
+    relocate_bookmarks, remove_tags, rename_profile, rename_tag, search_bookmark,
+    search_bookmark_by_query, search_bookmark_in_all_profiles,
+    search_bookmark_in_all_profiles_by_query, set_hosts, set_tags, undo_operation, ImportOptions,
+    PruneOptions, QueryResult, SortOrder,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Storage backend (overrides `store` in the config file)
+    #[arg(long, value_enum)]
+    store: Option<StoreKind>,
+    /// The bookmark file to use (overrides BM_FILE)
+    #[arg(long, value_name = "PATH")]
+    file: Option<PathBuf>,
+    /// The profile to use (defaults to the default profile)
+    #[arg(long, short, value_name = "NAME")]
+    profile: Option<String>,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -26,80 +81,854 @@ struct Cli {
 enum Commands {
     /// Add a bookmark
     Add {
-        /// The bookmark to add (the absolute path of a directory).
+        /// The directory to bookmark, or the file with `--file` (normalized before it is saved).
         /// If not specified, the current directory will be registered.
-        path: Option<String>,
+        path: Option<PathBuf>,
+        /// Bookmark a regular file instead of a directory
+        #[arg(long, requires = "path")]
+        file: bool,
+        /// A unique name to jump to the bookmark with `bm go <NAME>`
+        #[arg(short, long)]
+        name: Option<String>,
         /// Tags for the bookmark
         #[arg(short, long, value_delimiter = ',', num_args = 0..)]
         tags: Option<Vec<String>>,
+        /// Save the path relative to the home directory or a root in `[paths.roots]`
+        /// (e.g. `~/src/api` or `$WORKSPACE/api`), so that it works on other machines
+        #[arg(long)]
+        portable: bool,
+    },
+    /// Print the path of the bookmark with the given name
+    Go {
+        /// The name given with `bm add --name`
+        name: String,
+    },
+    /// Delete bookmarks
+    ///
+    /// Without arguments, a prompt is shown to select the bookmarks to delete.
+    Delete {
+        /// The names or paths of the bookmarks to delete
+        #[arg(conflicts_with_all = ["tags", "tag_query"])]
+        bookmarks: Vec<String>,
+        #[command(flatten)]
+        filter: FilterArgs,
     },
-    /// Delete a bookmark
-    Delete,
     /// Search for a bookmark
-    Search,
+    ///
+    /// Without a query, a prompt is shown to filter and select a bookmark.
+    /// With a query, the only matching bookmark is printed without a prompt; if several
+    /// bookmarks match, the prompt is shown with the matches only when running in a terminal.
+    /// Exits with 3 if nothing matches and with 4 if the query is ambiguous.
+    Search {
+        /// Fuzzy query matched against the path, name and tags
+        query: Option<String>,
+        /// Print all matches, best first, instead of selecting one
+        #[arg(long)]
+        all: bool,
+        /// Print the directory containing a file bookmark instead of the file itself
+        #[arg(long)]
+        dir: bool,
+        /// Search bookmarks in all profiles
+        #[arg(long)]
+        all_profiles: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Open a file bookmark in $VISUAL or $EDITOR
+    ///
+    /// The query selects the bookmark in the same way as `bm search`.
+    /// Exits with 3 if nothing matches and with 4 if the query is ambiguous.
+    Open {
+        /// Fuzzy query matched against the path, name and tags
+        query: Option<String>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Edit bookmarks in $VISUAL or $EDITOR
+    ///
+    /// Removed lines are deleted, new lines are added and changed lines are updated.
+    /// If there are errors, the editor is opened again with them as comments.
+    Edit {
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
     /// List bookmarks
-    List,
+    List {
+        /// List bookmarks in all profiles with the profile name
+        #[arg(long)]
+        all_profiles: bool,
+        /// The order of bookmarks
+        #[arg(long, value_enum, default_value_t)]
+        sort: SortOrder,
+        /// Print bookmarks in a machine-readable format
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+        /// The fields to print, separated by commas [default: path,name,tags,visits,last_visited]
+        #[arg(long, value_enum, value_delimiter = ',', requires = "format")]
+        fields: Vec<Field>,
+        /// Print the paths saved as `~/...` or `$NAME/...` as they resolve on this machine
+        #[arg(long)]
+        expanded: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Show the most frequently and recently used bookmarks
+    Stats {
+        /// The number of bookmarks to show
+        #[arg(short = 'n', long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Point a bookmark to another location, keeping its name, tags and visits
+    Mv {
+        /// The name or path of the bookmark
+        bookmark: String,
+        /// The new directory (or file, for a file bookmark)
+        path: PathBuf,
+    },
     /// Remove all broken bookmarks
-    Prune,
+    ///
+    /// Bookmarks under a mount point that is not mounted are kept, and nothing is deleted
+    /// if more than `prune.max_fraction` of the bookmarks (half by default) would be removed.
+    Prune {
+        /// Look for the directories that broken bookmarks were moved to and offer to
+        /// point the bookmarks there instead of deleting them
+        #[arg(long, conflicts_with_all = ["dry_run", "interactive"])]
+        relocate: bool,
+        /// Only print the bookmarks that would be deleted
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Choose the bookmarks to delete
+        #[arg(short, long, conflicts_with = "dry_run")]
+        interactive: bool,
+        /// Delete without the safeguards for unmounted drives and the fraction limit
+        #[arg(short, long)]
+        force: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Merge bookmarks whose paths point to the same place
+    ///
+    /// Paths are normalized as in `bm add`. The first bookmark added is kept and
+    /// takes the tags, visits and name of the others.
+    Dedupe {
+        /// Only print the bookmarks that would be merged
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Copy all bookmarks from one storage backend to another
+    MigrateStore {
+        /// The backend to copy from
+        #[arg(long, value_enum)]
+        from: StoreKind,
+        /// The backend to copy to
+        #[arg(long, value_enum)]
+        to: StoreKind,
+    },
+    /// Import bookmarks from another directory jumper
+    ///
+    /// Directories that are already bookmarked or no longer exist are skipped and reported
+    Import {
+        /// The tool the file comes from
+        #[arg(long, value_enum)]
+        from: Source,
+        /// The database or bookmark file of the tool
+        file: PathBuf,
+        /// Carry over the tool's scores and access times as visits
+        #[arg(long)]
+        with_scores: bool,
+        /// Import directories that do not exist (always done for --from json)
+        #[arg(long)]
+        keep_missing: bool,
+    },
+    /// Write bookmarks in a format for another tool
+    ///
+    /// `bm export --to json` writes all data of the bookmarks, which `bm import --from json` reads back
+    Export {
+        /// The format to write
+        #[arg(long, value_enum)]
+        to: Target,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Manage profiles
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    /// Change the tags of bookmarks
+    Tag {
+        #[command(subcommand)]
+        command: TagCommands,
+    },
+    /// List all tags with the number of bookmarks that have them
+    Tags,
+    /// Limit a bookmark to some hosts, for a bookmark file shared between machines
+    ///
+    /// The bookmark is hidden on other hosts. Without hosts, it is available on all hosts again.
+    Hosts {
+        /// The name or path of the bookmark
+        bookmark: String,
+        /// The host names, with or without the domain (`BM_HOSTNAME` overrides the name of this host)
+        hosts: Vec<String>,
+    },
+    /// Revert the last change to the bookmarks
+    Undo,
+    /// Apply the last undone change again
+    Redo,
+    /// Show the recorded changes, newest first
+    History {
+        /// The number of changes to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Print a script that defines a command to jump to bookmarks
+    ///
+    /// Add e.g. `eval "$(bm init zsh)"` to your shell's configuration file.
+    Init {
+        /// The shell to generate the script for
+        #[arg(value_enum)]
+        shell: Shell,
+        /// The name of the command to jump to a bookmark
+        #[arg(long, default_value = "cb", value_parser = init::parse_command_name)]
+        cmd: String,
+        /// Do not bind Ctrl-G to jump to a bookmark
+        #[arg(long)]
+        no_key_bindings: bool,
+    },
+    /// Print completion candidates for a command line (used by the `bm init` scripts)
+    #[command(hide = true)]
+    Complete {
+        /// The index of the word to complete (defaults to the last word)
+        #[arg(long)]
+        index: Option<usize>,
+        /// The words of the command line, starting with `bm`
+        #[arg(last = true)]
+        args: Vec<OsString>,
+    },
+}
+
+/// Options to narrow down bookmarks by their tags
+#[derive(Args)]
+struct FilterArgs {
+    /// Only bookmarks with this tag (can be repeated; all must match)
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>,
+    /// Only bookmarks whose tags match the expression, e.g. `rust & !archived | infra`
+    #[arg(long = "query", value_name = "EXPR")]
+    tag_query: Option<String>,
+}
+
+/// Subcommands for managing profiles
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// List profiles (the default profile is marked with `*`)
+    List,
+    /// Create an empty profile
+    Create {
+        /// The name of the profile
+        name: String,
+    },
+    /// Delete a profile and all of its bookmarks
+    Delete {
+        /// The name of the profile
+        name: String,
+    },
+    /// Rename a profile
+    Rename {
+        /// The current name of the profile
+        from: String,
+        /// The new name of the profile
+        to: String,
+    },
+    /// Show or set the default profile
+    Default {
+        /// The profile to use by default. If not specified, the current default is printed.
+        name: Option<String>,
+    },
+}
+
+/// Subcommands for managing tags
+#[derive(Subcommand)]
+enum TagCommands {
+    /// Add tags to a bookmark
+    Add {
+        /// The name or path of the bookmark
+        bookmark: String,
+        /// The tags to add
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from a bookmark
+    Remove {
+        /// The name or path of the bookmark
+        bookmark: String,
+        /// The tags to remove
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Replace all tags of a bookmark (no tags clears them)
+    Set {
+        /// The name or path of the bookmark
+        bookmark: String,
+        /// The new tags
+        tags: Vec<String>,
+    },
+    /// Rename a tag on all bookmarks
+    Rename {
+        /// The current name of the tag
+        old: String,
+        /// The new name of the tag (must not be in use)
+        new: String,
+    },
+    /// Replace a tag with another existing tag on all bookmarks
+    Merge {
+        /// The tag to merge away
+        from: String,
+        /// The tag to keep
+        into: String,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    let home_dir = std::env::var("HOME").unwrap_or_else(|_| {
-        eprintln!("HOME is not set");
+    let env = Env::from_process();
+    let config = Config::load(location::config_path(&env).as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
         std::process::exit(1);
     });
-    let src_filename = ".bookmarks";
-    let src = PathBuf::from(home_dir).join(src_filename);
-    if !src.exists() {
-        File::create(&src).unwrap_or_else(|_| {
-            eprintln!("failed to create file");
+    let store = cli.store.unwrap_or(config.store);
+    let host = host_view(&config);
+    if let Some(Commands::Complete { index, args }) = cli.command {
+        print_completions(index, args, store, &env, host);
+        return;
+    }
+    let file = cli.file.as_deref();
+    let repo_host = command_host(cli.command.as_ref(), &host);
+    let mut bookmark_repo = open_repository(store, file, &env, repo_host)
+        .and_then(|mut repo| {
+            if let Some(profile) = &cli.profile {
+                repo.select_profile(profile)?;
+            }
+            Ok(repo)
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
             std::process::exit(1);
         });
-    }
-
-    let dao = BookmarkDao::new(src);
-    let mut bookmark_repo = BookmarkRepository::new(dao);
 
-    let path_ops = DefaultPathOps::new();
+    let path_ops =
+        DefaultPathOps::new(config.paths.resolve_symlinks, config.paths.expand_variables)
+            .with_roots(config.paths.roots(env.home.as_deref()));
     let selector = FuzzyBookmarkSelector::new();
+    let clock = SystemClock::new();
 
     let result: Result<(), Box<dyn std::error::Error>> = match cli.command {
-        Some(Commands::Add { path, tags }) => {
-            let tags = match tags {
-                Some(t) => t,
-                None => vec![],
+        Some(Commands::Add {
+            path,
+            file,
+            name,
+            tags,
+            portable,
+        }) => {
+            let tags = tags.unwrap_or_default();
+            let kind = if file { Kind::File } else { Kind::Dir };
+            add_bookmark(
+                &mut bookmark_repo,
+                &path_ops,
+                path,
+                name,
+                tags,
+                kind,
+                portable,
+            )
+        }
+        Some(Commands::Go { name }) => go_bookmark(&mut bookmark_repo, &clock, &name)
+            .and_then(|bookmark| print_bookmark(&path_ops, &bookmark, false)),
+        Some(Commands::Delete { bookmarks, filter }) => {
+            let result = if bookmarks.is_empty() {
+                delete_bookmark(&mut bookmark_repo, &selector, tag_filter(filter).as_ref())
+            } else {
+                delete_bookmarks_by_target(&mut bookmark_repo, &path_ops, &bookmarks)
             };
-            add_bookmark(&mut bookmark_repo, &path_ops, path, tags)
+            result.map(|deleted| {
+                for bookmark in deleted {
+                    println!("deleted: {}", bookmark);
+                }
+            })
         }
-        Some(Commands::Delete) => delete_bookmark(&mut bookmark_repo, &selector),
-        Some(Commands::Search) => match search_bookmark(&mut bookmark_repo, &selector) {
-            Ok(Some(bookmark)) => {
-                println!("{}", bookmark.get_path());
-                Ok(())
+        Some(Commands::Search {
+            query: None,
+            all: false,
+            all_profiles,
+            dir,
+            filter,
+        }) => {
+            let filter = tag_filter(filter);
+            let result = if all_profiles {
+                search_bookmark_in_all_profiles(
+                    &mut bookmark_repo,
+                    &selector,
+                    &clock,
+                    filter.as_ref(),
+                )
+                .map(|selected| selected.map(|(_, bookmark)| bookmark))
+            } else {
+                search_bookmark(&mut bookmark_repo, &selector, &clock, filter.as_ref())
+            };
+            match result {
+                Ok(Some(bookmark)) => print_bookmark(&path_ops, &bookmark, dir),
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
             }
-            Ok(None) => Ok(()),
-            Err(e) => Err(e),
-        },
-        Some(Commands::List) => match list_bookmarks(&mut bookmark_repo) {
-            Ok(bookmarks) => {
-                for bookmark in bookmarks {
-                    println!("{}", bookmark);
+        }
+        Some(Commands::Search {
+            query,
+            all: true,
+            all_profiles,
+            dir,
+            filter,
+        }) => {
+            let query = query.unwrap_or_default();
+            let filter = tag_filter(filter);
+            let result = if all_profiles {
+                find_bookmarks_in_all_profiles_by_query(&mut bookmark_repo, &query, filter.as_ref())
+                    .map(|matches| matches.into_iter().map(|(_, bookmark)| bookmark).collect())
+            } else {
+                find_bookmarks_by_query(&mut bookmark_repo, &query, filter.as_ref())
+            };
+            result.and_then(|matches| {
+                if matches.is_empty() {
+                    exit_with(EXIT_NO_MATCH, &format!("No bookmark matches '{}'", query));
+                }
+                matches
+                    .iter()
+                    .try_for_each(|b| print_bookmark(&path_ops, b, dir))
+            })
+        }
+        Some(Commands::Search {
+            query: Some(query),
+            all: false,
+            all_profiles,
+            dir,
+            filter,
+        }) => {
+            let filter = tag_filter(filter);
+            // プロンプトは標準エラー出力に描画されるので、標準出力がリダイレクトされていても選択できる
+            let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+            let result = if all_profiles {
+                search_bookmark_in_all_profiles_by_query(
+                    &mut bookmark_repo,
+                    &selector,
+                    &clock,
+                    &query,
+                    interactive,
+                    filter.as_ref(),
+                )
+                .map(|result| result.map(|(_, bookmark)| bookmark))
+            } else {
+                search_bookmark_by_query(
+                    &mut bookmark_repo,
+                    &selector,
+                    &clock,
+                    &query,
+                    interactive,
+                    filter.as_ref(),
+                )
+            };
+            match result {
+                Ok(QueryResult::Selected(bookmark)) => print_bookmark(&path_ops, &bookmark, dir),
+                Ok(QueryResult::NoMatch) => {
+                    exit_with(EXIT_NO_MATCH, &format!("No bookmark matches '{}'", query))
                 }
+                Ok(QueryResult::Ambiguous(matches)) => {
+                    let mut message = format!(
+                        "{} bookmarks match '{}' (use --all to print them all):",
+                        matches.len(),
+                        query
+                    );
+                    for bookmark in &matches {
+                        message.push_str(&format!("\n  {}", bookmark));
+                    }
+                    exit_with(EXIT_AMBIGUOUS, &message)
+                }
+                Ok(QueryResult::Cancelled) => Ok(()),
+                Err(e) => Err(e),
+            }
+        }
+        Some(Commands::List {
+            all_profiles,
+            sort,
+            format: Some(format),
+            fields,
+            expanded,
+            filter,
+        }) => {
+            let filter = tag_filter(filter);
+            let result = if all_profiles {
+                list_bookmarks_in_all_profiles(&mut bookmark_repo, &clock, sort, filter.as_ref())
+            } else {
+                current_profile(&mut bookmark_repo).and_then(|profile| {
+                    list_bookmarks(&mut bookmark_repo, &clock, sort, filter.as_ref()).map(
+                        |bookmarks| {
+                            bookmarks
+                                .into_iter()
+                                .map(|bookmark| (profile.clone(), bookmark))
+                                .collect()
+                        },
+                    )
+                })
+            };
+            result.and_then(|bookmarks| {
+                let fields = if !fields.is_empty() {
+                    fields
+                } else if all_profiles {
+                    std::iter::once(Field::Profile)
+                        .chain(format::DEFAULT_FIELDS.iter().copied())
+                        .collect()
+                } else {
+                    format::DEFAULT_FIELDS.to_vec()
+                };
+                let bookmarks: Vec<_> = bookmarks
+                    .into_iter()
+                    .map(|(p, b)| (p, show_bookmark(&path_ops, b, expanded)))
+                    .collect();
+                let rows: Vec<_> = bookmarks.iter().map(|(p, b)| (p.as_str(), b)).collect();
+                let mut out = std::io::stdout().lock();
+                let skipped = format::write_bookmarks(&mut out, format, &fields, &rows)?;
+                print_skipped_items(&skipped);
                 Ok(())
+            })
+        }
+        Some(Commands::List {
+            all_profiles,
+            sort,
+            expanded,
+            filter,
+            ..
+        }) => {
+            let filter = tag_filter(filter);
+            let result: Result<Vec<String>, _> = if all_profiles {
+                list_bookmarks_in_all_profiles(&mut bookmark_repo, &clock, sort, filter.as_ref())
+                    .map(|bookmarks| {
+                        bookmarks
+                            .into_iter()
+                            .map(|(profile, bookmark)| {
+                                let bookmark = show_bookmark(&path_ops, bookmark, expanded);
+                                format!("[{}] {}", profile, bookmark)
+                            })
+                            .collect()
+                    })
+            } else {
+                list_bookmarks(&mut bookmark_repo, &clock, sort, filter.as_ref()).map(|bookmarks| {
+                    bookmarks
+                        .into_iter()
+                        .map(|b| show_bookmark(&path_ops, b, expanded).to_string())
+                        .collect()
+                })
+            };
+            match result {
+                Ok(lines) => {
+                    for line in lines {
+                        println!("{}", line);
+                    }
+                    Ok(())
+                }
+                Err(e) => Err(e),
             }
-            Err(e) => Err(e),
-        },
-        Some(Commands::Prune) => match prune_bookmarks(&mut bookmark_repo) {
-            Ok(deleted_bookmarks) => {
-                for bookmark in deleted_bookmarks {
+        }
+        Some(Commands::Open { query, filter }) => {
+            let editor = ExternalEditor::from_env();
+            let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+            let result = open_bookmark(
+                &mut bookmark_repo,
+                &selector,
+                &editor,
+                &path_ops,
+                &clock,
+                query.as_deref(),
+                interactive,
+                tag_filter(filter).as_ref(),
+            );
+            let query = query.unwrap_or_default();
+            match result {
+                Ok(QueryResult::NoMatch) => exit_with(
+                    EXIT_NO_MATCH,
+                    &format!("No file bookmark matches '{}'", query),
+                ),
+                Ok(QueryResult::Ambiguous(matches)) => {
+                    let mut message =
+                        format!("{} file bookmarks match '{}':", matches.len(), query);
+                    for bookmark in &matches {
+                        message.push_str(&format!("\n  {}", bookmark));
+                    }
+                    exit_with(EXIT_AMBIGUOUS, &message)
+                }
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            }
+        }
+        Some(Commands::Edit { filter }) => {
+            let editor = ExternalEditor::from_env();
+            edit_bookmarks(
+                &mut bookmark_repo,
+                &editor,
+                &path_ops,
+                tag_filter(filter).as_ref(),
+            )
+            .map(|summary| match summary {
+                Some(summary) => println!(
+                    "added {}, updated {}, deleted {}",
+                    summary.added, summary.updated, summary.deleted
+                ),
+                None => eprintln!("aborted"),
+            })
+        }
+        Some(Commands::Stats { limit }) => {
+            bookmark_stats(&mut bookmark_repo, &clock, limit).map(|stats| {
+                let now = clock.now();
+                println!("{:>8}  {:>6}  {:>10}  BOOKMARK", "SCORE", "VISITS", "LAST");
+                for (score, bookmark) in stats {
+                    let last = bookmark
+                        .get_last_visited()
+                        .map(|t| format_elapsed(now.saturating_sub(t)))
+                        .unwrap_or_default();
+                    println!(
+                        "{:>8.2}  {:>6}  {:>10}  {}",
+                        score,
+                        bookmark.get_visits(),
+                        last,
+                        bookmark
+                    );
+                }
+            })
+        }
+        Some(Commands::Dedupe { dry_run }) => {
+            dedupe_bookmarks(&mut bookmark_repo, &path_ops, dry_run).map(|merged| {
+                let verb = if dry_run { "would merge" } else { "merged" };
+                for (bookmark, sources) in merged {
+                    println!("{}: {}", verb, bookmark);
+                    for source in sources {
+                        println!("  <- {}", source.display());
+                    }
+                }
+            })
+        }
+        Some(Commands::Mv { bookmark, path }) => {
+            move_bookmark(&mut bookmark_repo, &path_ops, &bookmark, path)
+                .map(|bookmark| println!("{}", bookmark))
+        }
+        Some(Commands::Prune {
+            relocate: true,
+            force,
+            filter,
+            ..
+        }) => {
+            let roots = config.relocate.roots(env.home.as_deref());
+            let relocator = DirScanner::new(roots, config.relocate.max_depth);
+            let options = PruneOptions {
+                dry_run: false,
+                interactive: false,
+                max_fraction: config.prune.max_fraction,
+                force,
+            };
+            relocate_bookmarks(
+                &mut bookmark_repo,
+                &path_ops,
+                &SystemMountTable::load(),
+                &selector,
+                &relocator,
+                options,
+                tag_filter(filter).as_ref(),
+            )
+            .map(|summary| {
+                for (bookmark, path) in summary.relocated {
+                    println!("relocated: {} -> {}", bookmark, path.display());
+                }
+                for bookmark in summary.deleted {
                     println!("deleted: {}", bookmark);
                 }
-                Ok(())
+                print_skipped(&summary.skipped);
+            })
+        }
+        Some(Commands::Prune {
+            relocate: false,
+            dry_run,
+            interactive,
+            force,
+            filter,
+        }) => {
+            let options = PruneOptions {
+                dry_run,
+                interactive,
+                max_fraction: config.prune.max_fraction,
+                force,
+            };
+            match prune_bookmarks(
+                &mut bookmark_repo,
+                &path_ops,
+                &SystemMountTable::load(),
+                &selector,
+                options,
+                tag_filter(filter).as_ref(),
+            ) {
+                Ok(report) => {
+                    let verb = if dry_run { "would delete" } else { "deleted" };
+                    for bookmark in report.deleted {
+                        println!("{}: {}", verb, bookmark);
+                    }
+                    print_skipped(&report.skipped);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            }
+        }
+        Some(Commands::MigrateStore { from, to }) => {
+            if from == to {
+                Err("--from and --to must be different backends".into())
+            } else {
+                // --fileとBM_FILEは選択中の保存先にだけ適用する
+                let default_env = Env {
+                    bm_file: None,
+                    ..env.clone()
+                };
+                let open = |kind: StoreKind| {
+                    // 他のホストに限られたものも含め、保存されたままコピーする
+                    let host = HostView::default();
+                    if kind == store {
+                        open_repository(kind, file, &env, host)
+                    } else {
+                        open_repository(kind, None, &default_env, host)
+                    }
+                };
+                open(from)
+                    .and_then(|mut src_repo| {
+                        let mut dst_repo = open(to)?;
+                        migrate_store(&mut src_repo, &mut dst_repo)
+                    })
+                    .map(|count| println!("copied {} bookmarks", count))
+            }
+        }
+        Some(Commands::Import {
+            from,
+            file,
+            with_scores,
+            keep_missing,
+        }) => std::fs::read(&file)
+            .and_then(|content| import::parse(from, &content, env.home.as_deref()))
+            .map_err(|e| format!("{}: {}", file.display(), e).into())
+            .and_then(|parsed| {
+                import_bookmarks(
+                    &mut bookmark_repo,
+                    &path_ops,
+                    &clock,
+                    &parsed.entries,
+                    ImportOptions {
+                        with_scores,
+                        keep_missing: keep_missing || from == Source::Json,
+                    },
+                )
+                .map(|summary| {
+                    print_skipped_items(parsed.skipped.iter().chain(&summary.skipped));
+                    println!("imported {} bookmarks", summary.imported.len());
+                })
+            }),
+        Some(Commands::Export { to, filter }) => list_bookmarks(
+            &mut bookmark_repo,
+            &clock,
+            SortOrder::Added,
+            tag_filter(filter).as_ref(),
+        )
+        .and_then(|bookmarks| {
+            // JSONはbmで読み込み直せるよう、`~/...`や`$NAME/...`のパスをそのまま書き出す
+            let bookmarks: Vec<_> = bookmarks
+                .into_iter()
+                .map(|b| show_bookmark(&path_ops, b, to != export::Target::Json))
+                .collect();
+            let mut out = std::io::stdout().lock();
+            let skipped = export::export(&mut out, to, &bookmarks, clock.now())?;
+            print_skipped_items(&skipped);
+            Ok(())
+        }),
+        Some(Commands::Profile { command }) => match command {
+            ProfileCommands::List => list_profiles(&mut bookmark_repo).map(|profiles| {
+                for (profile, is_default) in profiles {
+                    let mark = if is_default { "*" } else { " " };
+                    println!("{} {}", mark, profile);
+                }
+            }),
+            ProfileCommands::Create { name } => create_profile(&mut bookmark_repo, &name),
+            ProfileCommands::Delete { name } => delete_profile(&mut bookmark_repo, &name),
+            ProfileCommands::Rename { from, to } => rename_profile(&mut bookmark_repo, &from, &to),
+            ProfileCommands::Default { name } => {
+                default_profile(&mut bookmark_repo, name.as_deref()).map(|profile| {
+                    if name.is_none() {
+                        println!("{}", profile);
+                    }
+                })
             }
-            Err(e) => Err(e),
         },
+        Some(Commands::Tag { command }) => match command {
+            TagCommands::Add { bookmark, tags } => {
+                add_tags(&mut bookmark_repo, &path_ops, &bookmark, tags)
+                    .map(|bookmark| println!("{}", bookmark))
+            }
+            TagCommands::Remove { bookmark, tags } => {
+                remove_tags(&mut bookmark_repo, &path_ops, &bookmark, tags)
+                    .map(|bookmark| println!("{}", bookmark))
+            }
+            TagCommands::Set { bookmark, tags } => {
+                set_tags(&mut bookmark_repo, &path_ops, &bookmark, tags)
+                    .map(|bookmark| println!("{}", bookmark))
+            }
+            TagCommands::Rename { old, new } => rename_tag(&mut bookmark_repo, &old, &new)
+                .map(|count| println!("renamed '{}' to '{}' on {} bookmarks", old, new, count)),
+            TagCommands::Merge { from, into } => merge_tags(&mut bookmark_repo, &from, &into)
+                .map(|count| println!("merged '{}' into '{}' on {} bookmarks", from, into, count)),
+        },
+        Some(Commands::Tags) => count_tags(&mut bookmark_repo).map(|counts| {
+            for (tag, count) in counts {
+                println!("{:>4} {}", count, tag);
+            }
+        }),
+        Some(Commands::Hosts { bookmark, hosts }) => {
+            set_hosts(&mut bookmark_repo, &path_ops, &bookmark, hosts).map(|bookmark| {
+                match bookmark.get_hosts() {
+                    [] => println!("{} (all hosts)", bookmark),
+                    hosts => println!("{} (hosts: {})", bookmark, hosts.join(", ")),
+                }
+                if !host.is_visible(&bookmark) {
+                    eprintln!("The bookmark is now hidden on this host");
+                }
+            })
+        }
+        Some(Commands::Undo) => undo_operation(&mut bookmark_repo)
+            .map(|operation| println!("undone: {}", describe_operation(&operation))),
+        Some(Commands::Redo) => redo_operation(&mut bookmark_repo)
+            .map(|operation| println!("redone: {}", describe_operation(&operation))),
+        Some(Commands::History { limit }) => {
+            list_history(&mut bookmark_repo, limit).map(|operations| {
+                let now = clock.now();
+                for (operation, undone) in operations {
+                    println!(
+                        "{:>4}  {:>10}  {}{}",
+                        operation.id,
+                        format_elapsed(now.saturating_sub(operation.time)),
+                        describe_operation(&operation),
+                        if undone { " (undone)" } else { "" }
+                    );
+                }
+            })
+        }
+        Some(Commands::Init {
+            shell,
+            cmd,
+            no_key_bindings,
+        }) => {
+            print!("{}", init::init_script(shell, &cmd, !no_key_bindings));
+            Ok(())
+        }
+        Some(Commands::Complete { .. }) => unreachable!("handled before opening the repository"),
         None => Ok(()),
     };
 
@@ -108,3 +937,219 @@ fn main() {
         std::process::exit(1);
     }
 }
+
+/// コマンドライン引数が不正な場合の終了コード (clapと同じ)
+const EXIT_USAGE: i32 = 2;
+/// `search`でクエリに一致するブックマークがない場合の終了コード
+const EXIT_NO_MATCH: i32 = 3;
+/// `search`でクエリに複数のブックマークが一致し、選択できない場合の終了コード
+const EXIT_AMBIGUOUS: i32 = 4;
+
+fn exit_with(code: i32, message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(code);
+}
+
+/// `--tag`と`--query`を1つの条件にまとめる。式が不正なら使い方の誤りとして終了する
+fn tag_filter(args: FilterArgs) -> Option<TagQuery> {
+    let query = args.tag_query.map(|query| {
+        TagQuery::parse(&query).unwrap_or_else(|e| exit_with(EXIT_USAGE, &e.to_string()))
+    });
+    match (TagQuery::all_of(&args.tags), query) {
+        (Some(tags), Some(query)) => Some(tags.and(query)),
+        (tags, query) => tags.or(query),
+    }
+}
+
+type Repository = JournaledRepository<BookmarkRepository<Box<dyn IBookmarkDao>>>;
+
+/// このホストの名前と、設定ファイルでこのホストに指定されたパスの書き換え
+fn host_view(config: &Config) -> HostView {
+    let hostname = host::hostname();
+    let rewrites = hostname
+        .as_deref()
+        .map(|hostname| config.host_rewrites(hostname))
+        .unwrap_or_default();
+    HostView::new(hostname).with_rewrites(rewrites)
+}
+
+/// コマンドが読み書きするブックマークを、どのホストから見た形にするか
+fn command_host(command: Option<&Commands>, host: &HostView) -> HostView {
+    match command {
+        // JSONへの書き出しはバックアップにも使うため、他のホストに限られたものも含め保存されたまま読む
+        Some(Commands::Export {
+            to: export::Target::Json,
+            ..
+        }) => HostView::default(),
+        _ => host.clone(),
+    }
+}
+
+/// 指定された種類の保存先を、`host`から見た形で開く
+fn open_repository(
+    store: StoreKind,
+    file: Option<&Path>,
+    env: &Env,
+    host: HostView,
+) -> Result<Repository, Box<dyn std::error::Error>> {
+    let location = location::store_location(file, store, env)?;
+    if let Some(legacy) = location.prepare()? {
+        eprintln!("moved {} to {}", legacy.display(), location.path.display());
+    }
+    let src = location.path;
+    let journal = Journal::for_store(&src);
+    let dao: Box<dyn IBookmarkDao> = match store {
+        StoreKind::Json => Box::new(BookmarkDao::new(src)),
+        StoreKind::Sqlite => Box::new(SqliteBookmarkDao::open(&src)?),
+    };
+    // 操作の記録には、どのコマンドによる変更かをコマンドラインのまま残す
+    let command = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+    Ok(JournaledRepository::new(
+        BookmarkRepository::new(dao).with_host(host),
+        journal,
+        command,
+        SystemClock::new().now(),
+    ))
+}
+
+/// 補完中のコマンドラインで選ばれた保存先とプロファイルの名前やタグを使って補完する。
+/// 保存先を開けなくても、サブコマンドやオプションは補完する
+fn print_completions(
+    index: Option<usize>,
+    mut args: Vec<OsString>,
+    store: StoreKind,
+    env: &Env,
+    host: HostView,
+) {
+    let index = index.unwrap_or(args.len().saturating_sub(1));
+    if args.is_empty() || index == 0 {
+        return;
+    }
+    if index >= args.len() {
+        args.resize(index + 1, OsString::new());
+    }
+    let options = GlobalOptions::parse(&args[..index]);
+    let candidates = open_repository(
+        options.store.unwrap_or(store),
+        options.file.as_deref(),
+        env,
+        host,
+    )
+    .and_then(|mut repo| {
+        if let Some(profile) = &options.profile {
+            repo.select_profile(profile)?;
+        }
+        Ok(Candidates {
+            names: bookmark_names(&mut repo)?,
+            tags: count_tags(&mut repo)?
+                .into_iter()
+                .map(|(tag, _)| tag)
+                .collect(),
+            profiles: repo.find_profiles()?,
+        })
+    })
+    .unwrap_or_default();
+    let current_dir = std::env::current_dir().ok();
+    match complete::complete(
+        candidates.attach(Cli::command()),
+        args,
+        index,
+        current_dir.as_deref(),
+    ) {
+        Ok(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        Err(e) => exit_with(1, &e.to_string()),
+    }
+}
+
+/// 経過時間を`5m ago`のように表す
+fn format_elapsed(secs: u64) -> String {
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// 記録された操作を`prune (+0 ~0 -2)`のように表す
+fn describe_operation(operation: &journal::Operation) -> String {
+    format!("{} ({})", operation.command, operation.summary())
+}
+
+/// マウントされていないため残したブックマークを知らせる
+fn print_skipped(skipped: &[(bookmark::Bookmark, PathBuf)]) {
+    for (bookmark, mount_point) in skipped {
+        eprintln!(
+            "skipped: {} ({} is not mounted; use --force to delete)",
+            bookmark,
+            mount_point.display()
+        );
+    }
+}
+
+/// 取り込みや書き出しで飛ばした項目を理由とともに表示する
+fn print_skipped_items<'a>(skipped: impl IntoIterator<Item = &'a import::Skipped>) {
+    for skipped in skipped {
+        eprintln!("skipped: {} ({})", skipped.item, skipped.reason);
+    }
+}
+
+/// `expanded`なら保存されたパスを展開したブックマークにする (展開できなければそのまま)
+fn show_bookmark(path_ops: &dyn PathOps, bookmark: Bookmark, expanded: bool) -> Bookmark {
+    if expanded {
+        expand_bookmark(path_ops, &bookmark).unwrap_or(bookmark)
+    } else {
+        bookmark
+    }
+}
+
+/// ブックマークの展開したパスを出力する。`dir`ならファイルのブックマークはそれがあるディレクトリを出力する
+fn print_bookmark(
+    path_ops: &dyn PathOps,
+    bookmark: &Bookmark,
+    dir: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let bookmark = expand_bookmark(path_ops, bookmark)?;
+    if dir {
+        print_path(bookmark.get_dir())
+    } else {
+        print_path(bookmark.get_path())
+    }
+}
+
+/// パスをそのまま (UTF-8として不正なバイトも含めて) 標準出力に書き出す
+fn print_path(path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(path.as_os_str().as_encoded_bytes())?;
+    stdout.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    // 引数の定義に矛盾がないこと
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    // JSONへの書き出しだけは、このホストから見た形にせず保存されたまま読むこと
+    fn test_command_host() {
+        let host = HostView::new(Some("macbook".to_string()));
+        let command_host = |args: &[&str]| {
+            let cli = Cli::parse_from(args);
+            command_host(cli.command.as_ref(), &host)
+        };
+        assert!(command_host(&["bm", "export", "--to", "json"]).is_identity());
+        assert!(!command_host(&["bm", "export", "--to", "csv"]).is_identity());
+        assert!(!command_host(&["bm", "list"]).is_identity());
+    }
+}
@@ -0,0 +1,93 @@
+use crate::bookmark::Bookmark;
+use std::io::{Error, ErrorKind};
+
+/// テキスト形式のヘッダ行。ヘッダがないファイルは最初期の形式として扱う
+const TEXT_HEADER_PREFIX: &str = "# bookmark-cli format ";
+const TEXT_FORMAT_VERSION: u32 = 2;
+
+/// JSON形式に移行する前のテキスト形式のファイルかどうか
+pub fn is_plain_text(content: &str) -> bool {
+    let content = content.trim_start();
+    !content.is_empty() && !content.starts_with('{')
+}
+
+/// テキスト形式のファイルの内容をブックマークに変換する
+pub fn parse_plain_text(content: &str) -> Result<Vec<Bookmark>, Error> {
+    let mut lines = content.lines().enumerate().peekable();
+    let version = match lines.peek() {
+        Some((_, line)) => match line.strip_prefix(TEXT_HEADER_PREFIX) {
+            Some(v) => v.trim().parse::<u32>().map_err(|_| {
+                Error::new(ErrorKind::InvalidData, format!("invalid header: {}", line))
+            })?,
+            None => 1,
+        },
+        None => 1,
+    };
+    if version > TEXT_FORMAT_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported format version: {}", version),
+        ));
+    }
+
+    let mut bookmarks = Vec::new();
+    for (i, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let bookmark = if version == 1 {
+            Bookmark::parse_legacy(line)
+        } else if line.starts_with('#') {
+            // ヘッダやコメント行
+            continue;
+        } else {
+            Bookmark::parse(line)
+                .map_err(|e| Error::new(e.kind(), format!("line {}: {}", i + 1, e)))?
+        };
+        bookmarks.push(bookmark);
+    }
+    Ok(bookmarks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    // 旧形式のファイルが読み込めること
+    #[case("/path/a #tag1\n\n/path/b\n", vec![("/path/a", vec!["tag1"]), ("/path/b", vec![])])]
+    // エスケープ付きの形式のファイルが読み込めること
+    #[case(
+        "# bookmark-cli format 2\n/My\\ Projects #a\\ b\n/path/b\n",
+        vec![("/My Projects", vec!["a b"]), ("/path/b", vec![])]
+    )]
+    // 空のファイル
+    #[case("", vec![])]
+    fn test_parse_plain_text(#[case] input: &str, #[case] expected: Vec<(&str, Vec<&str>)>) {
+        let expected: Vec<Bookmark> = expected
+            .into_iter()
+            .map(|(p, tags)| Bookmark::new(p, tags.into_iter().map(String::from).collect()))
+            .collect();
+        assert_eq!(parse_plain_text(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    // 未対応の新しい形式
+    #[case("# bookmark-cli format 3\n/path\n")]
+    // 不正な行
+    #[case("# bookmark-cli format 2\n/path\\q\n")]
+    fn test_parse_plain_text_invalid(#[case] input: &str) {
+        assert!(parse_plain_text(input).is_err());
+    }
+
+    #[rstest]
+    #[case("/path/a #tag\n", true)]
+    #[case("# bookmark-cli format 2\n", true)]
+    #[case("{\"version\": 1, \"bookmarks\": []}", false)]
+    #[case("\n  {}", false)]
+    #[case("", false)]
+    fn test_is_plain_text(#[case] content: &str, #[case] expected: bool) {
+        assert_eq!(is_plain_text(content), expected);
+    }
+}
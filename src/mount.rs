@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+pub trait MountTable {
+    /// パスがマウントされていないマウントポイントの下にあれば、そのマウントポイントを返す
+    fn unmounted_mount_point(&self, path: &Path) -> Option<PathBuf>;
+}
+
+/// リムーバブルメディアがマウントされるディレクトリ。この直下 (`/media`と`/run/media`は
+/// ユーザー名の下) がマウントポイントとみなされる
+const MEDIA_ROOTS: &[(&str, usize)] = &[
+    ("/mnt", 1),
+    ("/media", 2),
+    ("/run/media", 2),
+    ("/Volumes", 1),
+];
+
+/// `/etc/fstab`と現在のマウントの一覧から判断する
+pub struct SystemMountTable {
+    /// `/etc/fstab`に書かれたマウントポイント
+    configured: Vec<PathBuf>,
+    /// 現在マウントされているマウントポイント。一覧が読めなければ`None`
+    mounted: Option<Vec<PathBuf>>,
+}
+
+impl SystemMountTable {
+    pub fn load() -> Self {
+        let configured = fs::read_to_string("/etc/fstab")
+            .map(|content| parse_mount_points(&content, 1))
+            .unwrap_or_default();
+        let mounted = fs::read_to_string("/proc/self/mounts")
+            .ok()
+            .map(|content| parse_mount_points(&content, 1));
+        Self {
+            configured,
+            mounted,
+        }
+    }
+
+    fn is_mounted(&self, mount_point: &Path) -> bool {
+        match &self.mounted {
+            Some(mounted) => mounted.iter().any(|m| m == mount_point),
+            // 一覧がなければ (macOSなど)、マウントされていないマウントポイントは存在しないものとする
+            None => mount_point.exists(),
+        }
+    }
+}
+
+impl MountTable for SystemMountTable {
+    fn unmounted_mount_point(&self, path: &Path) -> Option<PathBuf> {
+        let configured = self
+            .configured
+            .iter()
+            .filter(|m| m.parent().is_some() && path.starts_with(m))
+            .cloned();
+        let media = MEDIA_ROOTS.iter().filter_map(|(root, depth)| {
+            let rest = path.strip_prefix(root).ok()?;
+            let components: Vec<_> = rest.components().take(*depth).collect();
+            (components.len() == *depth).then(|| {
+                components
+                    .iter()
+                    .fold(PathBuf::from(root), |acc, c| acc.join(c))
+            })
+        });
+        configured
+            .chain(media)
+            .find(|mount_point| !self.is_mounted(mount_point))
+    }
+}
+
+/// fstabやmountsの形式からマウントポイントの列を取り出す (空白は`\040`とエスケープされている)
+fn parse_mount_points(content: &str, field: usize) -> Vec<PathBuf> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().nth(field))
+        .filter(|mount_point| mount_point.starts_with('/'))
+        .map(|mount_point| PathBuf::from(unescape_octal(mount_point)))
+        .collect()
+}
+
+fn unescape_octal(s: &str) -> String {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('\\') {
+        result.push_str(&rest[..i]);
+        let code = rest
+            .get(i + 1..i + 4)
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) => {
+                result.push(code as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                result.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn table(configured: &[&str], mounted: &[&str]) -> SystemMountTable {
+        SystemMountTable {
+            configured: configured.iter().map(PathBuf::from).collect(),
+            mounted: Some(mounted.iter().map(PathBuf::from).collect()),
+        }
+    }
+
+    #[test]
+    fn test_parse_mount_points() {
+        let fstab = "# comment\n\
+                     UUID=1234 / ext4 defaults 0 1\n\
+                     \n\
+                     nas:/share /srv/my\\040share nfs defaults 0 0\n\
+                     /swapfile none swap sw 0 0\n";
+        assert_eq!(
+            parse_mount_points(fstab, 1),
+            vec![PathBuf::from("/"), PathBuf::from("/srv/my share")]
+        );
+    }
+
+    #[rstest]
+    // fstabにあってマウントされていない
+    #[case("/srv/nas/project", Some("/srv/nas"))]
+    // ルートは常にマウントされているものとして扱う
+    #[case("/home/me/project", None)]
+    // リムーバブルメディアの場所
+    #[case("/media/me/usb/project", Some("/media/me/usb"))]
+    #[case("/Volumes/Backup/project", Some("/Volumes/Backup"))]
+    #[case("/mnt/data/project", None)]
+    #[case("/mnt", None)]
+    fn test_unmounted_mount_point(#[case] path: &str, #[case] expected: Option<&str>) {
+        let table = table(&["/", "/srv/nas"], &["/", "/mnt/data"]);
+        assert_eq!(
+            table.unmounted_mount_point(Path::new(path)),
+            expected.map(PathBuf::from)
+        );
+    }
+}
@@ -1,33 +1,369 @@
-use std::{io::Error, path::PathBuf};
+use std::{
+    ffi::OsString,
+    io::{Error, ErrorKind},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::bookmark::{bytes_to_path, Fingerprint};
 
 #[cfg(test)]
 use mockall::automock;
 
 #[cfg_attr(test, automock)]
 pub trait PathOps {
-    fn exists(&self, path: &str) -> bool;
-    fn is_dir(&self, path: &str) -> bool;
-    fn get_current_dir(&self) -> Result<String, std::io::Error>;
+    fn exists(&self, path: &Path) -> bool;
+    fn is_dir(&self, path: &Path) -> bool;
+    fn get_current_dir(&self) -> Result<PathBuf, std::io::Error>;
+    /// ディレクトリが移動された後に探し出すための特徴を読み取る
+    fn fingerprint(&self, path: &Path) -> Option<Fingerprint>;
+    /// 同じ場所を指すパスが同じになるよう、ブックマークとして保存する形に直す
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, std::io::Error>;
+    /// `~/src`や`$WORKSPACE/api`のように保存された持ち運べるパスを、このマシンでのパスにする。
+    /// それ以外のパスはそのまま返す
+    fn expand(&self, path: &Path) -> Result<PathBuf, std::io::Error>;
+    /// 絶対パスを、ホームディレクトリか設定されたルートからの持ち運べるパスにする
+    fn contract(&self, path: &Path) -> Option<PathBuf>;
+}
+
+/// 持ち運べる形 (`~`か`$`で始まる) で保存されたパスか
+pub fn is_portable(path: &Path) -> bool {
+    matches!(
+        path.as_os_str().as_encoded_bytes().first(),
+        Some(b'~' | b'$')
+    )
 }
 
-pub struct DefaultPathOps {}
+pub struct DefaultPathOps {
+    /// シンボリックリンクを解決するか
+    resolve_symlinks: bool,
+    /// `~`や`$VAR`を展開するか
+    expand_variables: bool,
+    /// `$NAME`で参照できるルートの名前とパス (環境変数より優先する)
+    roots: Vec<(String, PathBuf)>,
+}
 
 impl DefaultPathOps {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(resolve_symlinks: bool, expand_variables: bool) -> Self {
+        Self {
+            resolve_symlinks,
+            expand_variables,
+            roots: Vec::new(),
+        }
+    }
+
+    /// 持ち運べるパスの基準にするルートを加える
+    pub fn with_roots(mut self, roots: Vec<(String, PathBuf)>) -> Self {
+        self.roots = roots;
+        self
+    }
+
+    /// 設定されたルートか環境変数の値 (空の値は未設定として扱う)
+    fn var(&self, name: &str) -> Option<OsString> {
+        self.roots
+            .iter()
+            .find(|(root, _)| root == name)
+            .map(|(_, path)| path.clone().into_os_string())
+            .or_else(|| std::env::var_os(name).filter(|v| !v.is_empty()))
     }
 }
 
 impl PathOps for DefaultPathOps {
-    fn exists(&self, path: &str) -> bool {
-        PathBuf::from(path).exists()
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn get_current_dir(&self) -> Result<PathBuf, Error> {
+        std::env::current_dir()
+    }
+
+    fn fingerprint(&self, path: &Path) -> Option<Fingerprint> {
+        crate::relocate::fingerprint(path)
     }
 
-    fn is_dir(&self, path: &str) -> bool {
-        PathBuf::from(path).is_dir()
+    /// 展開して絶対パスにし、存在するパスならシンボリックリンクを解決する。
+    /// 存在しないパスは`.`や`..`を取り除くだけにする
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, Error> {
+        let expanded = if self.expand_variables {
+            expand(path, |name| self.var(name))?
+        } else {
+            path.to_path_buf()
+        };
+        let absolute = if expanded.is_absolute() {
+            expanded
+        } else {
+            self.get_current_dir()?.join(expanded)
+        };
+        if self.resolve_symlinks {
+            if let Ok(resolved) = std::fs::canonicalize(&absolute) {
+                return Ok(resolved);
+            }
+        }
+        Ok(normalize(&absolute))
     }
 
-    fn get_current_dir(&self) -> Result<String, Error> {
-        Ok(std::env::current_dir()?.to_string_lossy().into_owned())
+    fn expand(&self, path: &Path) -> Result<PathBuf, Error> {
+        expand_portable(path, |name| self.var(name))
+    }
+
+    fn contract(&self, path: &Path) -> Option<PathBuf> {
+        let home = self
+            .var("HOME")
+            .map(|home| ("~".to_string(), PathBuf::from(home)));
+        let roots = self
+            .roots
+            .iter()
+            .map(|(name, root)| (format!("${}", name), root.clone()))
+            .chain(home);
+        // シンボリックリンクを解決したパスとも比べる
+        let roots: Vec<(String, PathBuf)> = roots
+            .flat_map(|(prefix, root)| {
+                let resolved = std::fs::canonicalize(&root).ok();
+                std::iter::once((prefix.clone(), root))
+                    .chain(resolved.map(|resolved| (prefix, resolved)))
+            })
+            .collect();
+        contract(path, &roots)
+    }
+}
+
+/// 先頭の`~`や`$NAME`、`${NAME}`だけを展開する。展開できなければエラーにする
+fn expand_portable(path: &Path, var: impl Fn(&str) -> Option<OsString>) -> Result<PathBuf, Error> {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    let end = bytes.iter().position(|&b| b == b'/').unwrap_or(bytes.len());
+    let (head, rest) = bytes.split_at(end);
+    let name = match head {
+        b"~" => "HOME",
+        [b'$', b'{', name @ .., b'}'] | [b'$', name @ ..] => {
+            std::str::from_utf8(name).map_err(|_| invalid_root(path))?
+        }
+        // `~user`には対応しない
+        [b'~', ..] => return Err(invalid_root(path)),
+        _ => return Ok(path.to_path_buf()),
+    };
+    if !is_variable_name(name) {
+        return Err(invalid_root(path));
+    }
+    let value = var(name).ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("${} is not set (used by {})", name, path.display()),
+        )
+    })?;
+    let mut expanded = value.into_encoded_bytes();
+    expanded.extend(rest);
+    bytes_to_path(expanded)
+}
+
+fn invalid_root(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid root in portable path: {}", path.display()),
+    )
+}
+
+fn is_variable_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// 最も長く一致するルートからのパスにする (同じ長さなら先のものを使う)
+fn contract(path: &Path, roots: &[(String, PathBuf)]) -> Option<PathBuf> {
+    let (_, prefix, rest) = roots
+        .iter()
+        .filter(|(_, root)| root.is_absolute())
+        .filter_map(|(prefix, root)| {
+            let rest = path.strip_prefix(root).ok()?;
+            Some((root.components().count(), prefix, rest))
+        })
+        .min_by_key(|(len, _, _)| std::cmp::Reverse(*len))?;
+    let mut portable = PathBuf::from(prefix);
+    if !rest.as_os_str().is_empty() {
+        portable.push(rest);
+    }
+    Some(portable)
+}
+
+/// 先頭の`~`をホームディレクトリに、`$VAR`や`${VAR}`を環境変数の値に置き換える
+///
+/// 設定されていない環境変数はそのまま残す (`$`を含む名前のディレクトリもあるため)
+fn expand(path: &Path, var: impl Fn(&str) -> Option<OsString>) -> Result<PathBuf, Error> {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    let mut expanded = Vec::with_capacity(bytes.len());
+    let mut rest = bytes;
+    if rest == b"~" || rest.starts_with(b"~/") {
+        let home = var("HOME").ok_or_else(|| Error::new(ErrorKind::NotFound, "HOME is not set"))?;
+        expanded.extend(home.as_encoded_bytes());
+        rest = &rest[1..];
+    }
+    while let Some(i) = rest.iter().position(|&b| b == b'$') {
+        expanded.extend(&rest[..i]);
+        rest = &rest[i..];
+        let (name, len) = match rest.get(1) {
+            Some(b'{') => match rest.iter().position(|&b| b == b'}') {
+                Some(end) => (&rest[2..end], end + 1),
+                None => (&rest[..0], 1),
+            },
+            _ => {
+                let end = rest[1..]
+                    .iter()
+                    .position(|&b| !(b.is_ascii_alphanumeric() || b == b'_'))
+                    .map_or(rest.len(), |n| n + 1);
+                (&rest[1..end], end)
+            }
+        };
+        let value = std::str::from_utf8(name)
+            .ok()
+            .filter(|name| name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_'))
+            .and_then(&var);
+        match value {
+            Some(value) => expanded.extend(value.as_encoded_bytes()),
+            None => expanded.extend(&rest[..len]),
+        }
+        rest = &rest[len..];
+    }
+    expanded.extend(rest);
+    bytes_to_path(expanded)
+}
+
+/// ファイルシステムを見ずに`.`や`..`、重複した`/`、末尾の`/`を取り除く
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(component),
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("/srv/app", "/srv/app")]
+    #[case("/srv/app/", "/srv/app")]
+    #[case("/srv/./app", "/srv/app")]
+    #[case("/srv//app/../app/.", "/srv/app")]
+    // ルートより上には出ない
+    #[case("/../srv", "/srv")]
+    #[case("/", "/")]
+    fn test_normalize(#[case] path: &str, #[case] expected: &str) {
+        assert_eq!(normalize(Path::new(path)), PathBuf::from(expected));
+    }
+
+    #[rstest]
+    #[case("~", "/home/me")]
+    #[case("~/src", "/home/me/src")]
+    // 先頭以外の`~`や`~user`は展開しない
+    #[case("/tmp/~", "/tmp/~")]
+    #[case("~other/src", "~other/src")]
+    #[case("$PROJECTS/app", "/work/projects/app")]
+    #[case("${PROJECTS}_old/$HOME", "/work/projects_old//home/me")]
+    // 設定されていない変数や変数名でないものはそのまま残す
+    #[case("/mnt/c/$RECYCLE.BIN", "/mnt/c/$RECYCLE.BIN")]
+    #[case("/price/$5/${", "/price/$5/${")]
+    fn test_expand(#[case] path: &str, #[case] expected: &str) {
+        let var = |name: &str| match name {
+            "HOME" => Some(OsString::from("/home/me")),
+            "PROJECTS" => Some(OsString::from("/work/projects")),
+            _ => None,
+        };
+        assert_eq!(
+            expand(Path::new(path), var).unwrap(),
+            PathBuf::from(expected)
+        );
+    }
+
+    #[test]
+    // HOMEが設定されていなければ`~`は展開できないこと
+    fn test_expand_without_home() {
+        assert!(expand(Path::new("~/src"), |_| None).is_err());
+    }
+
+    fn portable_var(name: &str) -> Option<OsString> {
+        match name {
+            "HOME" => Some(OsString::from("/home/me")),
+            "WORKSPACE" => Some(OsString::from("/work")),
+            _ => None,
+        }
+    }
+
+    #[rstest]
+    #[case("~", "/home/me")]
+    #[case("~/src/api", "/home/me/src/api")]
+    #[case("$WORKSPACE/api", "/work/api")]
+    #[case("${WORKSPACE}/api", "/work/api")]
+    // 持ち運べるパスでなければそのまま
+    #[case("/srv/$WORKSPACE", "/srv/$WORKSPACE")]
+    fn test_expand_portable(#[case] path: &str, #[case] expected: &str) {
+        assert_eq!(
+            expand_portable(Path::new(path), portable_var).unwrap(),
+            PathBuf::from(expected)
+        );
+    }
+
+    #[rstest]
+    // 設定されていないルートや、ルートの名前として使えないものは展開できない
+    #[case("$UNSET/api")]
+    #[case("$WORK-SPACE/api")]
+    #[case("~other/api")]
+    fn test_expand_portable_error(#[case] path: &str) {
+        assert!(expand_portable(Path::new(path), portable_var).is_err());
+    }
+
+    #[rstest]
+    #[case("/home/me/src/api", Some("~/src/api"))]
+    #[case("/home/me", Some("~"))]
+    // より長く一致するルートが使われる
+    #[case("/home/me/work/api", Some("$WORKSPACE/api"))]
+    #[case("/home/meow", None)]
+    #[case("/srv/app", None)]
+    fn test_contract(#[case] path: &str, #[case] expected: Option<&str>) {
+        let roots = vec![
+            ("~".to_string(), PathBuf::from("/home/me")),
+            ("$WORKSPACE".to_string(), PathBuf::from("/home/me/work")),
+        ];
+        assert_eq!(
+            contract(Path::new(path), &roots),
+            expected.map(PathBuf::from)
+        );
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case(true, "real")]
+    #[case(false, "link")]
+    // 設定に従ってシンボリックリンクを解決し、存在しないパスは正規化だけすること
+    fn test_canonicalize(#[case] resolve_symlinks: bool, #[case] expected: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        std::fs::create_dir(root.join("real")).unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("link")).unwrap();
+
+        let path_ops = DefaultPathOps::new(resolve_symlinks, true);
+        assert_eq!(
+            path_ops.canonicalize(&root.join("./link/")).unwrap(),
+            root.join(expected)
+        );
+        assert_eq!(
+            path_ops
+                .canonicalize(&root.join("missing/../gone/"))
+                .unwrap(),
+            root.join("gone")
+        );
     }
 }
@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bookmark::{Bookmark, Fingerprint, Kind};
+
+#[cfg(test)]
+use mockall::automock;
+
+/// 移動先の候補とみなした理由 (信頼できる順)
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Reason {
+    /// iノードが同じ
+    Inode,
+    /// gitのリモートが同じ
+    GitRemote,
+    /// 名前が同じ
+    Name,
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let s = match self {
+            Reason::Inode => "same inode",
+            Reason::GitRemote => "same git remote",
+            Reason::Name => "same name",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// 壊れたブックマークの移動先の候補
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub reason: Reason,
+}
+
+#[cfg_attr(test, automock)]
+pub trait Relocator {
+    /// 壊れたブックマークの移動先の候補を、信頼できる順に返す
+    fn find_candidates(&self, bookmark: &Bookmark) -> Vec<Candidate>;
+}
+
+/// 設定されたディレクトリの下を探す
+pub struct DirScanner {
+    roots: Vec<PathBuf>,
+    max_depth: usize,
+}
+
+impl DirScanner {
+    pub fn new(roots: Vec<PathBuf>, max_depth: usize) -> Self {
+        Self { roots, max_depth }
+    }
+}
+
+impl Relocator for DirScanner {
+    /// 隠しディレクトリとシンボリックリンクはたどらない。gitリポジトリの中も
+    /// プロジェクトの一部とみなしてたどらない。ファイルのブックマークは、たどったディレクトリの
+    /// 直下にあるファイル (隠しファイルも含む) から探す
+    fn find_candidates(&self, bookmark: &Bookmark) -> Vec<Candidate> {
+        let name = bookmark.get_path().file_name();
+        let fingerprint = bookmark.get_fingerprint();
+        let is_file = bookmark.get_kind() == Kind::File;
+        let mut candidates = Vec::new();
+        let mut push = |path: &Path, reason: Option<Reason>| {
+            if let Some(reason) = reason {
+                if !candidates.iter().any(|c: &Candidate| c.path == path) {
+                    candidates.push(Candidate {
+                        path: path.to_path_buf(),
+                        reason,
+                    });
+                }
+            }
+        };
+        let mut queue: VecDeque<(PathBuf, usize)> =
+            self.roots.iter().map(|root| (root.clone(), 0)).collect();
+        while let Some((dir, depth)) = queue.pop_front() {
+            let is_repo = dir.join(".git").exists();
+            let descend = !is_repo && depth < self.max_depth;
+            if !is_file {
+                push(&dir, match_reason(&dir, is_repo, fingerprint, name));
+                if !descend {
+                    continue;
+                }
+            }
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            let mut entries: Vec<(PathBuf, fs::FileType)> = entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| Some((entry.path(), entry.file_type().ok()?)))
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut children = Vec::new();
+            for (path, file_type) in entries {
+                if file_type.is_file() && is_file {
+                    push(&path, match_reason(&path, false, fingerprint, name));
+                } else if file_type.is_dir()
+                    && descend
+                    && !path
+                        .file_name()
+                        .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+                {
+                    children.push(path);
+                }
+            }
+            queue.extend(children.into_iter().map(|child| (child, depth + 1)));
+        }
+        candidates.sort_by_key(|c| c.reason);
+        candidates
+    }
+}
+
+/// 移動先の候補とみなす理由 (gitのリモートはリポジトリのディレクトリでだけ比べる)
+fn match_reason(
+    path: &Path,
+    is_repo: bool,
+    fingerprint: Option<&Fingerprint>,
+    name: Option<&OsStr>,
+) -> Option<Reason> {
+    match fingerprint {
+        Some(expected) if expected.inode.is_some() && inode(path) == expected.inode => {
+            Some(Reason::Inode)
+        }
+        Some(expected)
+            if is_repo
+                && expected.git_remote.is_some()
+                && read_git_remote(path) == expected.git_remote =>
+        {
+            Some(Reason::GitRemote)
+        }
+        _ if name.is_some() && path.file_name() == name => Some(Reason::Name),
+        _ => None,
+    }
+}
+
+/// ディレクトリやファイルの特徴を読み取る。存在しなければ`None`
+pub fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(Fingerprint {
+        inode: inode(path),
+        git_remote: metadata.is_dir().then(|| read_git_remote(path)).flatten(),
+    })
+}
+
+#[cfg(unix)]
+fn inode(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// `.git/config`からリモートのURLを読む (`origin`があればそれを優先する)
+fn read_git_remote(dir: &Path) -> Option<String> {
+    let config = fs::read_to_string(dir.join(".git").join("config")).ok()?;
+    let mut remote: Option<&str> = None;
+    let mut first = None;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            remote = line
+                .strip_prefix("[remote \"")
+                .and_then(|rest| rest.strip_suffix("\"]"));
+            continue;
+        }
+        let Some(name) = remote else {
+            continue;
+        };
+        let url = line
+            .split_once('=')
+            .filter(|(key, _)| key.trim() == "url")
+            .map(|(_, value)| value.trim().to_string());
+        if let Some(url) = url {
+            if name == "origin" {
+                return Some(url);
+            }
+            first.get_or_insert(url);
+        }
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mkdirs(root: &Path, dirs: &[&str]) {
+        for dir in dirs {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+    }
+
+    fn git_repo(dir: &Path, remote: &str) {
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(
+            dir.join(".git").join("config"),
+            format!(
+                "[core]\n\tbare = false\n[remote \"upstream\"]\n\turl = other\n\
+                 [remote \"origin\"]\n\turl = {}\n",
+                remote
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    // 同じ名前のディレクトリが候補になり、隠しディレクトリや深すぎる場所は探さないこと
+    fn test_find_by_name() {
+        let root = tempfile::tempdir().unwrap();
+        mkdirs(
+            root.path(),
+            &["a/api", "b/c/api", ".hidden/api", "b/c/d/e/api", "web"],
+        );
+        let scanner = DirScanner::new(vec![root.path().to_path_buf()], 3);
+        let bookmark = Bookmark::new("/old/api", vec![]);
+
+        let paths: Vec<PathBuf> = scanner
+            .find_candidates(&bookmark)
+            .into_iter()
+            .map(|c| c.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec![root.path().join("a/api"), root.path().join("b/c/api")]
+        );
+    }
+
+    #[test]
+    // iノードやgitのリモートが一致するディレクトリが名前の一致より優先されること
+    fn test_find_by_fingerprint() {
+        let root = tempfile::tempdir().unwrap();
+        mkdirs(root.path(), &["api", "renamed", "clone"]);
+        git_repo(&root.path().join("clone"), "git@example.com:me/api.git");
+        let moved = fingerprint(&root.path().join("renamed")).unwrap();
+        let bookmark = Bookmark::new("/old/api", vec![]).with_fingerprint(Some(Fingerprint {
+            inode: moved.inode,
+            git_remote: Some("git@example.com:me/api.git".to_string()),
+        }));
+        let scanner = DirScanner::new(vec![root.path().to_path_buf()], 1);
+
+        let candidates = scanner.find_candidates(&bookmark);
+        let reasons: Vec<(PathBuf, Reason)> =
+            candidates.into_iter().map(|c| (c.path, c.reason)).collect();
+        let mut expected = vec![
+            (root.path().join("clone"), Reason::GitRemote),
+            (root.path().join("api"), Reason::Name),
+        ];
+        if cfg!(unix) {
+            expected.insert(0, (root.path().join("renamed"), Reason::Inode));
+        }
+        assert_eq!(reasons, expected);
+    }
+
+    #[test]
+    // ファイルのブックマークは、iノードか名前が一致するファイルが候補になること
+    fn test_find_file() {
+        let root = tempfile::tempdir().unwrap();
+        mkdirs(root.path(), &["a", "b/notes.md", "repo"]);
+        git_repo(&root.path().join("repo"), "git@example.com:me/repo.git");
+        fs::write(root.path().join("a/renamed.md"), "").unwrap();
+        fs::write(root.path().join("repo/notes.md"), "").unwrap();
+        let moved = fingerprint(&root.path().join("a/renamed.md")).unwrap();
+        assert_eq!(moved.git_remote, None);
+        let bookmark = Bookmark::new("/old/notes.md", vec![])
+            .with_kind(Kind::File)
+            .with_fingerprint(Some(moved));
+        let scanner = DirScanner::new(vec![root.path().to_path_buf()], 2);
+
+        let reasons: Vec<(PathBuf, Reason)> = scanner
+            .find_candidates(&bookmark)
+            .into_iter()
+            .map(|c| (c.path, c.reason))
+            .collect();
+        let mut expected = vec![(root.path().join("repo/notes.md"), Reason::Name)];
+        if cfg!(unix) {
+            expected.insert(0, (root.path().join("a/renamed.md"), Reason::Inode));
+        }
+        assert_eq!(reasons, expected);
+    }
+
+    #[test]
+    // originのURLが優先され、なければ最初のリモートのURLを使うこと
+    fn test_read_git_remote() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(read_git_remote(dir.path()), None);
+        git_repo(dir.path(), "https://example.com/repo.git");
+        assert_eq!(
+            read_git_remote(dir.path()),
+            Some("https://example.com/repo.git".to_string())
+        );
+    }
+}
@@ -1,68 +1,367 @@
 use crate::bookmark::Bookmark;
-use crate::dao::IBookmarkDao;
-use std::io::Error;
+use crate::dao::{replace_or_push, IBookmarkDao};
+use crate::host::HostView;
+use crate::tag_query::TagQuery;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
 
 pub trait IBookmarkRepository {
     /// ブックマークを保存する
     fn save(&mut self, bookmark: &Bookmark) -> Result<(), Error>;
-    /// ブックマークを削除する
-    fn delete(&mut self, path: &str) -> Result<(), Error>;
+    /// ブックマークをまとめて削除する (存在しないパスは無視する)
+    fn delete(&mut self, paths: &[PathBuf]) -> Result<(), Error>;
     /// 全てのブックマークを取得する
     fn find_all(&mut self) -> Result<Vec<Bookmark>, Error>;
+    /// 全てのブックマークを1つの操作として書き換える (`f`が失敗したら何も変更しない)
+    fn update_all(
+        &mut self,
+        f: &mut dyn FnMut(&mut Vec<Bookmark>) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+    /// タグの条件に合うブックマークを取得する
+    fn find_by_tags(&mut self, query: &TagQuery) -> Result<Vec<Bookmark>, Error>;
+    /// タグごとに、そのタグを持つブックマークの数を取得する (順序は問わない)
+    fn count_tags(&mut self) -> Result<Vec<(String, usize)>, Error>;
+    /// プロファイルのブックマークを、このホストから見た形にせず保存されたまま取得する
+    fn find_stored(&mut self, profile: &str) -> Result<Vec<Bookmark>, Error>;
+    /// プロファイルのブックマークを、`find_stored`で取得したものでそのまま置き換える
+    fn replace_stored(&mut self, profile: &str, bookmarks: &[Bookmark]) -> Result<(), Error>;
+    /// 操作対象のプロファイルを切り替える
+    fn select_profile(&mut self, name: &str) -> Result<(), Error>;
+    /// 操作対象のプロファイルの名前を取得する
+    fn current_profile(&mut self) -> Result<String, Error>;
+    /// 全てのプロファイルの名前を名前順に取得する
+    fn find_profiles(&mut self) -> Result<Vec<String>, Error>;
+    /// 空のプロファイルを作成する
+    fn create_profile(&mut self, name: &str) -> Result<(), Error>;
+    /// プロファイルをブックマークごと削除する
+    fn delete_profile(&mut self, name: &str) -> Result<(), Error>;
+    /// プロファイルの名前を変更する
+    fn rename_profile(&mut self, from: &str, to: &str) -> Result<(), Error>;
+    /// デフォルトのプロファイルを取得する
+    fn get_default_profile(&mut self) -> Result<String, Error>;
+    /// デフォルトのプロファイルを設定する
+    fn set_default_profile(&mut self, name: &str) -> Result<(), Error>;
 }
 
 pub struct BookmarkRepository<B: IBookmarkDao> {
     dao: B,
+    /// 操作対象のプロファイル (未選択ならデフォルトのプロファイル)
+    profile: Option<String>,
+    /// 読み書きするブックマークをこのマシンから見た形にする
+    host: HostView,
 }
 
 impl<B: IBookmarkDao> BookmarkRepository<B> {
     pub fn new(dao: B) -> Self {
-        Self { dao }
+        Self {
+            dao,
+            profile: None,
+            host: HostView::default(),
+        }
+    }
+
+    /// 読み込むときにパスをこのマシンでのものに書き換え (書き込むときは元に戻し)、
+    /// 他のホストに限られたブックマークを隠す
+    pub fn with_host(mut self, host: HostView) -> Self {
+        self.host = host;
+        self
+    }
+
+    fn ensure_profile_exists(&mut self, name: &str) -> Result<(), Error> {
+        if self.dao.find_profiles()?.iter().any(|p| p == name) {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "profile not found: {} (create it with `bm profile create {}`)",
+                    name, name
+                ),
+            ))
+        }
     }
 }
 
 impl<B: IBookmarkDao> IBookmarkRepository for BookmarkRepository<B> {
     fn save(&mut self, bookmark: &Bookmark) -> Result<(), Error> {
-        self.dao.save(bookmark)
+        // 別名の重複を確かめてから書き込むまでを1つの操作にする
+        self.update_all(&mut |bookmarks| {
+            replace_or_push(bookmarks, bookmark);
+            Ok(())
+        })
     }
 
-    fn delete(&mut self, path: &str) -> Result<(), Error> {
-        self.dao.delete(path)
+    fn delete(&mut self, paths: &[PathBuf]) -> Result<(), Error> {
+        if !self.host.is_identity() {
+            return self.update_all(&mut |bookmarks| {
+                bookmarks.retain(|b| !paths.iter().any(|p| p == b.get_path()));
+                Ok(())
+            });
+        }
+        let profile = self.current_profile()?;
+        self.dao.delete(&profile, paths)
     }
 
     fn find_all(&mut self) -> Result<Vec<Bookmark>, Error> {
-        self.dao.find_all()
+        let profile = self.current_profile()?;
+        let bookmarks = self.dao.find_all(&profile)?;
+        Ok(self.host.split(bookmarks).0)
+    }
+
+    fn update_all(
+        &mut self,
+        f: &mut dyn FnMut(&mut Vec<Bookmark>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let profile = self.current_profile()?;
+        let host = &self.host;
+        self.dao.update_all(&profile, &mut |bookmarks| {
+            // 隠したブックマークには触れさせず、元の位置に戻す
+            let (mut visible, hidden) = host.split(std::mem::take(bookmarks));
+            f(&mut visible)?;
+            validate_unique(&visible)?;
+            *bookmarks = host.join(visible, hidden);
+            // 保存する形に戻したものが、他のホストに限られたブックマークとパスや別名で重ならないこと
+            validate_unique(bookmarks)
+                .map_err(|e| Error::new(e.kind(), format!("{} (already used for another host)", e)))
+        })
+    }
+
+    fn find_by_tags(&mut self, query: &TagQuery) -> Result<Vec<Bookmark>, Error> {
+        let profile = self.current_profile()?;
+        let bookmarks = self.dao.find_by_tags(&profile, query)?;
+        Ok(self.host.split(bookmarks).0)
+    }
+
+    fn count_tags(&mut self) -> Result<Vec<(String, usize)>, Error> {
+        let profile = self.current_profile()?;
+        let host = &self.host;
+        self.dao.count_tags(&profile, &|b| host.is_visible(b))
+    }
+
+    fn find_stored(&mut self, profile: &str) -> Result<Vec<Bookmark>, Error> {
+        self.dao.find_all(profile)
     }
+
+    fn replace_stored(&mut self, profile: &str, bookmarks: &[Bookmark]) -> Result<(), Error> {
+        self.dao.update_all(profile, &mut |stored| {
+            *stored = bookmarks.to_vec();
+            Ok(())
+        })
+    }
+
+    fn select_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.ensure_profile_exists(name)?;
+        self.profile = Some(name.to_string());
+        Ok(())
+    }
+
+    fn current_profile(&mut self) -> Result<String, Error> {
+        match &self.profile {
+            Some(profile) => Ok(profile.clone()),
+            None => self.dao.get_default_profile(),
+        }
+    }
+
+    fn find_profiles(&mut self) -> Result<Vec<String>, Error> {
+        self.dao.find_profiles()
+    }
+
+    fn create_profile(&mut self, name: &str) -> Result<(), Error> {
+        validate_name("profile", name)?;
+        self.dao.create_profile(name)
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<(), Error> {
+        if self.dao.get_default_profile()? == name {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "cannot delete the default profile: {} (set another default profile first)",
+                    name
+                ),
+            ));
+        }
+        self.dao.delete_profile(name)?;
+        if self.profile.as_deref() == Some(name) {
+            self.profile = None;
+        }
+        Ok(())
+    }
+
+    fn rename_profile(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        validate_name("profile", to)?;
+        self.dao.rename_profile(from, to)?;
+        if self.profile.as_deref() == Some(from) {
+            self.profile = Some(to.to_string());
+        }
+        Ok(())
+    }
+
+    fn get_default_profile(&mut self) -> Result<String, Error> {
+        self.dao.get_default_profile()
+    }
+
+    fn set_default_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.dao.set_default_profile(name)
+    }
+}
+
+/// 書き換えた結果、パスや別名が重複していないか確かめる
+fn validate_unique(bookmarks: &[Bookmark]) -> Result<(), Error> {
+    let mut paths = std::collections::HashSet::new();
+    let mut names = std::collections::HashMap::new();
+    for bookmark in bookmarks {
+        if !paths.insert(bookmark.get_path()) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("duplicate bookmark: {}", bookmark.get_path().display()),
+            ));
+        }
+        if let Some(name) = bookmark.get_name() {
+            validate_name("bookmark", name)?;
+            if let Some(other) = names.insert(name, bookmark.get_path()) {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!(
+                        "bookmark name already in use: {} ({})",
+                        name,
+                        other.display()
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
 }
 
+/// プロファイル名や別名は一覧表示やコマンドライン引数で扱いやすいものに限る
+pub fn validate_name(what: &str, name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name.starts_with('-')
+        || name.contains('/')
+        || name.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid {} name: {:?}", what, name),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
 pub struct MockBookmarkRepository {
-    bookmarks: Vec<Bookmark>,
+    profiles: std::collections::BTreeMap<String, Vec<Bookmark>>,
+    profile: String,
+    default_profile: String,
 }
 
 #[cfg(test)]
 impl MockBookmarkRepository {
     pub fn new(bookmarks: &[Bookmark]) -> Self {
+        let default_profile = crate::dao::DEFAULT_PROFILE.to_string();
         Self {
-            bookmarks: bookmarks.to_vec(),
+            profiles: [(default_profile.clone(), bookmarks.to_vec())].into(),
+            profile: default_profile.clone(),
+            default_profile,
         }
     }
+
+    fn bookmarks_mut(&mut self) -> &mut Vec<Bookmark> {
+        self.profiles.entry(self.profile.clone()).or_default()
+    }
 }
 
+#[cfg(test)]
 impl IBookmarkRepository for MockBookmarkRepository {
     fn save(&mut self, bookmark: &Bookmark) -> Result<(), Error> {
-        if !self.bookmarks.contains(bookmark) {
-            self.bookmarks.push(bookmark.clone());
-        }
+        replace_or_push(self.bookmarks_mut(), bookmark);
         Ok(())
     }
 
-    fn delete(&mut self, path: &str) -> Result<(), Error> {
-        self.bookmarks.retain(|b| b.get_path() != path);
+    fn delete(&mut self, paths: &[PathBuf]) -> Result<(), Error> {
+        self.bookmarks_mut()
+            .retain(|b| !paths.iter().any(|p| p == b.get_path()));
         Ok(())
     }
 
     fn find_all(&mut self) -> Result<Vec<Bookmark>, Error> {
-        Ok(self.bookmarks.clone())
+        Ok(self.bookmarks_mut().clone())
+    }
+
+    fn update_all(
+        &mut self,
+        f: &mut dyn FnMut(&mut Vec<Bookmark>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut updated = self.bookmarks_mut().clone();
+        f(&mut updated)?;
+        *self.bookmarks_mut() = updated;
+        Ok(())
+    }
+
+    fn find_by_tags(&mut self, query: &TagQuery) -> Result<Vec<Bookmark>, Error> {
+        let mut bookmarks = self.bookmarks_mut().clone();
+        bookmarks.retain(|b| query.matches(b));
+        Ok(bookmarks)
+    }
+
+    fn count_tags(&mut self) -> Result<Vec<(String, usize)>, Error> {
+        let mut counts = std::collections::BTreeMap::new();
+        for bookmark in self.bookmarks_mut().iter() {
+            crate::dao::count_tags_of(&mut counts, bookmark);
+        }
+        Ok(counts.into_iter().collect())
+    }
+
+    fn find_stored(&mut self, profile: &str) -> Result<Vec<Bookmark>, Error> {
+        Ok(self.profiles.get(profile).cloned().unwrap_or_default())
+    }
+
+    fn replace_stored(&mut self, profile: &str, bookmarks: &[Bookmark]) -> Result<(), Error> {
+        self.profiles
+            .insert(profile.to_string(), bookmarks.to_vec());
+        Ok(())
+    }
+
+    fn select_profile(&mut self, name: &str) -> Result<(), Error> {
+        if !self.profiles.contains_key(name) {
+            return Err(crate::dao::profile_not_found(name));
+        }
+        self.profile = name.to_string();
+        Ok(())
+    }
+
+    fn current_profile(&mut self) -> Result<String, Error> {
+        Ok(self.profile.clone())
+    }
+
+    fn find_profiles(&mut self) -> Result<Vec<String>, Error> {
+        Ok(self.profiles.keys().cloned().collect())
+    }
+
+    fn create_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.profiles.insert(name.to_string(), Vec::new());
+        Ok(())
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.profiles.remove(name);
+        Ok(())
+    }
+
+    fn rename_profile(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        let bookmarks = self.profiles.remove(from).unwrap_or_default();
+        self.profiles.insert(to.to_string(), bookmarks);
+        Ok(())
+    }
+
+    fn get_default_profile(&mut self) -> Result<String, Error> {
+        Ok(self.default_profile.clone())
+    }
+
+    fn set_default_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.default_profile = name.to_string();
+        Ok(())
     }
 }
 
@@ -124,7 +423,7 @@ mod tests {
         let dao = MockBookmarkDao::new(&init_bookmarks);
         let mut repo = BookmarkRepository::new(dao);
 
-        repo.delete(path_to_delete).unwrap();
+        repo.delete(&[PathBuf::from(path_to_delete)]).unwrap();
         let actual_bookmarks = repo.find_all().unwrap();
         assert_eq!(actual_bookmarks, expected_bookmarks);
     }
@@ -148,4 +447,194 @@ mod tests {
         let actual_bookmarks = repo.find_all().unwrap();
         assert_eq!(actual_bookmarks, expected_bookmarks);
     }
+
+    #[test]
+    // 選択したプロファイルのブックマークだけが操作されること
+    fn test_select_profile() {
+        let dao = MockBookmarkDao::new(&[Bookmark::new("path1", vec![])]);
+        let mut repo = BookmarkRepository::new(dao);
+        repo.create_profile("work").unwrap();
+
+        repo.select_profile("work").unwrap();
+        repo.save(&Bookmark::new("path2", vec![])).unwrap();
+        assert_eq!(repo.current_profile().unwrap(), "work");
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![Bookmark::new("path2", vec![])]
+        );
+
+        repo.select_profile("default").unwrap();
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![Bookmark::new("path1", vec![])]
+        );
+
+        assert!(repo.select_profile("unknown").is_err());
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("my profile")]
+    #[case("-work")]
+    fn test_create_profile_with_invalid_name(#[case] name: &str) {
+        let mut repo = BookmarkRepository::new(MockBookmarkDao::new(&[]));
+        assert!(repo.create_profile(name).is_err());
+        assert!(repo.rename_profile("default", name).is_err());
+    }
+
+    #[test]
+    // デフォルトのプロファイルは削除できないこと
+    fn test_delete_default_profile() {
+        let mut repo = BookmarkRepository::new(MockBookmarkDao::new(&[]));
+        repo.create_profile("work").unwrap();
+        assert!(repo.delete_profile("default").is_err());
+
+        repo.set_default_profile("work").unwrap();
+        repo.delete_profile("default").unwrap();
+        assert_eq!(repo.find_profiles().unwrap(), vec!["work"]);
+    }
+
+    #[rstest]
+    // 別のブックマークと同じ別名は付けられないこと
+    #[case("path2", Some("api"), false)]
+    // 同じブックマークの別名は付け直せること
+    #[case("path1", Some("api"), true)]
+    #[case("path2", Some("web"), true)]
+    #[case("path2", None, true)]
+    // 不正な別名
+    #[case("path2", Some("my api"), false)]
+    #[case("path2", Some("a/b"), false)]
+    fn test_save_bookmark_with_name(
+        #[case] path: &str,
+        #[case] name: Option<&str>,
+        #[case] expected_ok: bool,
+    ) {
+        let init_bookmarks = vec![
+            Bookmark::new("path1", vec![]).with_name(Some("api".to_string())),
+            Bookmark::new("path2", vec![]),
+        ];
+        let mut repo = BookmarkRepository::new(MockBookmarkDao::new(&init_bookmarks));
+
+        let bookmark = Bookmark::new(path, vec![]).with_name(name.map(String::from));
+        assert_eq!(repo.save(&bookmark).is_ok(), expected_ok);
+    }
+
+    #[test]
+    // 書き換えの結果パスや別名が重複する場合は何も変更されないこと
+    fn test_update_all_rejects_duplicates() {
+        let init_bookmarks = vec![
+            Bookmark::new("path1", vec![]).with_name(Some("api".to_string())),
+            Bookmark::new("path2", vec![]),
+        ];
+        let mut repo = BookmarkRepository::new(MockBookmarkDao::new(&init_bookmarks));
+
+        let result = repo.update_all(&mut |bookmarks| {
+            bookmarks[1] = Bookmark::new("path2", vec![]).with_name(Some("api".to_string()));
+            Ok(())
+        });
+        assert!(result.is_err());
+        let result = repo.update_all(&mut |bookmarks| {
+            bookmarks.push(Bookmark::new("path1", vec![]));
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(repo.find_all().unwrap(), init_bookmarks);
+
+        repo.update_all(&mut |bookmarks| {
+            bookmarks[1] = Bookmark::new("path2", vec!["tag".to_string()]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(repo.find_all().unwrap()[1].get_tags(), ["tag"]);
+    }
+
+    #[test]
+    // このホストでのパスで読み書きされ、他のホストに限られたブックマークは隠されたまま残されること
+    fn test_host_view() {
+        let devbox = vec!["devbox".to_string()];
+        let init_bookmarks = vec![
+            Bookmark::new("/home/alice/a", vec![]),
+            Bookmark::new("/home/alice/b", vec![]).with_hosts(devbox.clone()),
+        ];
+        let host = HostView::new(Some("macbook".to_string())).with_rewrites(vec![(
+            PathBuf::from("/home/alice"),
+            PathBuf::from("/Users/alice"),
+        )]);
+        let mut repo =
+            BookmarkRepository::new(MockBookmarkDao::new(&init_bookmarks)).with_host(host);
+
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![Bookmark::new("/Users/alice/a", vec![])]
+        );
+        repo.save(&Bookmark::new("/Users/alice/c", vec![])).unwrap();
+        repo.delete(&[PathBuf::from("/Users/alice/a")]).unwrap();
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![Bookmark::new("/Users/alice/c", vec![])]
+        );
+
+        // 保存する形に戻すと他のホストのブックマークと重なる場合はエラーになること
+        let result = repo.save(&Bookmark::new("/Users/alice/b", vec![]));
+        assert!(result.is_err());
+
+        let mut repo = BookmarkRepository::new(repo.dao);
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![
+                Bookmark::new("/home/alice/c", vec![]),
+                Bookmark::new("/home/alice/b", vec![]).with_hosts(devbox),
+            ]
+        );
+    }
+
+    #[test]
+    // 他のホストに限られたブックマークと同じ別名は付けられないこと
+    fn test_host_view_rejects_hidden_names() {
+        let init_bookmarks = vec![
+            Bookmark::new("/srv/a", vec![]),
+            Bookmark::new("/srv/b", vec![])
+                .with_name(Some("api".to_string()))
+                .with_hosts(vec!["devbox".to_string()]),
+        ];
+        let host = HostView::new(Some("macbook".to_string()));
+        let mut repo =
+            BookmarkRepository::new(MockBookmarkDao::new(&init_bookmarks)).with_host(host);
+
+        let named = Bookmark::new("/srv/a", vec![]).with_name(Some("api".to_string()));
+        assert!(repo.save(&named).is_err());
+        let result = repo.update_all(&mut |bookmarks| {
+            bookmarks[0] = named.clone();
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![Bookmark::new("/srv/a", vec![])]
+        );
+
+        let renamed = Bookmark::new("/srv/a", vec![]).with_name(Some("web".to_string()));
+        repo.save(&renamed).unwrap();
+        assert_eq!(repo.find_all().unwrap(), vec![renamed]);
+    }
+
+    #[test]
+    // タグで引くときも数えるときも、他のホストに限られたブックマークは含まれないこと
+    fn test_host_view_tags() {
+        let init_bookmarks = vec![
+            Bookmark::new("/srv/a", vec!["rust".to_string()]),
+            Bookmark::new("/srv/b", vec!["rust".to_string()])
+                .with_hosts(vec!["devbox".to_string()]),
+        ];
+        let host = HostView::new(Some("macbook".to_string()));
+        let mut repo =
+            BookmarkRepository::new(MockBookmarkDao::new(&init_bookmarks)).with_host(host);
+
+        let query = TagQuery::Tag("rust".to_string());
+        assert_eq!(
+            repo.find_by_tags(&query).unwrap(),
+            vec![init_bookmarks[0].clone()]
+        );
+        assert_eq!(repo.count_tags().unwrap(), vec![("rust".to_string(), 1)]);
+    }
 }
@@ -1,4 +1,4 @@
-use dialoguer::{theme::ColorfulTheme, Error, FuzzySelect};
+use dialoguer::{theme::ColorfulTheme, Error, FuzzySelect, MultiSelect};
 
 use crate::bookmark::Bookmark;
 
@@ -8,6 +8,15 @@ use mockall::automock;
 #[cfg_attr(test, automock)]
 pub trait BookmarkSelector {
     fn select(&self, items: &[Bookmark], prompt: String) -> Result<Option<Bookmark>, Error>;
+    /// 表示用の文字列から選択し、選ばれた位置を返す
+    fn select_index(&self, labels: &[String], prompt: String) -> Result<Option<usize>, Error>;
+    /// 表示用の文字列から複数選択し、選ばれた位置を返す (`checked`なら最初から全て選んでおく)
+    fn select_many(
+        &self,
+        labels: &[String],
+        prompt: String,
+        checked: bool,
+    ) -> Result<Vec<usize>, Error>;
 }
 
 pub struct FuzzyBookmarkSelector {}
@@ -31,4 +40,34 @@ impl BookmarkSelector for FuzzyBookmarkSelector {
             .interact()?;
         Ok(Some(items[selection].clone()))
     }
+
+    fn select_index(&self, labels: &[String], prompt: String) -> Result<Option<usize>, Error> {
+        if labels.is_empty() {
+            return Ok(None);
+        }
+        let theme = ColorfulTheme::default();
+        let selection = FuzzySelect::with_theme(&theme)
+            .with_prompt(prompt)
+            .items(labels)
+            .default(0)
+            .interact()?;
+        Ok(Some(selection))
+    }
+
+    fn select_many(
+        &self,
+        labels: &[String],
+        prompt: String,
+        checked: bool,
+    ) -> Result<Vec<usize>, Error> {
+        if labels.is_empty() {
+            return Ok(Vec::new());
+        }
+        let theme = ColorfulTheme::default();
+        MultiSelect::with_theme(&theme)
+            .with_prompt(prompt)
+            .items(labels)
+            .defaults(&vec![checked; labels.len()])
+            .interact()
+    }
 }
@@ -0,0 +1,850 @@
+use crate::bookmark::Bookmark;
+use crate::dao::{
+    count_tags_of, profile_already_exists, profile_not_found, IBookmarkDao, DEFAULT_PROFILE,
+};
+use crate::tag_query::TagQuery;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, TransactionBehavior};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 他のプロセスが書き込み中のときに待つ時間
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// データベースのスキーマのバージョン (`PRAGMA user_version` に保存する)
+///
+/// - 1: `bookmarks`と`tags`のみ
+/// - 2: プロファイルを追加
+/// - 3: 並び順を`position`列に持たせる
+const SCHEMA_VERSION: u32 = 3;
+
+/// ブックマーク本体は`data`列にJSONで保存し、検索に使う値だけを列と索引に持たせる
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS profiles (
+    name TEXT PRIMARY KEY
+);
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS bookmarks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    profile TEXT NOT NULL REFERENCES profiles(name) ON UPDATE CASCADE ON DELETE CASCADE,
+    path BLOB NOT NULL,
+    data TEXT NOT NULL,
+    position INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (profile, path)
+);
+CREATE TABLE IF NOT EXISTS tags (
+    bookmark_id INTEGER NOT NULL REFERENCES bookmarks(id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (bookmark_id, tag)
+);
+CREATE INDEX IF NOT EXISTS tags_tag ON tags(tag);
+CREATE INDEX IF NOT EXISTS bookmarks_position ON bookmarks(profile, position);
+";
+
+/// バージョン1のテーブルを退避する (新しいテーブルを作成した後に`COPY_V1_TO_V2`で移す)
+const MIGRATE_V1_TO_V2: &str = "
+ALTER TABLE bookmarks RENAME TO bookmarks_v1;
+ALTER TABLE tags RENAME TO tags_v1;
+DROP INDEX tags_tag;
+";
+
+/// バージョン1のブックマークを全てデフォルトのプロファイルに移す
+const COPY_V1_TO_V2: &str = "
+INSERT INTO bookmarks (id, profile, path, data)
+    SELECT id, 'default', path, data FROM bookmarks_v1;
+INSERT INTO tags (bookmark_id, tag) SELECT bookmark_id, tag FROM tags_v1;
+DROP TABLE tags_v1;
+DROP TABLE bookmarks_v1;
+";
+
+/// バージョン2のテーブルに並び順の列を加える (`SCHEMA`より前に実行する)
+const MIGRATE_V2_TO_V3: &str = "
+ALTER TABLE bookmarks ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+";
+
+const DEFAULT_PROFILE_KEY: &str = "default_profile";
+
+pub struct SqliteBookmarkDao {
+    conn: Connection,
+}
+
+impl SqliteBookmarkDao {
+    /// データベースを開く。存在しなければ作成する
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let conn = Connection::open(path).map_err(to_io_error)?;
+        Self::init(conn).map_err(|e| match e.kind() {
+            ErrorKind::InvalidData => Error::new(e.kind(), format!("{}: {}", path.display(), e)),
+            _ => e,
+        })
+    }
+
+    fn init(mut conn: Connection) -> Result<Self, Error> {
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(to_io_error)?;
+        let version: u32 = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .map_err(to_io_error)?;
+        if version > SCHEMA_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "written by a newer version of bm (schema version {}, supported up to {}); please upgrade bm",
+                    version, SCHEMA_VERSION
+                ),
+            ));
+        }
+
+        // 外部キー制約はトランザクション内では切り替えられないため、移行が終わってから有効にする
+        let tx = conn.transaction().map_err(to_io_error)?;
+        if version == 1 {
+            tx.execute_batch(MIGRATE_V1_TO_V2).map_err(to_io_error)?;
+        }
+        if version == 2 {
+            tx.execute_batch(MIGRATE_V2_TO_V3).map_err(to_io_error)?;
+        }
+        tx.execute_batch(SCHEMA).map_err(to_io_error)?;
+        tx.execute(
+            "INSERT INTO profiles (name) SELECT ?1 WHERE NOT EXISTS (SELECT 1 FROM profiles)",
+            params![DEFAULT_PROFILE],
+        )
+        .map_err(to_io_error)?;
+        if version == 1 {
+            tx.execute_batch(COPY_V1_TO_V2).map_err(to_io_error)?;
+        }
+        if (1..3).contains(&version) {
+            // それまでは登録順 (`id`の順) に並べていた
+            tx.execute("UPDATE bookmarks SET position = id", [])
+                .map_err(to_io_error)?;
+        }
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION)
+            .map_err(to_io_error)?;
+        tx.commit().map_err(to_io_error)?;
+
+        conn.pragma_update(None, "foreign_keys", true)
+            .map_err(to_io_error)?;
+        Ok(Self { conn })
+    }
+
+    fn profile_exists(&self, name: &str) -> Result<bool, Error> {
+        self.conn
+            .query_row(
+                "SELECT 1 FROM profiles WHERE name = ?1",
+                params![name],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|row| row.is_some())
+            .map_err(to_io_error)
+    }
+
+    fn ensure_profile(&self, name: &str) -> Result<(), Error> {
+        if self.profile_exists(name)? {
+            Ok(())
+        } else {
+            Err(profile_not_found(name))
+        }
+    }
+}
+
+impl IBookmarkDao for SqliteBookmarkDao {
+    fn delete(&mut self, profile: &str, paths: &[PathBuf]) -> Result<(), Error> {
+        self.ensure_profile(profile)?;
+        let tx = self.conn.transaction().map_err(to_io_error)?;
+        for path in paths {
+            tx.execute(
+                "DELETE FROM bookmarks WHERE profile = ?1 AND path = ?2",
+                params![profile, path.as_os_str().as_encoded_bytes()],
+            )
+            .map_err(to_io_error)?;
+        }
+        tx.commit().map_err(to_io_error)
+    }
+
+    fn find_all(&mut self, profile: &str) -> Result<Vec<Bookmark>, Error> {
+        self.ensure_profile(profile)?;
+        select_all(&self.conn, profile)
+    }
+
+    fn update_all(
+        &mut self,
+        profile: &str,
+        f: &mut dyn FnMut(&mut Vec<Bookmark>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.ensure_profile(profile)?;
+        // 読み込んでから書き込むまでの間に他のプロセスが書き込まないよう、最初に書き込みロックを取る
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(to_io_error)?;
+        let old = select_rows(&tx, profile)?;
+        let mut new: Vec<Bookmark> = old.iter().map(|row| row.bookmark.clone()).collect();
+        f(&mut new)?;
+
+        let old_paths: HashSet<&Path> = old.iter().map(|row| row.bookmark.get_path()).collect();
+        let new_paths: HashSet<&Path> = new.iter().map(|b| b.get_path()).collect();
+        // 書き込み後も残る行を、書き込むブックマークのパスで引けるようにする
+        let mut kept: HashMap<&Path, &Row> = HashMap::new();
+        for (i, row) in old.iter().enumerate() {
+            let path = row.bookmark.get_path();
+            if new_paths.contains(path) {
+                kept.insert(path, row);
+                continue;
+            }
+            // パスだけが変わったもの (同じ位置に新しいパスがあるもの) は、行をそのまま書き換える
+            match new.get(i).map(|b| b.get_path()) {
+                Some(moved) if !old_paths.contains(moved) && !kept.contains_key(moved) => {
+                    tx.execute(
+                        "UPDATE bookmarks SET path = ?1 WHERE id = ?2",
+                        params![moved.as_os_str().as_encoded_bytes(), row.id],
+                    )
+                    .map_err(to_io_error)?;
+                    kept.insert(moved, row);
+                }
+                _ => {
+                    tx.execute("DELETE FROM bookmarks WHERE id = ?1", params![row.id])
+                        .map_err(to_io_error)?;
+                }
+            }
+        }
+        for (position, bookmark) in new.iter().enumerate() {
+            let position = position as i64;
+            match kept.get(bookmark.get_path()) {
+                Some(row) if row.bookmark == *bookmark && row.position == position => {}
+                Some(row) if row.bookmark == *bookmark => {
+                    tx.execute(
+                        "UPDATE bookmarks SET position = ?1 WHERE id = ?2",
+                        params![position, row.id],
+                    )
+                    .map_err(to_io_error)?;
+                }
+                Some(row) => update_row(&tx, row.id, position, bookmark)?,
+                None => insert_row(&tx, profile, position, bookmark)?,
+            }
+        }
+        tx.commit().map_err(to_io_error)
+    }
+
+    fn find_by_tags(&mut self, profile: &str, query: &TagQuery) -> Result<Vec<Bookmark>, Error> {
+        self.ensure_profile(profile)?;
+        let mut values = vec![profile.to_string()];
+        let condition = tag_condition(query, &mut values);
+        let sql = format!(
+            "SELECT data FROM bookmarks WHERE profile = ?1 AND {} ORDER BY position, id",
+            condition
+        );
+        let mut stmt = self.conn.prepare(&sql).map_err(to_io_error)?;
+        let rows = stmt
+            .query_map(params_from_iter(&values), |row| row.get::<_, String>(0))
+            .map_err(to_io_error)?;
+        let mut bookmarks = Vec::new();
+        for data in rows {
+            bookmarks.push(serde_json::from_str(&data.map_err(to_io_error)?)?);
+        }
+        Ok(bookmarks)
+    }
+
+    fn count_tags(
+        &mut self,
+        profile: &str,
+        visible: &dyn Fn(&Bookmark) -> bool,
+    ) -> Result<Vec<(String, usize)>, Error> {
+        self.ensure_profile(profile)?;
+        // 全てのホストで使えるものは索引で数え、ホストを限ったものだけを読み込んで確かめる
+        let mut counts = BTreeMap::new();
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT t.tag, COUNT(*) FROM tags t JOIN bookmarks b ON b.id = t.bookmark_id \
+                 WHERE b.profile = ?1 AND json_extract(b.data, '$.hosts') IS NULL \
+                 GROUP BY t.tag",
+            )
+            .map_err(to_io_error)?;
+        let rows = stmt
+            .query_map(params![profile], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .map_err(to_io_error)?;
+        for row in rows {
+            let (tag, count) = row.map_err(to_io_error)?;
+            counts.insert(tag, count as usize);
+        }
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT data FROM bookmarks \
+                 WHERE profile = ?1 AND json_extract(data, '$.hosts') IS NOT NULL",
+            )
+            .map_err(to_io_error)?;
+        let rows = stmt
+            .query_map(params![profile], |row| row.get::<_, String>(0))
+            .map_err(to_io_error)?;
+        for data in rows {
+            let bookmark: Bookmark = serde_json::from_str(&data.map_err(to_io_error)?)?;
+            if visible(&bookmark) {
+                count_tags_of(&mut counts, &bookmark);
+            }
+        }
+        Ok(counts.into_iter().collect())
+    }
+
+    fn find_profiles(&mut self) -> Result<Vec<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM profiles ORDER BY name")
+            .map_err(to_io_error)?;
+        let rows = stmt.query_map([], |row| row.get(0)).map_err(to_io_error)?;
+        rows.collect::<Result<_, _>>().map_err(to_io_error)
+    }
+
+    fn create_profile(&mut self, name: &str) -> Result<(), Error> {
+        let inserted = self
+            .conn
+            .execute(
+                "INSERT OR IGNORE INTO profiles (name) VALUES (?1)",
+                params![name],
+            )
+            .map_err(to_io_error)?;
+        match inserted {
+            0 => Err(profile_already_exists(name)),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<(), Error> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM profiles WHERE name = ?1", params![name])
+            .map_err(to_io_error)?;
+        match deleted {
+            0 => Err(profile_not_found(name)),
+            _ => Ok(()),
+        }
+    }
+
+    fn rename_profile(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        self.ensure_profile(from)?;
+        if self.profile_exists(to)? {
+            return Err(profile_already_exists(to));
+        }
+        let default_profile = self.get_default_profile()?;
+        let tx = self.conn.transaction().map_err(to_io_error)?;
+        tx.execute(
+            "UPDATE profiles SET name = ?1 WHERE name = ?2",
+            params![to, from],
+        )
+        .map_err(to_io_error)?;
+        if default_profile == from {
+            tx.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+                params![DEFAULT_PROFILE_KEY, to],
+            )
+            .map_err(to_io_error)?;
+        }
+        tx.commit().map_err(to_io_error)
+    }
+
+    fn get_default_profile(&mut self) -> Result<String, Error> {
+        let value: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = ?1",
+                params![DEFAULT_PROFILE_KEY],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(to_io_error)?;
+        Ok(value.unwrap_or_else(|| DEFAULT_PROFILE.to_string()))
+    }
+
+    fn set_default_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.ensure_profile(name)?;
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+                params![DEFAULT_PROFILE_KEY, name],
+            )
+            .map_err(to_io_error)?;
+        Ok(())
+    }
+}
+
+/// 保存されている1件分
+struct Row {
+    id: i64,
+    position: i64,
+    bookmark: Bookmark,
+}
+
+fn select_rows(conn: &Connection, profile: &str) -> Result<Vec<Row>, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, position, data FROM bookmarks WHERE profile = ?1 ORDER BY position, id",
+        )
+        .map_err(to_io_error)?;
+    let rows = stmt
+        .query_map(params![profile], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+        })
+        .map_err(to_io_error)?;
+    let mut result = Vec::new();
+    for row in rows {
+        let (id, position, data) = row.map_err(to_io_error)?;
+        result.push(Row {
+            id,
+            position,
+            bookmark: serde_json::from_str(&data)?,
+        });
+    }
+    Ok(result)
+}
+
+fn select_all(conn: &Connection, profile: &str) -> Result<Vec<Bookmark>, Error> {
+    Ok(select_rows(conn, profile)?
+        .into_iter()
+        .map(|row| row.bookmark)
+        .collect())
+}
+
+/// ブックマークを`position`の位置に追加する
+fn insert_row(
+    conn: &Connection,
+    profile: &str,
+    position: i64,
+    bookmark: &Bookmark,
+) -> Result<(), Error> {
+    let path = bookmark.get_path().as_os_str().as_encoded_bytes();
+    let data = serde_json::to_string(bookmark)?;
+    conn.execute(
+        "INSERT INTO bookmarks (profile, path, data, position) VALUES (?1, ?2, ?3, ?4)",
+        params![profile, path, data, position],
+    )
+    .map_err(to_io_error)?;
+    insert_tags(conn, conn.last_insert_rowid(), bookmark)
+}
+
+/// 既存の行の内容と位置を書き換える
+fn update_row(conn: &Connection, id: i64, position: i64, bookmark: &Bookmark) -> Result<(), Error> {
+    let path = bookmark.get_path().as_os_str().as_encoded_bytes();
+    let data = serde_json::to_string(bookmark)?;
+    conn.execute(
+        "UPDATE bookmarks SET path = ?1, data = ?2, position = ?3 WHERE id = ?4",
+        params![path, data, position, id],
+    )
+    .map_err(to_io_error)?;
+    conn.execute("DELETE FROM tags WHERE bookmark_id = ?1", params![id])
+        .map_err(to_io_error)?;
+    insert_tags(conn, id, bookmark)
+}
+
+fn insert_tags(conn: &Connection, id: i64, bookmark: &Bookmark) -> Result<(), Error> {
+    for tag in bookmark.get_tags() {
+        conn.execute(
+            "INSERT OR IGNORE INTO tags (bookmark_id, tag) VALUES (?1, ?2)",
+            params![id, tag],
+        )
+        .map_err(to_io_error)?;
+    }
+    Ok(())
+}
+
+/// タグの条件を、タグの索引で引くSQLの条件にする。タグは`values`に加えて番号付きのパラメータにする
+fn tag_condition(query: &TagQuery, values: &mut Vec<String>) -> String {
+    match query {
+        TagQuery::Tag(tag) => {
+            values.push(tag.clone());
+            format!(
+                "id IN (SELECT bookmark_id FROM tags WHERE tag = ?{})",
+                values.len()
+            )
+        }
+        TagQuery::Not(query) => format!("NOT ({})", tag_condition(query, values)),
+        TagQuery::And(a, b) => format!(
+            "({} AND {})",
+            tag_condition(a, values),
+            tag_condition(b, values)
+        ),
+        TagQuery::Or(a, b) => format!(
+            "({} OR {})",
+            tag_condition(a, values),
+            tag_condition(b, values)
+        ),
+    }
+}
+
+fn to_io_error(e: rusqlite::Error) -> Error {
+    Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dao::save_bookmark;
+    use rstest::rstest;
+
+    const D: &str = DEFAULT_PROFILE;
+
+    fn setup(bookmarks: &[Bookmark]) -> SqliteBookmarkDao {
+        let mut dao = SqliteBookmarkDao::init(Connection::open_in_memory().unwrap()).unwrap();
+        for bookmark in bookmarks {
+            save_bookmark(&mut dao, D, bookmark).unwrap();
+        }
+        dao
+    }
+
+    fn tags_of(dao: &SqliteBookmarkDao, tag: &str) -> Vec<String> {
+        let mut stmt = dao
+            .conn
+            .prepare(
+                "SELECT b.data FROM tags t JOIN bookmarks b ON b.id = t.bookmark_id \
+                 WHERE t.tag = ?1 ORDER BY b.id",
+            )
+            .unwrap();
+        stmt.query_map(params![tag], |row| row.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    #[test]
+    // 保存したブックマークが登録順に取得されること
+    fn test_save_and_find_all() {
+        let bookmarks = vec![
+            Bookmark::new("/path/b", vec!["tag1".to_string()]),
+            Bookmark::new("/path/a", vec![]),
+        ];
+        let mut dao = setup(&bookmarks);
+        assert_eq!(dao.find_all(D).unwrap(), bookmarks);
+    }
+
+    #[test]
+    // 既存のブックマークは同じ位置のまま更新され、タグの索引も更新されること
+    fn test_save_existing() {
+        let mut dao = setup(&[
+            Bookmark::new("/path/a", vec!["old".to_string()]),
+            Bookmark::new("/path/b", vec![]),
+        ]);
+        save_bookmark(
+            &mut dao,
+            D,
+            &Bookmark::new("/path/a", vec!["new".to_string()]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![
+                Bookmark::new("/path/a", vec!["new".to_string()]),
+                Bookmark::new("/path/b", vec![]),
+            ]
+        );
+        assert!(tags_of(&dao, "old").is_empty());
+        assert_eq!(tags_of(&dao, "new").len(), 1);
+    }
+
+    #[test]
+    // 指定した全てのブックマークとそのタグが削除されること
+    fn test_delete() {
+        let mut dao = setup(&[
+            Bookmark::new("/path/a", vec!["tag".to_string()]),
+            Bookmark::new("/path/b", vec![]),
+            Bookmark::new("/path/c", vec!["tag".to_string()]),
+        ]);
+        dao.delete(D, &[PathBuf::from("/path/a"), PathBuf::from("/path/c")])
+            .unwrap();
+
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![Bookmark::new("/path/b", vec![])]
+        );
+        assert!(tags_of(&dao, "tag").is_empty());
+    }
+
+    #[test]
+    // まとめて書き換えられ、途中で失敗した場合は何も変更されないこと
+    fn test_update_all() {
+        let mut dao = setup(&[
+            Bookmark::new("/path/a", vec!["old".to_string()]),
+            Bookmark::new("/path/b", vec![]),
+        ]);
+
+        let result = dao.update_all(D, &mut |bookmarks| {
+            bookmarks.clear();
+            Err(Error::other("failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(dao.find_all(D).unwrap().len(), 2);
+
+        dao.update_all(D, &mut |bookmarks| {
+            bookmarks[0].set_tags(vec!["new".to_string()]);
+            bookmarks.remove(1);
+            bookmarks.push(Bookmark::new("/path/c", vec![]));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![
+                Bookmark::new("/path/a", vec!["new".to_string()]),
+                Bookmark::new("/path/c", vec![]),
+            ]
+        );
+        assert!(tags_of(&dao, "old").is_empty());
+        assert_eq!(tags_of(&dao, "new").len(), 1);
+    }
+
+    fn ids(dao: &SqliteBookmarkDao) -> Vec<i64> {
+        let mut stmt = dao
+            .conn
+            .prepare("SELECT id FROM bookmarks ORDER BY position, id")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    #[test]
+    // パスを変えたブックマークは同じ行のまま同じ位置に残ること
+    fn test_update_all_moves_in_place() {
+        let mut dao = setup(&[
+            Bookmark::new("/path/a", vec!["tag".to_string()]),
+            Bookmark::new("/path/b", vec![]),
+            Bookmark::new("/path/c", vec![]),
+        ]);
+        let before = ids(&dao);
+
+        dao.update_all(D, &mut |bookmarks| {
+            bookmarks[0].relocate(PathBuf::from("/path/x"), None);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![
+                Bookmark::new("/path/x", vec!["tag".to_string()]),
+                Bookmark::new("/path/b", vec![]),
+                Bookmark::new("/path/c", vec![]),
+            ]
+        );
+        assert_eq!(ids(&dao), before);
+        assert_eq!(tags_of(&dao, "tag").len(), 1);
+    }
+
+    #[test]
+    // 並べ替えた順に保存されること
+    fn test_update_all_reorders() {
+        let mut dao = setup(&[
+            Bookmark::new("/path/a", vec![]),
+            Bookmark::new("/path/b", vec![]),
+            Bookmark::new("/path/c", vec![]),
+        ]);
+
+        dao.update_all(D, &mut |bookmarks| {
+            bookmarks.reverse();
+            bookmarks.insert(1, Bookmark::new("/path/d", vec![]));
+            Ok(())
+        })
+        .unwrap();
+        let paths: Vec<_> = dao
+            .find_all(D)
+            .unwrap()
+            .iter()
+            .map(|b| b.get_path().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(paths, ["/path/c", "/path/d", "/path/b", "/path/a"]);
+    }
+
+    #[test]
+    // バージョン2のデータベースは登録順のまま移行されること
+    fn test_migrate_schema_version_2() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE profiles (name TEXT PRIMARY KEY);
+            CREATE TABLE bookmarks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                profile TEXT NOT NULL REFERENCES profiles(name) ON UPDATE CASCADE ON DELETE CASCADE,
+                path BLOB NOT NULL,
+                data TEXT NOT NULL,
+                UNIQUE (profile, path)
+            );
+            INSERT INTO profiles (name) VALUES ('default');
+            INSERT INTO bookmarks (profile, path, data) VALUES ('default', X'2F62', '{\"path\":\"/b\",\"tags\":[]}');
+            INSERT INTO bookmarks (profile, path, data) VALUES ('default', X'2F61', '{\"path\":\"/a\",\"tags\":[]}');
+            PRAGMA user_version = 2;",
+        )
+        .unwrap();
+
+        let mut dao = SqliteBookmarkDao::init(conn).unwrap();
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![Bookmark::new("/b", vec![]), Bookmark::new("/a", vec![])]
+        );
+        save_bookmark(&mut dao, D, &Bookmark::new("/c", vec![])).unwrap();
+        assert_eq!(dao.find_all(D).unwrap()[2], Bookmark::new("/c", vec![]));
+    }
+
+    fn tagged() -> Vec<Bookmark> {
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect();
+        vec![
+            Bookmark::new("/path/a", tags(&["rust", "web"])),
+            Bookmark::new("/path/b", tags(&["rust"])),
+            Bookmark::new("/path/c", tags(&["web", "old"])),
+            Bookmark::new("/path/d", vec![]),
+        ]
+    }
+
+    #[rstest]
+    #[case("rust")]
+    #[case("!rust")]
+    #[case("rust & web")]
+    #[case("rust | old")]
+    #[case("(web | rust) & !old")]
+    #[case("missing")]
+    // タグの索引で引いた結果が、条件式で絞り込んだ結果と同じ順に一致すること
+    fn test_find_by_tags(#[case] query: &str) {
+        let bookmarks = tagged();
+        let mut dao = setup(&bookmarks);
+        let query = TagQuery::parse(query).unwrap();
+
+        let expected: Vec<_> = bookmarks.into_iter().filter(|b| query.matches(b)).collect();
+        assert_eq!(dao.find_by_tags(D, &query).unwrap(), expected);
+    }
+
+    #[test]
+    // タグで引くときはタグの索引が使われること
+    fn test_find_by_tags_uses_index() {
+        let dao = setup(&tagged());
+        let mut values = vec![D.to_string()];
+        let condition = tag_condition(&TagQuery::Tag("rust".to_string()), &mut values);
+        let sql = format!(
+            "EXPLAIN QUERY PLAN SELECT data FROM bookmarks WHERE profile = ?1 AND {}",
+            condition
+        );
+        let mut stmt = dao.conn.prepare(&sql).unwrap();
+        let plan: Vec<String> = stmt
+            .query_map(params_from_iter(&values), |row| row.get(3))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert!(
+            plan.iter().any(|step| step.contains("tags_tag")),
+            "{:?}",
+            plan
+        );
+    }
+
+    #[test]
+    // タグごとに数えられ、ホストを限ったものは`visible`なものだけが数えられること
+    fn test_count_tags() {
+        let mut bookmarks = tagged();
+        bookmarks.push(
+            Bookmark::new("/path/e", vec!["rust".to_string()])
+                .with_hosts(vec!["devbox".to_string()]),
+        );
+        bookmarks.push(
+            Bookmark::new("/path/f", vec!["old".to_string()])
+                .with_hosts(vec!["laptop".to_string()]),
+        );
+        let mut dao = setup(&bookmarks);
+
+        let counts = dao
+            .count_tags(D, &|b| !b.get_hosts().contains(&"devbox".to_string()))
+            .unwrap();
+        assert_eq!(
+            counts,
+            vec![
+                ("old".to_string(), 2),
+                ("rust".to_string(), 2),
+                ("web".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    // 新しいスキーマのデータベースは開かないこと
+    fn test_newer_schema_version() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        let err = SqliteBookmarkDao::init(conn).err().unwrap();
+        assert!(err.to_string().contains("newer version"));
+    }
+
+    #[test]
+    // バージョン1のデータベースがデフォルトのプロファイルとして移行されること
+    fn test_migrate_schema_version_1() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE bookmarks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                path BLOB NOT NULL UNIQUE,
+                data TEXT NOT NULL
+            );
+            CREATE TABLE tags (
+                bookmark_id INTEGER NOT NULL REFERENCES bookmarks(id) ON DELETE CASCADE,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (bookmark_id, tag)
+            );
+            CREATE INDEX tags_tag ON tags(tag);
+            INSERT INTO bookmarks (path, data) VALUES (X'2F612F62', '{\"path\":\"/a/b\",\"tags\":[\"t\"]}');
+            INSERT INTO tags (bookmark_id, tag) VALUES (1, 't');
+            PRAGMA user_version = 1;",
+        )
+        .unwrap();
+
+        let mut dao = SqliteBookmarkDao::init(conn).unwrap();
+        assert_eq!(dao.find_profiles().unwrap(), vec![D]);
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![Bookmark::new("/a/b", vec!["t".to_string()])]
+        );
+        assert_eq!(tags_of(&dao, "t").len(), 1);
+    }
+
+    #[test]
+    // プロファイルごとにブックマークが分かれて保存されること
+    fn test_profiles() {
+        let mut dao = setup(&[Bookmark::new("/path/a", vec![])]);
+        dao.create_profile("work").unwrap();
+        save_bookmark(
+            &mut dao,
+            "work",
+            &Bookmark::new("/path/a", vec!["w".to_string()]),
+        )
+        .unwrap();
+
+        assert_eq!(dao.find_profiles().unwrap(), vec![D, "work"]);
+        assert_eq!(
+            dao.find_all(D).unwrap(),
+            vec![Bookmark::new("/path/a", vec![])]
+        );
+        assert_eq!(
+            dao.find_all("work").unwrap(),
+            vec![Bookmark::new("/path/a", vec!["w".to_string()])]
+        );
+        assert!(dao.create_profile("work").is_err());
+        assert!(dao.find_all("unknown").is_err());
+    }
+
+    #[test]
+    // 名前の変更と削除がブックマークにも反映されること
+    fn test_rename_and_delete_profile() {
+        let mut dao = setup(&[Bookmark::new("/path/a", vec!["t".to_string()])]);
+        dao.rename_profile(D, "personal").unwrap();
+
+        assert_eq!(dao.get_default_profile().unwrap(), "personal");
+        assert_eq!(
+            dao.find_all("personal").unwrap(),
+            vec![Bookmark::new("/path/a", vec!["t".to_string()])]
+        );
+
+        dao.create_profile("work").unwrap();
+        dao.set_default_profile("work").unwrap();
+        dao.delete_profile("personal").unwrap();
+        assert_eq!(dao.find_profiles().unwrap(), vec!["work"]);
+        assert!(tags_of(&dao, "t").is_empty());
+    }
+}
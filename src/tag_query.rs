@@ -0,0 +1,305 @@
+use crate::bookmark::Bookmark;
+use std::fmt::{Display, Formatter};
+
+/// タグの条件式 (例: `rust & !archived | infra`)
+///
+/// 優先順位は`!`、`&`、`|`の順で、括弧でまとめられる。空白や記号を含むタグは
+/// ダブルクォートで囲む (`"my tag"`、`"a\"b"`)。
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum TagQuery {
+    Tag(String),
+    Not(Box<TagQuery>),
+    And(Box<TagQuery>, Box<TagQuery>),
+    Or(Box<TagQuery>, Box<TagQuery>),
+}
+
+impl TagQuery {
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            input,
+            tokens,
+            pos: 0,
+        };
+        let query = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some((Token::Close, at)) => Err(parser.error(at, "unmatched ')'")),
+            Some((_, at)) => Err(parser.error(at, "expected '&', '|' or end of query")),
+        }
+    }
+
+    /// 全てのタグを持つことを表す条件。タグがなければ`None`
+    pub fn all_of(tags: &[String]) -> Option<Self> {
+        tags.iter()
+            .map(|tag| TagQuery::Tag(tag.clone()))
+            .reduce(|a, b| TagQuery::And(Box::new(a), Box::new(b)))
+    }
+
+    pub fn and(self, other: TagQuery) -> Self {
+        TagQuery::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn matches(&self, bookmark: &Bookmark) -> bool {
+        match self {
+            TagQuery::Tag(tag) => bookmark.get_tags().contains(tag),
+            TagQuery::Not(query) => !query.matches(bookmark),
+            TagQuery::And(a, b) => a.matches(bookmark) && b.matches(bookmark),
+            TagQuery::Or(a, b) => a.matches(bookmark) || b.matches(bookmark),
+        }
+    }
+}
+
+/// 条件式の構文エラー。表示すると式と誤りの位置を示す
+#[derive(PartialEq, Eq, Debug)]
+pub struct ParseError {
+    input: String,
+    /// 誤りの位置 (文字数)
+    position: usize,
+    message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "invalid tag query: {}", self.message)?;
+        writeln!(f, "  {}", self.input)?;
+        write!(f, "  {}^", " ".repeat(self.position))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+enum Token {
+    Tag(String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn is_tag_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '!' | '&' | '|' | '(' | ')' | '"')
+}
+
+/// 字句に分け、それぞれの開始位置 (文字数) と組にする
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let error = |position, message: &str| ParseError {
+        input: input.to_string(),
+        position,
+        message: message.to_string(),
+    };
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '!' => Token::Not,
+            '&' => Token::And,
+            '|' => Token::Or,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '"' => {
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => tag.push(c),
+                            Some((j, _)) => return Err(error(j, "invalid escape in quoted tag")),
+                            None => return Err(error(i, "unterminated quoted tag")),
+                        },
+                        Some((_, c)) => tag.push(c),
+                        None => return Err(error(i, "unterminated quoted tag")),
+                    }
+                }
+                if tag.is_empty() {
+                    return Err(error(i, "empty tag"));
+                }
+                Token::Tag(tag)
+            }
+            c => {
+                let mut tag = c.to_string();
+                while let Some((_, c)) = chars.next_if(|(_, c)| is_tag_char(*c)) {
+                    tag.push(c);
+                }
+                Token::Tag(tag)
+            }
+        };
+        tokens.push((token, i));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<(Token, usize)> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    /// 次の字句の位置 (末尾なら式の長さ)
+    fn position(&self) -> usize {
+        self.peek()
+            .map(|(_, at)| at)
+            .unwrap_or_else(|| self.input.chars().count())
+    }
+
+    fn error(&self, position: usize, message: &str) -> ParseError {
+        ParseError {
+            input: self.input.to_string(),
+            position,
+            message: message.to_string(),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<TagQuery, ParseError> {
+        let mut query = self.parse_and()?;
+        while let Some((Token::Or, _)) = self.peek() {
+            self.pos += 1;
+            query = TagQuery::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<TagQuery, ParseError> {
+        let mut query = self.parse_not()?;
+        while let Some((Token::And, _)) = self.peek() {
+            self.pos += 1;
+            query = TagQuery::And(Box::new(query), Box::new(self.parse_not()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_not(&mut self) -> Result<TagQuery, ParseError> {
+        if let Some((Token::Not, _)) = self.peek() {
+            self.pos += 1;
+            return Ok(TagQuery::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<TagQuery, ParseError> {
+        let at = self.position();
+        match self.peek() {
+            Some((Token::Tag(tag), _)) => {
+                self.pos += 1;
+                Ok(TagQuery::Tag(tag))
+            }
+            Some((Token::Open, _)) => {
+                self.pos += 1;
+                let query = self.parse_or()?;
+                match self.peek() {
+                    Some((Token::Close, _)) => {
+                        self.pos += 1;
+                        Ok(query)
+                    }
+                    _ => Err(self.error(at, "unclosed '('")),
+                }
+            }
+            Some((token, _)) => {
+                let found = match token {
+                    Token::And => "'&'",
+                    Token::Or => "'|'",
+                    Token::Close => "')'",
+                    _ => "unexpected token",
+                };
+                Err(self.error(at, &format!("expected a tag or '(', found {}", found)))
+            }
+            None => Err(self.error(at, "expected a tag or '(', found end of query")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn tag(name: &str) -> TagQuery {
+        TagQuery::Tag(name.to_string())
+    }
+
+    fn not(q: TagQuery) -> TagQuery {
+        TagQuery::Not(Box::new(q))
+    }
+
+    fn and(a: TagQuery, b: TagQuery) -> TagQuery {
+        TagQuery::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: TagQuery, b: TagQuery) -> TagQuery {
+        TagQuery::Or(Box::new(a), Box::new(b))
+    }
+
+    #[rstest]
+    #[case("rust", tag("rust"))]
+    // `&`は`|`より優先されること
+    #[case(
+        "rust & !archived | infra",
+        or(and(tag("rust"), not(tag("archived"))), tag("infra"))
+    )]
+    #[case("infra | rust & go", or(tag("infra"), and(tag("rust"), tag("go"))))]
+    // `!`は`&`より優先されること
+    #[case("!a & b", and(not(tag("a")), tag("b")))]
+    #[case("!!a", not(not(tag("a"))))]
+    // 括弧で優先順位を変えられること
+    #[case("!(a | b) & c", and(not(or(tag("a"), tag("b"))), tag("c")))]
+    // 左結合であること
+    #[case("a|b|c", or(or(tag("a"), tag("b")), tag("c")))]
+    // クォートされたタグ
+    #[case(r#""my tag" & "a\"b""#, and(tag("my tag"), tag("a\"b")))]
+    #[case("c++ & node.js", and(tag("c++"), tag("node.js")))]
+    fn test_parse(#[case] input: &str, #[case] expected: TagQuery) {
+        assert_eq!(TagQuery::parse(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("", 0, "expected a tag or '(', found end of query")]
+    #[case("rust &", 6, "expected a tag or '(', found end of query")]
+    #[case("rust & | infra", 7, "expected a tag or '(', found '|'")]
+    #[case("(rust | go", 0, "unclosed '('")]
+    #[case("rust)", 4, "unmatched ')'")]
+    #[case("rust infra", 5, "expected '&', '|' or end of query")]
+    #[case("\"rust", 0, "unterminated quoted tag")]
+    #[case("\"\"", 0, "empty tag")]
+    fn test_parse_invalid(#[case] input: &str, #[case] position: usize, #[case] message: &str) {
+        let error = TagQuery::parse(input).unwrap_err();
+        assert_eq!(error.position, position);
+        assert_eq!(error.message, message);
+    }
+
+    #[test]
+    // エラーの位置が示されること
+    fn test_parse_error_display() {
+        let error = TagQuery::parse("rust & | infra").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid tag query: expected a tag or '(', found '|'\n  rust & | infra\n         ^"
+        );
+    }
+
+    #[rstest]
+    #[case("rust & !archived | infra", vec!["rust"], true)]
+    #[case("rust & !archived | infra", vec!["rust", "archived"], false)]
+    #[case("rust & !archived | infra", vec!["archived", "infra"], true)]
+    #[case("rust & !archived | infra", vec![], false)]
+    #[case("!(a | b)", vec!["c"], true)]
+    #[case("!(a | b)", vec!["b"], false)]
+    fn test_matches(#[case] input: &str, #[case] tags: Vec<&str>, #[case] expected: bool) {
+        let bookmark = Bookmark::new("/path", tags.into_iter().map(String::from).collect());
+        assert_eq!(TagQuery::parse(input).unwrap().matches(&bookmark), expected);
+    }
+
+    #[test]
+    fn test_all_of() {
+        let tags = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(TagQuery::all_of(&tags), Some(and(tag("a"), tag("b"))));
+        assert_eq!(TagQuery::all_of(&[]), None);
+    }
+}
@@ -1,19 +1,37 @@
+use clap::ValueEnum;
 use console::Emoji;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
 
-use crate::bookmark::Bookmark;
-use crate::path::PathOps;
-use crate::repository::IBookmarkRepository;
+use crate::bookmark::{Bookmark, Kind};
+use crate::clock::Clock;
+use crate::editor::Editor;
+use crate::frecency;
+use crate::fuzzy;
+use crate::import::{Entry, Skipped};
+use crate::journal::{History, Operation};
+use crate::mount::MountTable;
+use crate::path::{is_portable, PathOps};
+use crate::relocate::{Candidate, Relocator};
+use crate::repository::{validate_name, IBookmarkRepository};
 use crate::selector::BookmarkSelector;
+use crate::tag_query::TagQuery;
 
+/// パスを正規化して登録する。正規化したパスが登録済みなら置き換える
+///
+/// `portable`なら`~`や設定したルートからのパス (`~/src/api`や`$WORKSPACE/api`) として保存する
 pub fn add_bookmark(
     bookmark_repo: &mut dyn IBookmarkRepository,
     path_ops: &dyn PathOps,
-    path: Option<String>,
+    path: Option<PathBuf>,
+    name: Option<String>,
     tags: Vec<String>,
+    kind: Kind,
+    portable: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let path = match path {
         Some(p) => {
-            if p.is_empty() {
+            if p.as_os_str().is_empty() {
                 path_ops.get_current_dir()?
             } else {
                 p
@@ -21,228 +39,3233 @@ pub fn add_bookmark(
         }
         None => path_ops.get_current_dir()?,
     };
+    let path = path_ops.canonicalize(&path)?;
     if !path_ops.exists(&path) {
-        return Err(format!("Path does not exist: {}", path).into());
+        return Err(format!("Path does not exist: {}", path.display()).into());
     }
-    if !path_ops.is_dir(&path) {
-        return Err(format!("Path is not a directory: {}", path).into());
+    check_kind(path_ops, &path, kind)?;
+
+    let stored = if portable {
+        path_ops.contract(&path).ok_or_else(|| {
+            format!(
+                "Path is not under the home directory or a configured root: {}",
+                path.display()
+            )
+        })?
+    } else {
+        path.clone()
+    };
+
+    let fingerprint = path_ops.fingerprint(&path);
+    let mut bookmark = Bookmark::new(&stored, tags)
+        .with_kind(kind)
+        .with_name(name)
+        .with_fingerprint(fingerprint);
+    // 登録し直しても訪問の記録は残す
+    let existing = bookmark_repo.find_all()?.into_iter().find(|b| {
+        b.get_path() == stored
+            || expand_bookmark(path_ops, b).is_ok_and(|expanded| expanded.get_path() == path)
+    });
+    match existing {
+        // 同じ場所を別の形で保存していれば (絶対パスと`~/`からのパスなど)、保存し直す形に置き換える
+        Some(existing) if existing.get_path() != stored => {
+            bookmark.inherit_visits(&existing);
+            Ok(bookmark_repo.update_all(&mut |bookmarks| {
+                for b in bookmarks.iter_mut() {
+                    if b.get_path() == existing.get_path() {
+                        *b = bookmark.clone();
+                    }
+                }
+                Ok(())
+            })?)
+        }
+        existing => {
+            if let Some(existing) = existing {
+                bookmark.inherit_visits(&existing);
+            }
+            Ok(bookmark_repo.save(&bookmark)?)
+        }
+    }
+}
+
+/// 保存されたパス (`~`や`$NAME`で始まるもの) を展開したブックマークを返す
+pub fn expand_bookmark(path_ops: &dyn PathOps, bookmark: &Bookmark) -> Result<Bookmark, Error> {
+    let mut expanded = bookmark.clone();
+    if is_portable(bookmark.get_path()) {
+        let fingerprint = bookmark.get_fingerprint().cloned();
+        expanded.relocate(path_ops.expand(bookmark.get_path())?, fingerprint);
+    }
+    Ok(expanded)
+}
+
+/// 元のパスが`~`や`$NAME`で始まっていれば、新しいパスも同じ形で保存する (できなければ絶対パスのまま)
+fn keep_portable(path_ops: &dyn PathOps, old: &Path, new: PathBuf) -> PathBuf {
+    if is_portable(old) {
+        path_ops.contract(&new).unwrap_or(new)
+    } else {
+        new
     }
+}
 
-    let bookmark = Bookmark::new(&path, tags); // Pass tags to Bookmark
-    Ok(bookmark_repo.save(&bookmark)?)
+/// パスがブックマークの種類 (ディレクトリかファイルか) と合っているか確かめる
+fn check_kind(path_ops: &dyn PathOps, path: &Path, kind: Kind) -> Result<(), Error> {
+    let message = match kind {
+        Kind::Dir if !path_ops.is_dir(path) => "Path is not a directory",
+        Kind::File if path_ops.is_dir(path) => "Path is a directory",
+        _ => return Ok(()),
+    };
+    Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!("{}: {}", message, path.display()),
+    ))
 }
 
+/// 選択したブックマークをまとめて削除し、削除したブックマークを返す
 pub fn delete_bookmark(
     bookmark_repo: &mut dyn IBookmarkRepository,
     selector: &dyn BookmarkSelector,
-) -> Result<(), Box<dyn std::error::Error>> {
+    filter: Option<&TagQuery>,
+) -> Result<Vec<Bookmark>, Box<dyn std::error::Error>> {
+    let bookmarks = find_filtered(bookmark_repo, filter)?;
+    let prompt = format!(
+        "{} Select bookmarks to delete (space to select, enter to confirm): ",
+        Emoji("🗑️", "")
+    );
+    let labels: Vec<String> = bookmarks.iter().map(|b| b.to_string()).collect();
+    let selected = selector.select_many(&labels, prompt, false)?;
+    let selected: Vec<Bookmark> = bookmarks
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| selected.contains(i))
+        .map(|(_, b)| b)
+        .collect();
+    if selected.is_empty() {
+        return Ok(selected);
+    }
+    let paths: Vec<PathBuf> = selected
+        .iter()
+        .map(|b| b.get_path().to_path_buf())
+        .collect();
+    bookmark_repo.delete(&paths)?;
+    Ok(selected)
+}
+
+/// 名前かパスで指定したブックマークをまとめて削除し、削除したブックマークを返す。
+/// 見つからないものが1つでもあれば何も削除しない
+pub fn delete_bookmarks_by_target(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    targets: &[String],
+) -> Result<Vec<Bookmark>, Box<dyn std::error::Error>> {
+    let current_dir = path_ops.get_current_dir()?;
+    let mut deleted = Vec::new();
+    bookmark_repo.update_all(&mut |bookmarks| {
+        deleted.clear();
+        for target in targets {
+            let bookmark = find_target(bookmarks, path_ops, &current_dir, target)?.clone();
+            if !deleted.contains(&bookmark) {
+                deleted.push(bookmark);
+            }
+        }
+        bookmarks.retain(|b| !deleted.iter().any(|d| d.get_path() == b.get_path()));
+        Ok(())
+    })?;
+    Ok(deleted)
+}
+
+/// ブックマークを選択する。選択肢はよく使うものから並べ、選ばれたものは訪問を記録する
+pub fn search_bookmark(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    selector: &dyn BookmarkSelector,
+    clock: &dyn Clock,
+    filter: Option<&TagQuery>,
+) -> Result<Option<Bookmark>, Box<dyn std::error::Error>> {
+    let mut bookmarks = find_filtered(bookmark_repo, filter)?;
+    frecency::sort_by(&mut bookmarks, clock.now(), |b| b);
+    let bookmark = select_bookmark(selector, &bookmarks)?;
+    if let Some(bookmark) = &bookmark {
+        record_visit(bookmark_repo, clock, bookmark)?;
+    }
+    Ok(bookmark)
+}
+
+/// `list`の並び順
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum SortOrder {
+    /// 登録順
+    #[default]
+    Added,
+    /// よく使う順
+    Frecency,
+}
+
+/// クエリによる検索の結果
+#[derive(PartialEq, Debug)]
+pub enum QueryResult<T> {
+    /// 1件に決まった (一致が1件だけだったか、候補から選択された)
+    Selected(T),
+    /// 一致するものがない
+    NoMatch,
+    /// 複数一致し、対話的に選択できない (一致の良い順)
+    Ambiguous(Vec<T>),
+    /// 候補からの選択が取り消された
+    Cancelled,
+}
+
+impl<T> QueryResult<T> {
+    pub fn map<U>(self, f: impl Fn(T) -> U) -> QueryResult<U> {
+        match self {
+            QueryResult::Selected(item) => QueryResult::Selected(f(item)),
+            QueryResult::NoMatch => QueryResult::NoMatch,
+            QueryResult::Ambiguous(items) => {
+                QueryResult::Ambiguous(items.into_iter().map(f).collect())
+            }
+            QueryResult::Cancelled => QueryResult::Cancelled,
+        }
+    }
+}
+
+/// クエリに一致するブックマークを一致の良い順に取得する
+pub fn find_bookmarks_by_query(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    query: &str,
+    filter: Option<&TagQuery>,
+) -> Result<Vec<Bookmark>, Box<dyn std::error::Error>> {
+    let bookmarks = find_filtered(bookmark_repo, filter)?;
+    Ok(fuzzy::rank(query, bookmarks, |b| b.to_string()))
+}
+
+/// クエリでブックマークを探す
+///
+/// 一致が1件だけならそれを選び、複数あれば`interactive`の場合だけ一致したものから選択させる
+pub fn search_bookmark_by_query(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    selector: &dyn BookmarkSelector,
+    clock: &dyn Clock,
+    query: &str,
+    interactive: bool,
+    filter: Option<&TagQuery>,
+) -> Result<QueryResult<Bookmark>, Box<dyn std::error::Error>> {
+    let matches = find_bookmarks_by_query(bookmark_repo, query, filter)?;
+    let labels: Vec<String> = matches.iter().map(|b| b.to_string()).collect();
+    let result = resolve_query(selector, matches, &labels, interactive)?;
+    if let QueryResult::Selected(bookmark) = &result {
+        record_visit(bookmark_repo, clock, bookmark)?;
+    }
+    Ok(result)
+}
+
+/// 全てのプロファイルからクエリに一致するブックマークを一致の良い順に取得する
+pub fn find_bookmarks_in_all_profiles_by_query(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    query: &str,
+    filter: Option<&TagQuery>,
+) -> Result<Vec<(String, Bookmark)>, Box<dyn std::error::Error>> {
+    let bookmarks = collect_all_profiles(bookmark_repo, filter)?;
+    Ok(fuzzy::rank(query, bookmarks, |(profile, bookmark)| {
+        format!("[{}] {}", profile, bookmark)
+    }))
+}
+
+/// 全てのプロファイルからクエリでブックマークを探す
+pub fn search_bookmark_in_all_profiles_by_query(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    selector: &dyn BookmarkSelector,
+    clock: &dyn Clock,
+    query: &str,
+    interactive: bool,
+    filter: Option<&TagQuery>,
+) -> Result<QueryResult<(String, Bookmark)>, Box<dyn std::error::Error>> {
+    let matches = find_bookmarks_in_all_profiles_by_query(bookmark_repo, query, filter)?;
+    let labels: Vec<String> = matches
+        .iter()
+        .map(|(profile, bookmark)| format!("[{}] {}", profile, bookmark))
+        .collect();
+    let result = resolve_query(selector, matches, &labels, interactive)?;
+    if let QueryResult::Selected((profile, bookmark)) = &result {
+        record_visit_in_profile(bookmark_repo, clock, profile, bookmark)?;
+    }
+    Ok(result)
+}
+
+/// ファイルのブックマークを選んでエディタで開く
+///
+/// クエリがなければよく使うものから並べて選択させ、クエリがあれば`search_bookmark_by_query`と同じように選ぶ
+#[allow(clippy::too_many_arguments)]
+pub fn open_bookmark(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    selector: &dyn BookmarkSelector,
+    editor: &dyn Editor,
+    path_ops: &dyn PathOps,
+    clock: &dyn Clock,
+    query: Option<&str>,
+    interactive: bool,
+    filter: Option<&TagQuery>,
+) -> Result<QueryResult<Bookmark>, Box<dyn std::error::Error>> {
+    let mut files = find_filtered(bookmark_repo, filter)?;
+    files.retain(|b| b.get_kind() == Kind::File);
+    let result = match query {
+        Some(query) => {
+            let matches = fuzzy::rank(query, files, |b| b.to_string());
+            let labels: Vec<String> = matches.iter().map(|b| b.to_string()).collect();
+            resolve_query(selector, matches, &labels, interactive)?
+        }
+        None if files.is_empty() => return Err("No file bookmarks".into()),
+        None => {
+            frecency::sort_by(&mut files, clock.now(), |b| b);
+            match select_bookmark(selector, &files)? {
+                Some(bookmark) => QueryResult::Selected(bookmark),
+                None => QueryResult::Cancelled,
+            }
+        }
+    };
+    if let QueryResult::Selected(bookmark) = &result {
+        record_visit(bookmark_repo, clock, bookmark)?;
+        editor.open(expand_bookmark(path_ops, bookmark)?.get_path())?;
+    }
+    Ok(result)
+}
+
+fn resolve_query<T>(
+    selector: &dyn BookmarkSelector,
+    mut matches: Vec<T>,
+    labels: &[String],
+    interactive: bool,
+) -> Result<QueryResult<T>, Box<dyn std::error::Error>> {
+    match matches.len() {
+        0 => Ok(QueryResult::NoMatch),
+        1 => Ok(QueryResult::Selected(matches.remove(0))),
+        _ if !interactive => Ok(QueryResult::Ambiguous(matches)),
+        _ => {
+            let prompt = format!("{} Select a bookmark (type to filter): ", Emoji("🔖", ""));
+            match selector.select_index(labels, prompt)? {
+                Some(i) => Ok(QueryResult::Selected(matches.swap_remove(i))),
+                None => Ok(QueryResult::Cancelled),
+            }
+        }
+    }
+}
+
+/// 別名でブックマークを探す。見つからなければ似た別名を候補としてエラーに含める
+pub fn go_bookmark(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    clock: &dyn Clock,
+    name: &str,
+) -> Result<Bookmark, Box<dyn std::error::Error>> {
     let bookmarks = bookmark_repo.find_all()?;
-    if let Some(bookmark) = select_bookmark(selector, &bookmarks)? {
-        bookmark_repo.delete(&bookmark.get_path())?;
+    if let Some(bookmark) = bookmarks.iter().find(|b| b.get_name() == Some(name)) {
+        record_visit(bookmark_repo, clock, bookmark)?;
+        return Ok(bookmark.clone());
+    }
+    let suggestions = suggest_names(name, bookmarks.iter().filter_map(|b| b.get_name()));
+    if suggestions.is_empty() {
+        Err(format!("No bookmark named '{}'", name).into())
+    } else {
+        Err(format!(
+            "No bookmark named '{}' (did you mean: {}?)",
+            name,
+            suggestions.join(", ")
+        )
+        .into())
+    }
+}
+
+pub fn list_bookmarks(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    clock: &dyn Clock,
+    sort: SortOrder,
+    filter: Option<&TagQuery>,
+) -> Result<Vec<Bookmark>, Box<dyn std::error::Error>> {
+    let mut bookmarks = find_filtered(bookmark_repo, filter)?;
+    if sort == SortOrder::Frecency {
+        frecency::sort_by(&mut bookmarks, clock.now(), |b| b);
+    }
+    Ok(bookmarks)
+}
+
+/// よく使うブックマークを、スコアとともに最大`limit`件取得する (訪問したことがないものは除く)
+pub fn bookmark_stats(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    clock: &dyn Clock,
+    limit: usize,
+) -> Result<Vec<(f64, Bookmark)>, Box<dyn std::error::Error>> {
+    let now = clock.now();
+    let mut bookmarks: Vec<Bookmark> = bookmark_repo
+        .find_all()?
+        .into_iter()
+        .filter(|b| b.get_visits() > 0)
+        .collect();
+    frecency::sort_by(&mut bookmarks, now, |b| b);
+    Ok(bookmarks
+        .into_iter()
+        .take(limit)
+        .map(|b| (frecency::score(&b, now), b))
+        .collect())
+}
+
+/// `prune`の動作
+#[derive(Clone, Copy, Debug)]
+pub struct PruneOptions {
+    /// 削除せずに対象だけを返す
+    pub dry_run: bool,
+    /// 削除するブックマークを選ばせる
+    pub interactive: bool,
+    /// 一度に削除してよいブックマークの割合
+    pub max_fraction: f64,
+    /// 割合の上限やマウントされていないドライブの確認をしない
+    pub force: bool,
+}
+
+/// `prune`で削除された (`dry_run`なら削除される) ブックマークと、
+/// マウントされていないドライブの下にあるため残したブックマーク
+#[derive(PartialEq, Eq, Debug, Default)]
+pub struct PruneReport {
+    pub deleted: Vec<Bookmark>,
+    pub skipped: Vec<(Bookmark, PathBuf)>,
+}
+
+/// 壊れたブックマークを削除する
+///
+/// ドライブが一時的に外れているだけの場合に備え、マウントされていないマウントポイントの下の
+/// ブックマークは残し、全体の`max_fraction`を超えて削除することになる場合は中止する。
+/// 対話的に選んだ場合は割合の上限を確認しない。
+pub fn prune_bookmarks(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    mounts: &dyn MountTable,
+    selector: &dyn BookmarkSelector,
+    options: PruneOptions,
+    filter: Option<&TagQuery>,
+) -> Result<PruneReport, Box<dyn std::error::Error>> {
+    let total = bookmark_repo.find_all()?.len();
+    let mut report = PruneReport::default();
+    let mut broken = Vec::new();
+    for bookmark in find_filtered(bookmark_repo, filter)? {
+        // このマシンでは展開できないパス (設定されていない`$NAME`など) は壊れているとみなさない
+        let Ok(expanded) = expand_bookmark(path_ops, &bookmark) else {
+            continue;
+        };
+        if !expanded.is_broken()? {
+            continue;
+        }
+        match mounts.unmounted_mount_point(expanded.get_path()) {
+            Some(mount_point) if !options.force => report.skipped.push((bookmark, mount_point)),
+            _ => broken.push(bookmark),
+        }
+    }
+
+    if options.interactive {
+        let labels: Vec<String> = broken.iter().map(|b| b.to_string()).collect();
+        let prompt = format!(
+            "{} Select bookmarks to delete (space to toggle): ",
+            Emoji("🗑️", "")
+        );
+        let selected = selector.select_many(&labels, prompt, true)?;
+        broken = broken
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| selected.contains(i))
+            .map(|(_, b)| b)
+            .collect();
+    } else if !options.force {
+        // 試すだけの場合も、実際には中止されることが分かるよう確かめる
+        check_fraction(broken.len(), total, options.max_fraction)?;
+    }
+
+    if !options.dry_run && !broken.is_empty() {
+        bookmark_repo.update_all(&mut |bookmarks| {
+            bookmarks.retain(|b| broken.iter().all(|d| d.get_path() != b.get_path()));
+            Ok(())
+        })?;
+    }
+    report.deleted = broken;
+    Ok(report)
+}
+
+/// 削除する数が全体の`max_fraction`を超えていれば中止する
+fn check_fraction(
+    deleting: usize,
+    total: usize,
+    max_fraction: f64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if deleting as f64 > total as f64 * max_fraction {
+        return Err(format!(
+            "Refusing to delete {} of {} bookmarks (more than {}%). \
+             Check that all drives are mounted, then choose with --interactive or run with --force",
+            deleting,
+            total,
+            max_fraction * 100.0
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// 壊れたブックマークの移動先を探し、選ばれた場所を指すように直す
+///
+/// 候補がなくても残すか削除するかを選ばせ、削除が選ばれたものは`prune_bookmarks`と同じく削除する。
+/// 既に他のブックマークが指している場所は候補にしない。`options.force`がなければ、マウントされていない
+/// マウントポイントの下のブックマークは何もせずに残し、全体の`options.max_fraction`を超えて
+/// 削除することになる場合は何も変更せずに中止する (`dry_run`と`interactive`は使わない)。
+pub fn relocate_bookmarks(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    mounts: &dyn MountTable,
+    selector: &dyn BookmarkSelector,
+    relocator: &dyn Relocator,
+    options: PruneOptions,
+    filter: Option<&TagQuery>,
+) -> Result<RelocateSummary, Box<dyn std::error::Error>> {
+    let all: Vec<Bookmark> = bookmark_repo
+        .find_all()?
+        .iter()
+        .filter_map(|b| expand_bookmark(path_ops, b).ok())
+        .collect();
+    let total = all.len();
+    let mut summary = RelocateSummary::default();
+    for bookmark in find_filtered(bookmark_repo, filter)? {
+        let Ok(expanded) = expand_bookmark(path_ops, &bookmark) else {
+            continue;
+        };
+        if !expanded.is_broken()? {
+            continue;
+        }
+        if let Some(mount_point) = mounts.unmounted_mount_point(expanded.get_path()) {
+            if !options.force {
+                summary.skipped.push((bookmark, mount_point));
+                continue;
+            }
+        }
+        let candidates: Vec<Candidate> = relocator
+            .find_candidates(&expanded)
+            .into_iter()
+            .filter(|c| {
+                all.iter().all(|b| b.get_path() != c.path)
+                    && summary.relocated.iter().all(|(_, path)| *path != c.path)
+            })
+            .collect();
+        if candidates.is_empty() {
+            let labels = ["Keep the bookmark as it is", "Delete the bookmark"].map(String::from);
+            let prompt = format!("{} {} was not found elsewhere: ", Emoji("🚚", ""), bookmark);
+            if selector.select_index(&labels, prompt)? == Some(1) {
+                summary.deleted.push(bookmark);
+            }
+            continue;
+        }
+        let mut labels: Vec<String> = candidates
+            .iter()
+            .map(|c| format!("{} ({})", c.path.display(), c.reason))
+            .collect();
+        labels.push("Keep the bookmark as it is".to_string());
+        labels.push("Delete the bookmark".to_string());
+        let prompt = format!("{} {} has moved to: ", Emoji("🚚", ""), bookmark);
+        match selector.select_index(&labels, prompt)? {
+            Some(i) if i < candidates.len() => summary
+                .relocated
+                .push((bookmark, candidates[i].path.clone())),
+            Some(i) if i == candidates.len() + 1 => summary.deleted.push(bookmark),
+            _ => {}
+        }
+    }
+
+    if !options.force {
+        check_fraction(summary.deleted.len(), total, options.max_fraction)?;
+    }
+    bookmark_repo.update_all(&mut |bookmarks| {
+        bookmarks.retain(|b| summary.deleted.iter().all(|d| d.get_path() != b.get_path()));
+        for (old, path) in &summary.relocated {
+            if let Some(bookmark) = bookmarks
+                .iter_mut()
+                .find(|b| b.get_path() == old.get_path())
+            {
+                let new = keep_portable(path_ops, old.get_path(), path.clone());
+                bookmark.relocate(new, path_ops.fingerprint(path));
+            }
+        }
+        Ok(())
+    })?;
+    Ok(summary)
+}
+
+/// `relocate_bookmarks`で削除されたブックマークと、移動先を直したブックマーク、
+/// マウントされていないドライブの下にあるため残したブックマーク
+#[derive(PartialEq, Eq, Debug, Default)]
+pub struct RelocateSummary {
+    pub deleted: Vec<Bookmark>,
+    pub relocated: Vec<(Bookmark, PathBuf)>,
+    pub skipped: Vec<(Bookmark, PathBuf)>,
+}
+
+/// 別名またはパスで指定したブックマークを別の場所 (ファイルのブックマークなら別のファイル) に付け替える。
+/// 別名やタグ、訪問の記録は引き継ぐ
+pub fn move_bookmark(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    target: &str,
+    new_path: PathBuf,
+) -> Result<Bookmark, Box<dyn std::error::Error>> {
+    let current_dir = path_ops.get_current_dir()?;
+    let new_path = path_ops.canonicalize(&new_path)?;
+    if !path_ops.exists(&new_path) {
+        return Err(format!("Path does not exist: {}", new_path.display()).into());
+    }
+    let fingerprint = path_ops.fingerprint(&new_path);
+    let mut moved = None;
+    bookmark_repo.update_all(&mut |bookmarks| {
+        if let Some(existing) = bookmarks.iter().find(|b| {
+            expand_bookmark(path_ops, b).is_ok_and(|expanded| expanded.get_path() == new_path)
+        }) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Already bookmarked: {}", existing),
+            ));
+        }
+        let bookmark = find_target(bookmarks, path_ops, &current_dir, target)?;
+        check_kind(path_ops, &new_path, bookmark.get_kind())?;
+        let path = keep_portable(path_ops, bookmark.get_path(), new_path.clone());
+        bookmark.relocate(path, fingerprint.clone());
+        moved = Some(bookmark.clone());
+        Ok(())
+    })?;
+    Ok(moved.expect("the bookmark is moved on success"))
+}
+
+/// まとめたブックマークと、まとめる前のパス
+pub type Merged = (Bookmark, Vec<PathBuf>);
+
+/// 正規化すると同じになるパスのブックマークを1つにまとめ、保存されたパスも正規化する
+///
+/// 最初に登録されたものを残して他のものを`Bookmark::merge`で取り込む。
+/// 変更されたブックマークを、まとめる前のパスとともに返す (`dry_run`なら保存しない)
+pub fn dedupe_bookmarks(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    dry_run: bool,
+) -> Result<Vec<Merged>, Box<dyn std::error::Error>> {
+    if dry_run {
+        return Ok(merge_duplicates(&bookmark_repo.find_all()?, path_ops)
+            .into_iter()
+            .filter(is_merged)
+            .collect());
+    }
+    let mut merged = Vec::new();
+    bookmark_repo.update_all(&mut |bookmarks| {
+        merged = merge_duplicates(bookmarks, path_ops);
+        *bookmarks = merged
+            .iter()
+            .map(|(bookmark, _)| bookmark.clone())
+            .collect();
+        Ok(())
+    })?;
+    merged.retain(is_merged);
+    Ok(merged)
+}
+
+/// 他のブックマークをまとめたか、パスを正規化したか
+/// (`Path`の比較は末尾の`/`などを無視するため、文字列として比べる)
+fn is_merged((bookmark, sources): &Merged) -> bool {
+    match sources.as_slice() {
+        [source] => source.as_os_str() != bookmark.get_path().as_os_str(),
+        _ => true,
+    }
+}
+
+/// 正規化したパスごとにブックマークをまとめ、まとめる前のパスを添えて登録順に返す。
+/// 相対パスや正規化できないパスはそのまま残す
+fn merge_duplicates(bookmarks: &[Bookmark], path_ops: &dyn PathOps) -> Vec<Merged> {
+    let mut merged: Vec<Merged> = Vec::new();
+    // まとめたブックマークごとの、展開して正規化したパス
+    let mut keys: Vec<PathBuf> = Vec::new();
+    for bookmark in bookmarks {
+        let path = bookmark.get_path();
+        let canonical = expand_bookmark(path_ops, bookmark)
+            .ok()
+            .filter(|expanded| expanded.get_path().is_absolute())
+            .and_then(|expanded| path_ops.canonicalize(expanded.get_path()).ok());
+        let key = canonical.clone().unwrap_or_else(|| path.to_path_buf());
+        match keys.iter().position(|k| k.as_os_str() == key.as_os_str()) {
+            Some(i) => {
+                let (existing, sources) = &mut merged[i];
+                existing.merge(bookmark);
+                sources.push(path.to_path_buf());
+            }
+            None => {
+                // `~`や`$NAME`で始まるパスは、その形のまま正規化する
+                let stored = match canonical {
+                    Some(canonical) if is_portable(path) => path_ops
+                        .contract(&canonical)
+                        .unwrap_or_else(|| path.to_path_buf()),
+                    Some(canonical) => canonical,
+                    None => path.to_path_buf(),
+                };
+                let mut bookmark = bookmark.clone();
+                if path.as_os_str() != stored.as_os_str() {
+                    let fingerprint = bookmark.get_fingerprint().cloned();
+                    bookmark.relocate(stored, fingerprint);
+                }
+                keys.push(key);
+                merged.push((bookmark, vec![path.to_path_buf()]));
+            }
+        }
+    }
+    merged
+}
+
+/// 保存先の全てのプロファイルのブックマークを別の保存先にコピーし、コピーした件数を返す
+pub fn migrate_store(
+    src_repo: &mut dyn IBookmarkRepository,
+    dst_repo: &mut dyn IBookmarkRepository,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let dst_profiles = dst_repo.find_profiles()?;
+    let mut count = 0;
+    for profile in src_repo.find_profiles()? {
+        if !dst_profiles.contains(&profile) {
+            dst_repo.create_profile(&profile)?;
+        }
+        src_repo.select_profile(&profile)?;
+        dst_repo.select_profile(&profile)?;
+        let bookmarks = src_repo.find_all()?;
+        // プロファイルごとに1回で書き込む (同じパスのものはコピー元で置き換える)
+        dst_repo.update_all(&mut |dst| {
+            for bookmark in &bookmarks {
+                match dst.iter_mut().find(|b| b.get_path() == bookmark.get_path()) {
+                    Some(existing) => *existing = bookmark.clone(),
+                    None => dst.push(bookmark.clone()),
+                }
+            }
+            Ok(())
+        })?;
+        count += bookmarks.len();
+    }
+    Ok(count)
+}
+
+/// `import`の動作
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportOptions {
+    /// ツールのスコアを訪問回数に、最後に使われた時刻を最後の訪問の時刻にする
+    /// (時刻が記録されていなければ取り込んだ時刻にする)
+    pub with_scores: bool,
+    /// 存在しないディレクトリも取り込む
+    pub keep_missing: bool,
+}
+
+/// 他のツールから読み取った項目をブックマークとして追加する
+///
+/// 登録済みのディレクトリや存在しないディレクトリは取り込まず、理由とともに返す。使えない別名は付けずに取り込む
+pub fn import_bookmarks(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    clock: &dyn Clock,
+    entries: &[Entry],
+    options: ImportOptions,
+) -> Result<ImportSummary, Box<dyn std::error::Error>> {
+    let now = clock.now();
+    let mut summary = ImportSummary::default();
+    bookmark_repo.update_all(&mut |bookmarks| {
+        summary = ImportSummary::default();
+        for entry in entries {
+            let item = entry.bookmark.get_path().display().to_string();
+            let canonical = match entry.bookmark.get_path() {
+                path if path.is_absolute() => match path_ops.canonicalize(path) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        summary.skipped.push(Skipped {
+                            item,
+                            reason: e.to_string(),
+                        });
+                        continue;
+                    }
+                },
+                path => path.to_path_buf(),
+            };
+            let path = canonical.as_path();
+            // `~`や`$NAME`で始まるパスはそのまま取り込み、展開した場所を確かめる
+            let expanded = if is_portable(path) {
+                path_ops.expand(path).ok()
+            } else {
+                Some(path.to_path_buf())
+            };
+            let target = expanded.as_deref().unwrap_or(path);
+            // `~/src`と`/home/me/src`のように、展開すると同じ場所になるものも重複とみなす
+            let same_place = |b: &Bookmark| {
+                b.get_path() == path
+                    || expanded.as_deref().is_some_and(|expanded| {
+                        expand_bookmark(path_ops, b).is_ok_and(|b| b.get_path() == expanded)
+                    })
+            };
+            let is_dir = entry.bookmark.get_kind() == Kind::Dir;
+            let reason = if !path.is_absolute() && !is_portable(path) {
+                Some("not an absolute path")
+            } else if summary.imported.iter().any(same_place) {
+                Some("duplicate entry")
+            } else if bookmarks.iter().any(same_place) {
+                Some("already bookmarked")
+            } else if options.keep_missing {
+                None
+            } else if expanded.is_none() {
+                Some("cannot expand the path")
+            } else if !path_ops.exists(target) {
+                Some(if is_dir {
+                    "directory does not exist"
+                } else {
+                    "file does not exist"
+                })
+            } else if path_ops.is_dir(target) != is_dir {
+                Some(if is_dir {
+                    "not a directory"
+                } else {
+                    "not a file"
+                })
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                summary.skipped.push(Skipped {
+                    item,
+                    reason: reason.to_string(),
+                });
+                continue;
+            }
+
+            let mut bookmark = entry.bookmark.clone();
+            if bookmark.get_path() != path {
+                let fingerprint = bookmark.get_fingerprint().cloned();
+                bookmark.relocate(path.to_path_buf(), fingerprint);
+            }
+            if let Some(name) = bookmark.get_name() {
+                let reason = if validate_name("bookmark", name).is_err() {
+                    Some("not a valid bookmark name".to_string())
+                } else {
+                    bookmarks
+                        .iter()
+                        .find(|b| b.get_name() == Some(name))
+                        .map(|other| format!("already used by {}", other.get_path().display()))
+                };
+                if let Some(reason) = reason {
+                    summary.skipped.push(Skipped {
+                        item: format!("name '{}' of {}", name, item),
+                        reason: format!("{}; imported without the name", reason),
+                    });
+                    bookmark = bookmark.with_name(None);
+                }
+            }
+            if bookmark.get_fingerprint().is_none() {
+                bookmark = bookmark.with_fingerprint(path_ops.fingerprint(target));
+            }
+            let visits = entry
+                .score
+                .filter(|_| options.with_scores)
+                .map_or(0, |s| s.round() as u32);
+            if visits > 0 {
+                bookmark = bookmark.with_visits(visits, Some(entry.last_accessed.unwrap_or(now)));
+            }
+            bookmarks.push(bookmark.clone());
+            summary.imported.push(bookmark);
+        }
+        frecency::age(bookmarks);
+        Ok(())
+    })?;
+    Ok(summary)
+}
+
+/// `import_bookmarks`で追加したブックマークと、取り込まなかった項目
+#[derive(PartialEq, Eq, Debug, Default)]
+pub struct ImportSummary {
+    pub imported: Vec<Bookmark>,
+    pub skipped: Vec<Skipped>,
+}
+
+/// 全てのプロファイルのブックマークを、プロファイル名と組にして取得する
+pub fn list_bookmarks_in_all_profiles(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    clock: &dyn Clock,
+    sort: SortOrder,
+    filter: Option<&TagQuery>,
+) -> Result<Vec<(String, Bookmark)>, Box<dyn std::error::Error>> {
+    let mut bookmarks = collect_all_profiles(bookmark_repo, filter)?;
+    if sort == SortOrder::Frecency {
+        frecency::sort_by(&mut bookmarks, clock.now(), |(_, b)| b);
+    }
+    Ok(bookmarks)
+}
+
+fn collect_all_profiles(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    filter: Option<&TagQuery>,
+) -> Result<Vec<(String, Bookmark)>, Box<dyn std::error::Error>> {
+    let current = bookmark_repo.current_profile()?;
+    let mut result = Vec::new();
+    for profile in bookmark_repo.find_profiles()? {
+        bookmark_repo.select_profile(&profile)?;
+        for bookmark in find_filtered(bookmark_repo, filter)? {
+            result.push((profile.clone(), bookmark));
+        }
+    }
+    bookmark_repo.select_profile(&current)?;
+    Ok(result)
+}
+
+/// 全てのプロファイルのブックマークから選択する
+pub fn search_bookmark_in_all_profiles(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    selector: &dyn BookmarkSelector,
+    clock: &dyn Clock,
+    filter: Option<&TagQuery>,
+) -> Result<Option<(String, Bookmark)>, Box<dyn std::error::Error>> {
+    let mut bookmarks =
+        list_bookmarks_in_all_profiles(bookmark_repo, clock, SortOrder::Frecency, filter)?;
+    let labels: Vec<String> = bookmarks
+        .iter()
+        .map(|(profile, bookmark)| format!("[{}] {}", profile, bookmark))
+        .collect();
+    let prompt = format!("{} Select a bookmark (type to filter): ", Emoji("🔖", ""));
+    let selection = selector.select_index(&labels, prompt)?;
+    let selected = selection.map(|i| bookmarks.swap_remove(i));
+    if let Some((profile, bookmark)) = &selected {
+        record_visit_in_profile(bookmark_repo, clock, profile, bookmark)?;
+    }
+    Ok(selected)
+}
+
+/// プロファイルの名前と、デフォルトのプロファイルかどうかを取得する
+pub fn list_profiles(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+) -> Result<Vec<(String, bool)>, Box<dyn std::error::Error>> {
+    let default_profile = bookmark_repo.get_default_profile()?;
+    let profiles = bookmark_repo
+        .find_profiles()?
+        .into_iter()
+        .map(|profile| {
+            let is_default = profile == default_profile;
+            (profile, is_default)
+        })
+        .collect();
+    Ok(profiles)
+}
+
+/// 使用中のプロファイルの名前を取得する
+pub fn current_profile(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(bookmark_repo.current_profile()?)
+}
+
+pub fn create_profile(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(bookmark_repo.create_profile(name)?)
+}
+
+pub fn delete_profile(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(bookmark_repo.delete_profile(name)?)
+}
+
+pub fn rename_profile(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    from: &str,
+    to: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(bookmark_repo.rename_profile(from, to)?)
+}
+
+/// デフォルトのプロファイルを設定する。名前を省略した場合は現在のデフォルトを返す
+pub fn default_profile(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    name: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(name) = name {
+        bookmark_repo.set_default_profile(name)?;
+    }
+    Ok(bookmark_repo.get_default_profile()?)
+}
+
+/// 別名またはパスで指定したブックマークにタグを追加する
+pub fn add_tags(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    target: &str,
+    tags: Vec<String>,
+) -> Result<Bookmark, Box<dyn std::error::Error>> {
+    validate_tags(&tags)?;
+    update_tags(bookmark_repo, path_ops, target, |current| {
+        for tag in &tags {
+            if !current.contains(tag) {
+                current.push(tag.clone());
+            }
+        }
+    })
+}
+
+/// 別名またはパスで指定したブックマークからタグを取り除く
+pub fn remove_tags(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    target: &str,
+    tags: Vec<String>,
+) -> Result<Bookmark, Box<dyn std::error::Error>> {
+    update_tags(bookmark_repo, path_ops, target, |current| {
+        current.retain(|tag| !tags.contains(tag));
+    })
+}
+
+/// 別名またはパスで指定したブックマークのタグを置き換える
+pub fn set_tags(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    target: &str,
+    tags: Vec<String>,
+) -> Result<Bookmark, Box<dyn std::error::Error>> {
+    validate_tags(&tags)?;
+    update_tags(bookmark_repo, path_ops, target, |current| {
+        current.clear();
+        for tag in &tags {
+            if !current.contains(tag) {
+                current.push(tag.clone());
+            }
+        }
+    })
+}
+
+/// 別名またはパスで指定したブックマークを使えるホストを置き換える (空ならどのホストでも使えるようにする)
+pub fn set_hosts(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    target: &str,
+    hosts: Vec<String>,
+) -> Result<Bookmark, Box<dyn std::error::Error>> {
+    let mut unique: Vec<String> = Vec::new();
+    for host in hosts {
+        validate_name("host", &host)?;
+        if !unique.contains(&host) {
+            unique.push(host);
+        }
+    }
+    let current_dir = path_ops.get_current_dir()?;
+    let mut updated = None;
+    bookmark_repo.update_all(&mut |bookmarks| {
+        let bookmark = find_target(bookmarks, path_ops, &current_dir, target)?;
+        *bookmark = bookmark.clone().with_hosts(unique.clone());
+        updated = Some(bookmark.clone());
+        Ok(())
+    })?;
+    Ok(updated.expect("the bookmark is updated on success"))
+}
+
+/// 全てのブックマークでタグの名前を変える。書き換えたブックマークの数を返す
+pub fn rename_tag(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    old: &str,
+    new: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    validate_tags(&[new.to_string()])?;
+    let mut count = 0;
+    bookmark_repo.update_all(&mut |bookmarks| {
+        if bookmarks
+            .iter()
+            .any(|b| b.get_tags().iter().any(|t| t == new))
+        {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "Tag '{}' already exists (use `bm tag merge {} {}` to combine them)",
+                    new, old, new
+                ),
+            ));
+        }
+        count = replace_tag(bookmarks, old, new)?;
+        Ok(())
+    })?;
+    Ok(count)
+}
+
+/// 全てのブックマークで`from`のタグを`into`にまとめる。書き換えたブックマークの数を返す
+pub fn merge_tags(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    from: &str,
+    into: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    validate_tags(&[into.to_string()])?;
+    if from == into {
+        return Err(format!("Cannot merge tag '{}' into itself", from).into());
+    }
+    let mut count = 0;
+    bookmark_repo.update_all(&mut |bookmarks| {
+        count = replace_tag(bookmarks, from, into)?;
+        Ok(())
+    })?;
+    Ok(count)
+}
+
+/// 名前の付いたブックマークの名前を名前順に取得する
+pub fn bookmark_names(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut names: Vec<String> = bookmark_repo
+        .find_all()?
+        .iter()
+        .filter_map(|b| b.get_name().map(str::to_string))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// 全てのタグと、それが付いたブックマークの数を多い順に取得する
+pub fn count_tags(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+) -> Result<Vec<(String, usize)>, Box<dyn std::error::Error>> {
+    let mut counts = bookmark_repo.count_tags()?;
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    Ok(counts)
+}
+
+/// 最後の操作を取り消し、取り消した操作を返す
+pub fn undo_operation(history: &mut dyn History) -> Result<Operation, Box<dyn std::error::Error>> {
+    history.undo()?.ok_or_else(|| "Nothing to undo".into())
+}
+
+/// 最後に取り消した操作をやり直し、やり直した操作を返す
+pub fn redo_operation(history: &mut dyn History) -> Result<Operation, Box<dyn std::error::Error>> {
+    history.redo()?.ok_or_else(|| "Nothing to redo".into())
+}
+
+/// 新しい順に最大`limit`件の操作と、取り消されているかどうかを取得する
+pub fn list_history(
+    history: &mut dyn History,
+    limit: usize,
+) -> Result<Vec<(Operation, bool)>, Box<dyn std::error::Error>> {
+    Ok(history.history()?.into_iter().rev().take(limit).collect())
+}
+
+fn validate_tags(tags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if tags.iter().any(|tag| tag.is_empty()) {
+        return Err("Tag must not be empty".into());
+    }
+    Ok(())
+}
+
+/// 指定したブックマークのタグを書き換え、書き換えた後のブックマークを返す
+fn update_tags(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    path_ops: &dyn PathOps,
+    target: &str,
+    mut f: impl FnMut(&mut Vec<String>),
+) -> Result<Bookmark, Box<dyn std::error::Error>> {
+    let current_dir = path_ops.get_current_dir()?;
+    let mut updated = None;
+    bookmark_repo.update_all(&mut |bookmarks| {
+        let bookmark = find_target(bookmarks, path_ops, &current_dir, target)?;
+        let mut tags = bookmark.get_tags().to_vec();
+        f(&mut tags);
+        bookmark.set_tags(tags);
+        updated = Some(bookmark.clone());
+        Ok(())
+    })?;
+    Ok(updated.expect("the bookmark is updated on success"))
+}
+
+/// 別名で探し、なければパス (相対パスは現在のディレクトリから) で探す。
+/// `~/src`のように保存されたブックマークは展開したパスでも探す
+fn find_target<'a>(
+    bookmarks: &'a mut [Bookmark],
+    path_ops: &dyn PathOps,
+    current_dir: &Path,
+    target: &str,
+) -> Result<&'a mut Bookmark, Error> {
+    let index = bookmarks
+        .iter()
+        .position(|b| b.get_name() == Some(target))
+        .or_else(|| {
+            let path = current_dir.join(target);
+            bookmarks.iter().position(|b| {
+                b.get_path() == path
+                    || expand_bookmark(path_ops, b).is_ok_and(|b| b.get_path() == path)
+            })
+        });
+    match index {
+        Some(i) => Ok(&mut bookmarks[i]),
+        None => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No bookmark named or located at '{}'", target),
+        )),
+    }
+}
+
+/// `old`のタグを`new`に置き換える (既に`new`があれば`old`を取り除くだけにする)
+fn replace_tag(bookmarks: &mut [Bookmark], old: &str, new: &str) -> Result<usize, Error> {
+    let mut count = 0;
+    for bookmark in bookmarks.iter_mut() {
+        let Some(i) = bookmark.get_tags().iter().position(|t| t == old) else {
+            continue;
+        };
+        let mut tags = bookmark.get_tags().to_vec();
+        if tags.iter().any(|t| t == new) {
+            tags.remove(i);
+        } else {
+            tags[i] = new.to_string();
+        }
+        bookmark.set_tags(tags);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No bookmark is tagged '{}'", old),
+        ));
+    }
+    Ok(count)
+}
+
+/// `bm edit`で変更されたブックマークの数
+#[derive(PartialEq, Eq, Debug, Default)]
+pub struct EditSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+}
+
+/// 編集するファイルの先頭に付ける説明
+const EDIT_HELP: &str = "\
+# Edit the bookmarks and save the file to apply the changes.
+#
+# Each line is `path [@name] [#tag ...]`. Spaces, '#' at the start of a path
+# and control characters are escaped with a backslash.
+# Removing a line deletes the bookmark and a new line adds one.
+# Lines starting with '#' are ignored. Empty the file to abort.
+";
+/// エラーを示すコメントの接頭辞。再編集の際には取り除いてから付け直す
+const EDIT_ERROR_PREFIX: &str = "# error: ";
+
+/// ブックマークをエディタで編集し、差分を追加・更新・削除として反映する
+///
+/// 編集結果に誤りがあれば、該当する行の下にエラーのコメントを付けてエディタを開き直す。
+/// ファイルが空にされた場合は何もせずに`None`を返す。
+pub fn edit_bookmarks(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    editor: &dyn Editor,
+    path_ops: &dyn PathOps,
+    filter: Option<&TagQuery>,
+) -> Result<Option<EditSummary>, Box<dyn std::error::Error>> {
+    let all = bookmark_repo.find_all()?;
+    let (original, others): (Vec<Bookmark>, Vec<Bookmark>) = all
+        .into_iter()
+        .partition(|b| filter.is_none_or(|filter| filter.matches(b)));
+
+    let mut text = EDIT_HELP.to_string();
+    for bookmark in &original {
+        text.push_str(&format!("{}\n", bookmark));
+    }
+    let edited = loop {
+        let edited = editor.edit(&text)?;
+        if edited.trim().is_empty() {
+            return Ok(None);
+        }
+        match check_edited(&edited, &original, &others, path_ops) {
+            Ok(bookmarks) => break bookmarks,
+            // 誤りを直さずに保存された場合は、エディタを開き続けないよう中止する
+            Err(_) if edited == text => {
+                return Err("Aborted: the bookmarks still have errors".into())
+            }
+            Err(errors) => text = annotate_errors(&edited, &errors),
+        }
+    };
+
+    let mut summary = EditSummary::default();
+    bookmark_repo.update_all(&mut |bookmarks| {
+        summary = EditSummary::default();
+        for old in &original {
+            if edited.iter().all(|b| b.get_path() != old.get_path()) {
+                bookmarks.retain(|b| b.get_path() != old.get_path());
+                summary.deleted += 1;
+            }
+        }
+        for new in &edited {
+            match bookmarks
+                .iter_mut()
+                .find(|b| b.get_path() == new.get_path())
+            {
+                Some(existing) => {
+                    if existing.get_name() != new.get_name()
+                        || existing.get_tags() != new.get_tags()
+                    {
+                        let mut updated =
+                            existing.clone().with_name(new.get_name().map(String::from));
+                        updated.set_tags(new.get_tags().to_vec());
+                        *existing = updated;
+                        summary.updated += 1;
+                    }
+                }
+                None => {
+                    // 追加された行は、ディレクトリでなければファイルのブックマークにする
+                    let expanded = expand_bookmark(path_ops, new)?;
+                    let kind = if path_ops.is_dir(expanded.get_path()) {
+                        Kind::Dir
+                    } else {
+                        Kind::File
+                    };
+                    let fingerprint = path_ops.fingerprint(expanded.get_path());
+                    bookmarks.push(new.clone().with_kind(kind).with_fingerprint(fingerprint));
+                    summary.added += 1;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    Ok(Some(summary))
+}
+
+/// 編集されたテキストをパースする。誤りがあれば行番号 (0始まり) とその内容を返す
+fn check_edited(
+    text: &str,
+    original: &[Bookmark],
+    others: &[Bookmark],
+    path_ops: &dyn PathOps,
+) -> Result<Vec<Bookmark>, Vec<(usize, String)>> {
+    let mut bookmarks: Vec<(usize, Bookmark)> = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut bookmark = match Bookmark::parse(line) {
+            Ok(bookmark) => bookmark,
+            Err(e) => {
+                errors.push((i, e.to_string()));
+                continue;
+            }
+        };
+        let is_known = |path: &Path| original.iter().chain(others).any(|b| b.get_path() == path);
+        // 追加された行のパスは保存する形に直す
+        if bookmark.get_path().is_absolute() && !is_known(bookmark.get_path()) {
+            match path_ops.canonicalize(bookmark.get_path()) {
+                Ok(path) => bookmark.relocate(path, None),
+                Err(e) => {
+                    errors.push((i, e.to_string()));
+                    continue;
+                }
+            }
+        }
+        let path = bookmark.get_path();
+        let missing = match expand_bookmark(path_ops, &bookmark) {
+            _ if is_known(path) => None,
+            Ok(expanded) if path_ops.exists(expanded.get_path()) => None,
+            Ok(_) => Some(format!("path does not exist: {}", path.display())),
+            Err(e) => Some(e.to_string()),
+        };
+        let error = if !path.is_absolute() && !is_portable(path) {
+            Some(format!(
+                "path must be absolute or start with ~ or $NAME: {}",
+                path.display()
+            ))
+        } else if missing.is_some() {
+            missing
+        } else if let Some((j, _)) = bookmarks.iter().find(|(_, b)| b.get_path() == path) {
+            Some(format!("duplicate bookmark (also on line {})", j + 1))
+        } else if let Some(name) = bookmark.get_name() {
+            check_edited_name(name, path, &bookmarks, others)
+        } else {
+            None
+        };
+        match error {
+            Some(error) => errors.push((i, error)),
+            None => bookmarks.push((i, bookmark)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(bookmarks.into_iter().map(|(_, b)| b).collect())
+    } else {
+        Err(errors)
+    }
+}
+
+/// 別名が正しく、他のブックマークと重複していないか確かめる
+fn check_edited_name(
+    name: &str,
+    path: &Path,
+    edited: &[(usize, Bookmark)],
+    others: &[Bookmark],
+) -> Option<String> {
+    if let Err(e) = validate_name("bookmark", name) {
+        return Some(e.to_string());
+    }
+    if let Some((j, _)) = edited.iter().find(|(_, b)| b.get_name() == Some(name)) {
+        return Some(format!("bookmark name already in use on line {}", j + 1));
+    }
+    others
+        .iter()
+        .find(|b| b.get_name() == Some(name) && b.get_path() != path)
+        .map(|other| {
+            format!(
+                "bookmark name already in use: {} ({})",
+                name,
+                other.get_path().display()
+            )
+        })
+}
+
+/// 誤りのある行の下にエラーのコメントを付ける (前回付けたコメントは取り除く)
+fn annotate_errors(text: &str, errors: &[(usize, String)]) -> String {
+    let mut annotated = String::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.starts_with(EDIT_ERROR_PREFIX) {
+            continue;
+        }
+        annotated.push_str(line);
+        annotated.push('\n');
+        for (_, error) in errors.iter().filter(|(j, _)| *j == i) {
+            annotated.push_str(&format!("{}{}\n", EDIT_ERROR_PREFIX, error));
+        }
+    }
+    annotated
+}
+
+/// 条件に合うブックマークだけを取得する
+fn find_filtered(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    filter: Option<&TagQuery>,
+) -> Result<Vec<Bookmark>, Box<dyn std::error::Error>> {
+    match filter {
+        Some(filter) => Ok(bookmark_repo.find_by_tags(filter)?),
+        None => Ok(bookmark_repo.find_all()?),
+    }
+}
+
+/// ブックマークが選ばれたことを記録し、必要なら全体の訪問回数を縮める
+///
+/// 読み込んでから書き込むまでの間に他の変更が失われないよう、1つの操作として書き換える
+fn record_visit(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    clock: &dyn Clock,
+    bookmark: &Bookmark,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let now = clock.now();
+    bookmark_repo.update_all(&mut |bookmarks| {
+        if let Some(visited) = bookmarks
+            .iter_mut()
+            .find(|b| b.get_path() == bookmark.get_path())
+        {
+            visited.visit(now);
+            frecency::age(bookmarks);
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn record_visit_in_profile(
+    bookmark_repo: &mut dyn IBookmarkRepository,
+    clock: &dyn Clock,
+    profile: &str,
+    bookmark: &Bookmark,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let current = bookmark_repo.current_profile()?;
+    bookmark_repo.select_profile(profile)?;
+    let result = record_visit(bookmark_repo, clock, bookmark);
+    bookmark_repo.select_profile(&current)?;
+    result
+}
+
+/// 編集距離が近い順に、似た別名を最大3件返す
+fn suggest_names<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let name = name.to_lowercase();
+    let threshold = (name.chars().count() / 3).max(1);
+    let mut scored: Vec<(usize, &str)> = candidates
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            let distance = edit_distance(&name, &lower);
+            let is_prefix = lower.starts_with(&name) || name.starts_with(&lower);
+            (distance <= threshold || is_prefix).then_some((distance, candidate))
+        })
+        .collect();
+    scored.sort();
+    scored.into_iter().take(3).map(|(_, c)| c).collect()
+}
+
+/// レーベンシュタイン距離
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current.push((prev[j] + cost).min(prev[j + 1] + 1).min(current[j] + 1));
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+fn select_bookmark(
+    selector: &dyn BookmarkSelector,
+    bookmarks: &[Bookmark],
+) -> Result<Option<Bookmark>, dialoguer::Error> {
+    let prompt = format!("{} Select a bookmark (type to filter): ", Emoji("🔖", ""));
+    selector.select(bookmarks, prompt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookmark::Fingerprint;
+    use crate::clock::MockClock;
+    use crate::editor::MockEditor;
+    use crate::import::Entry;
+    use crate::journal::MockHistory;
+    use crate::mount::MockMountTable;
+    use crate::path::MockPathOps;
+    use crate::relocate::{MockRelocator, Reason};
+    use crate::repository::MockBookmarkRepository;
+    use crate::selector::MockBookmarkSelector;
+    use rstest::rstest;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn clock() -> MockClock {
+        let mut clock = MockClock::new();
+        clock.expect_now().return_const(NOW);
+        clock
+    }
+
+    #[test]
+    // ブックマークが登録されること
+    fn test_add_bookmark() {
+        let path = Some(PathBuf::from("/path/to/dir"));
+        let tags = vec!["tag1".to_string(), "tag2".to_string()]; // Add tags
+
+        let mut repo = MockBookmarkRepository::new(&[]);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_exists().returning(|_| true);
+        path_ops.expect_is_dir().returning(|_| true);
+        path_ops.expect_fingerprint().returning(|_| None);
+
+        let result = add_bookmark(
+            &mut repo,
+            &path_ops,
+            path,
+            None,
+            tags.clone(),
+            Kind::Dir,
+            false,
+        ); // Pass tags
+        assert!(result.is_ok());
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![Bookmark::new("/path/to/dir", tags)] // Check tags
+        );
+    }
+
+    #[rstest]
+    // パスが空文字列の場合、カレントディレクトリが登録されること
+    #[case(Some(PathBuf::new()), "/current/dir")]
+    // パスがNoneの場合、カレントディレクトリが登録されること
+    #[case(None, "/current/dir")]
+    fn test_add_bookmark_with_empty_or_none_path(
+        #[case] path: Option<PathBuf>,
+        #[case] expected_path: &str,
+    ) {
+        let tags = vec!["tag1".to_string(), "tag2".to_string()]; // Add tags
+        let mut repo = MockBookmarkRepository::new(&[]);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops
+            .expect_get_current_dir()
+            .returning(|| Ok(PathBuf::from("/current/dir")));
+        path_ops.expect_exists().returning(|_| true);
+        path_ops.expect_is_dir().returning(|_| true);
+        path_ops.expect_fingerprint().returning(|_| None);
+
+        let result = add_bookmark(
+            &mut repo,
+            &path_ops,
+            path,
+            None,
+            tags.clone(),
+            Kind::Dir,
+            false,
+        ); // Pass tags
+        assert!(result.is_ok());
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![Bookmark::new(expected_path, tags)] // Check tags
+        );
+    }
+
+    #[test]
+    // パスが存在しない場合、エラーが返ること
+    fn test_add_bookmark_with_nonexistent_path() {
+        let path = Some(PathBuf::from("/nonexistent/path"));
+
+        let mut repo = MockBookmarkRepository::new(&[]);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_exists().returning(|_| false);
+
+        let result = add_bookmark(&mut repo, &path_ops, path, None, vec![], Kind::Dir, false); // Pass empty tags
+        assert!(result.is_err());
+    }
+
+    #[test]
+    // パスがディレクトリでない場合、エラーが返ること
+    fn test_add_bookmark_with_non_dir_path() {
+        let path = Some(PathBuf::from("/file"));
+
+        let mut repo = MockBookmarkRepository::new(&[]);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_exists().returning(|_| true);
+        path_ops.expect_is_dir().returning(|_| false);
+
+        let result = add_bookmark(&mut repo, &path_ops, path, None, vec![], Kind::Dir, false); // Pass empty tags
+        assert!(result.is_err());
+    }
+
+    #[test]
+    // 正規化したパスで登録され、同じ場所のブックマークは置き換えられること
+    fn test_add_bookmark_normalizes_path() {
+        let mut repo =
+            MockBookmarkRepository::new(&[Bookmark::new("/srv/app", vec!["old".to_string()])]);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .withf(|p| p.as_os_str() == "/srv/./app/")
+            .returning(|_| Ok(PathBuf::from("/srv/app")));
+        path_ops.expect_exists().returning(|_| true);
+        path_ops.expect_is_dir().returning(|_| true);
+        path_ops.expect_fingerprint().returning(|_| None);
+
+        let path = Some(PathBuf::from("/srv/./app/"));
+        add_bookmark(&mut repo, &path_ops, path, None, vec![], Kind::Dir, false).unwrap();
+        let bookmarks = repo.find_all().unwrap();
+        assert_eq!(bookmarks, vec![Bookmark::new("/srv/app", vec![])]);
+        assert_eq!(bookmarks[0].get_path().as_os_str(), "/srv/app");
+    }
+
+    #[test]
+    // `~`からのパスで登録され、同じ場所を指す絶対パスのブックマークは訪問の記録ごと置き換えられること
+    fn test_add_bookmark_portable() {
+        let mut repo = MockBookmarkRepository::new(&[
+            Bookmark::new("/home/me/src/api", vec![]).with_visits(3, Some(10))
+        ]);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_exists().returning(|_| true);
+        path_ops.expect_is_dir().returning(|_| true);
+        path_ops.expect_fingerprint().returning(|_| None);
+        path_ops
+            .expect_contract()
+            .withf(|p| p == Path::new("/home/me/src/api"))
+            .returning(|_| Some(PathBuf::from("~/src/api")));
+        path_ops
+            .expect_expand()
+            .returning(|p| Ok(Path::new("/home/me").join(p.strip_prefix("~").unwrap())));
+
+        let path = Some(PathBuf::from("/home/me/src/api"));
+        add_bookmark(&mut repo, &path_ops, path, None, vec![], Kind::Dir, true).unwrap();
+        let bookmarks = repo.find_all().unwrap();
+        assert_eq!(bookmarks.len(), 1);
+        assert_eq!(bookmarks[0].get_path(), Path::new("~/src/api"));
+        assert_eq!(bookmarks[0].get_visits(), 3);
+    }
+
+    #[test]
+    // ホームディレクトリや設定したルートの下にないパスは登録できないこと
+    fn test_add_bookmark_portable_outside_roots() {
+        let mut repo = MockBookmarkRepository::new(&[]);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_exists().returning(|_| true);
+        path_ops.expect_is_dir().returning(|_| true);
+        path_ops.expect_contract().returning(|_| None);
+
+        let path = Some(PathBuf::from("/srv/app"));
+        let result = add_bookmark(&mut repo, &path_ops, path, None, vec![], Kind::Dir, true);
+        assert!(result.is_err());
+        assert!(repo.find_all().unwrap().is_empty());
+    }
+
+    #[rstest]
+    // ファイルはファイルのブックマークとして登録されること
+    #[case(false, true)]
+    // ディレクトリはファイルとして登録できないこと
+    #[case(true, false)]
+    fn test_add_file_bookmark(#[case] is_dir: bool, #[case] expected_ok: bool) {
+        let path = Some(PathBuf::from("/path/notes.md"));
+
+        let mut repo = MockBookmarkRepository::new(&[]);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_exists().returning(|_| true);
+        path_ops.expect_is_dir().return_const(is_dir);
+        path_ops.expect_fingerprint().returning(|_| None);
+
+        let result = add_bookmark(&mut repo, &path_ops, path, None, vec![], Kind::File, false);
+        assert_eq!(result.is_ok(), expected_ok);
+        if expected_ok {
+            assert_eq!(
+                repo.find_all().unwrap(),
+                vec![Bookmark::new("/path/notes.md", vec![]).with_kind(Kind::File)]
+            );
+        }
+    }
+
+    #[test]
+    // 選択した全てのブックマークが削除されること
+    fn test_delete_bookmark() {
+        let bookmarks = vec![
+            Bookmark::new("/path/a", vec![]),
+            Bookmark::new("/path/b", vec![]),
+            Bookmark::new("/path/c", vec![]),
+        ];
+
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let mut selector = MockBookmarkSelector::new();
+        selector
+            .expect_select_many()
+            .withf(|_, _, checked| !checked)
+            .returning(|_, _, _| Ok(vec![0, 2]));
+
+        let result = delete_bookmark(&mut repo, &selector, None);
+        assert_eq!(
+            result.unwrap(),
+            vec![bookmarks[0].clone(), bookmarks[2].clone()]
+        );
+        assert_eq!(repo.find_all().unwrap(), vec![bookmarks[1].clone()]);
+    }
+
+    #[test]
+    // 何も選択しなかった場合は何もせずに正常終了
+    fn test_delete_bookmark_no_match() {
+        let bookmark = Bookmark::new("/path/to/dir", vec![]);
+
+        let mut repo = MockBookmarkRepository::new(std::slice::from_ref(&bookmark));
+        let mut selector = MockBookmarkSelector::new();
+        selector
+            .expect_select_many()
+            .returning(|_, _, _| Ok(vec![]));
+
+        let result = delete_bookmark(&mut repo, &selector, None);
+        assert!(result.unwrap().is_empty());
+        assert_eq!(repo.find_all().unwrap(), vec![bookmark]);
+    }
+
+    #[rstest]
+    // 名前とパス (カレントディレクトリからの相対パスも) で指定できる
+    #[case(&["web", "/path/to/api"], Ok(vec!["/path/to/web", "/path/to/api"]))]
+    #[case(&["api"], Ok(vec!["/path/to/api"]))]
+    // 同じブックマークを重ねて指定しても1回だけ削除する
+    #[case(&["web", "/path/to/web"], Ok(vec!["/path/to/web"]))]
+    // 1つでも見つからなければ何も削除しない
+    #[case(&["web", "missing"], Err("No bookmark named or located at 'missing'"))]
+    fn test_delete_bookmarks_by_target(
+        #[case] targets: &[&str],
+        #[case] expected: Result<Vec<&str>, &str>,
+    ) {
+        let bookmarks = vec![
+            Bookmark::new("/path/to/web", vec![]).with_name(Some("web".to_string())),
+            Bookmark::new("/path/to/api", vec![]),
+        ];
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+
+        let result = delete_bookmarks_by_target(&mut repo, &current_dir("/path/to"), &targets);
+        match expected {
+            Ok(paths) => {
+                let deleted: Vec<PathBuf> = result
+                    .unwrap()
+                    .iter()
+                    .map(|b| b.get_path().to_path_buf())
+                    .collect();
+                assert_eq!(deleted, paths.iter().map(PathBuf::from).collect::<Vec<_>>());
+                assert_eq!(
+                    repo.find_all().unwrap().len(),
+                    bookmarks.len() - paths.len()
+                );
+            }
+            Err(message) => {
+                assert_eq!(result.unwrap_err().to_string(), message);
+                assert_eq!(repo.find_all().unwrap(), bookmarks);
+            }
+        }
+    }
+
+    #[test]
+    // 正常にブックマークが取得できること
+    fn test_search_bookmark() {
+        let bookmarks = vec![Bookmark::new("/path/to/dir", vec![])];
+
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let mut selector = MockBookmarkSelector::new();
+        selector
+            .expect_select()
+            .returning(|_, _| Ok(Some(Bookmark::new("/path/to/dir", vec![]))));
+
+        let result = search_bookmark(&mut repo, &selector, &clock(), None);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), Some(Bookmark::new("/path/to/dir", vec![])));
+    }
+
+    #[test]
+    // ブックマークが１件もない場合はNoneを返すこと
+    fn test_search_bookmark_no_bookmarks() {
+        let mut repo = MockBookmarkRepository::new(&[]);
+        let mut selector = MockBookmarkSelector::new();
+        selector.expect_select().returning(|_, _| Ok(None));
+
+        let result = search_bookmark(&mut repo, &selector, &clock(), None);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[test]
+    // 全てのブックマークが取得されること
+    fn test_list_bookmarks() {
+        let bookmarks = vec![
+            Bookmark::new("/path/to/dir1", vec![]),
+            Bookmark::new("/path/to/dir2", vec![]),
+        ];
+
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+
+        let result = list_bookmarks(&mut repo, &clock(), SortOrder::Added, None);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), bookmarks);
+    }
+
+    #[test]
+    // ブックマークがない場合は空のベクタが取得されること
+    fn test_list_bookmarks_empty() {
+        let mut repo = MockBookmarkRepository::new(&[]);
+
+        let result = list_bookmarks(&mut repo, &clock(), SortOrder::Added, None);
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    // 全てのブックマークがコピーされ、コピー先の既存のブックマークは残ること
+    fn test_migrate_store() {
+        let bookmarks = vec![
+            Bookmark::new("/path/to/dir1", vec!["tag".to_string()]),
+            Bookmark::new("/path/to/dir2", vec![]),
+        ];
+        let existing = Bookmark::new("/path/to/dir3", vec![]);
+        // コピー元と同じパスのものは置き換えられること
+        let stale = Bookmark::new("/path/to/dir2", vec!["old".to_string()]);
+
+        let mut src_repo = MockBookmarkRepository::new(&bookmarks);
+        let mut dst_repo = MockBookmarkRepository::new(&[existing.clone(), stale]);
+
+        let result = migrate_store(&mut src_repo, &mut dst_repo);
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(
+            dst_repo.find_all().unwrap(),
+            vec![existing, bookmarks[1].clone(), bookmarks[0].clone()]
+        );
+    }
+
+    #[test]
+    // プロファイルごとにコピーされ、コピー先にないプロファイルは作成されること
+    fn test_migrate_store_with_profiles() {
+        let mut src_repo = MockBookmarkRepository::new(&[Bookmark::new("/default", vec![])]);
+        src_repo.create_profile("work").unwrap();
+        src_repo.select_profile("work").unwrap();
+        src_repo.save(&Bookmark::new("/work", vec![])).unwrap();
+        let mut dst_repo = MockBookmarkRepository::new(&[]);
+
+        assert_eq!(migrate_store(&mut src_repo, &mut dst_repo).unwrap(), 2);
+        assert_eq!(dst_repo.find_profiles().unwrap(), vec!["default", "work"]);
+        dst_repo.select_profile("work").unwrap();
+        assert_eq!(
+            dst_repo.find_all().unwrap(),
+            vec![Bookmark::new("/work", vec![])]
+        );
+    }
+
+    fn entry(path: &str, name: Option<&str>, score: Option<f64>, last: Option<u64>) -> Entry {
+        Entry {
+            bookmark: Bookmark::new(path, vec![]).with_name(name.map(String::from)),
+            score,
+            last_accessed: last,
+        }
+    }
+
+    fn import_path_ops() -> MockPathOps {
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops
+            .expect_exists()
+            .returning(|path| path != Path::new("/missing"));
+        path_ops
+            .expect_is_dir()
+            .returning(|path| path != Path::new("/file.txt"));
+        path_ops.expect_fingerprint().return_const(None);
+        path_ops
+    }
+
+    #[test]
+    // 取り込めない項目が理由とともに報告され、使えない別名は付けずに取り込まれること
+    fn test_import_bookmarks() {
+        let existing = Bookmark::new("/existing", vec![]).with_name(Some("api".to_string()));
+        let mut repo = MockBookmarkRepository::new(std::slice::from_ref(&existing));
+        let entries = vec![
+            entry("/web", Some("web"), Some(3.0), None),
+            entry("/existing", None, None, None),
+            entry("/web", None, None, None),
+            entry("/missing", None, None, None),
+            entry("/file.txt", None, None, None),
+            entry("relative", None, None, None),
+            entry("/api", Some("api"), None, None),
+            entry("/docs", Some("my docs"), None, None),
+        ];
+
+        let summary = import_bookmarks(
+            &mut repo,
+            &import_path_ops(),
+            &clock(),
+            &entries,
+            ImportOptions::default(),
+        )
+        .unwrap();
+        let imported = vec![
+            Bookmark::new("/web", vec![]).with_name(Some("web".to_string())),
+            Bookmark::new("/api", vec![]),
+            Bookmark::new("/docs", vec![]),
+        ];
+        assert_eq!(summary.imported, imported);
+        let skipped: Vec<(&str, &str)> = summary
+            .skipped
+            .iter()
+            .map(|s| (s.item.as_str(), s.reason.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("/existing", "already bookmarked"),
+                ("/web", "duplicate entry"),
+                ("/missing", "directory does not exist"),
+                ("/file.txt", "not a directory"),
+                ("relative", "not an absolute path"),
+                (
+                    "name 'api' of /api",
+                    "already used by /existing; imported without the name"
+                ),
+                (
+                    "name 'my docs' of /docs",
+                    "not a valid bookmark name; imported without the name"
+                ),
+            ]
+        );
+        assert_eq!(
+            repo.find_all().unwrap(),
+            [vec![existing], imported].concat()
+        );
+    }
+
+    #[test]
+    // 展開すると登録済みのものや先に取り込んだものと同じ場所になる項目は取り込まれないこと
+    fn test_import_bookmarks_portable_duplicates() {
+        let existing = Bookmark::new("~/api", vec![]);
+        let mut repo = MockBookmarkRepository::new(std::slice::from_ref(&existing));
+        let mut path_ops = import_path_ops();
+        path_ops
+            .expect_expand()
+            .returning(|p| Ok(Path::new("/home/me").join(p.strip_prefix("~").unwrap())));
+        let entries = vec![
+            entry("/home/me/api", None, None, None),
+            entry("~/web", None, None, None),
+            entry("/home/me/web", None, None, None),
+        ];
+
+        let summary = import_bookmarks(
+            &mut repo,
+            &path_ops,
+            &clock(),
+            &entries,
+            ImportOptions::default(),
+        )
+        .unwrap();
+        let skipped: Vec<(&str, &str)> = summary
+            .skipped
+            .iter()
+            .map(|s| (s.item.as_str(), s.reason.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("/home/me/api", "already bookmarked"),
+                ("/home/me/web", "duplicate entry"),
+            ]
+        );
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![existing, Bookmark::new("~/web", vec![])]
+        );
+    }
+
+    #[test]
+    // スコアが訪問回数に、最後に使われた時刻が訪問の時刻になり、合計が上限を超えれば縮められること
+    fn test_import_bookmarks_with_scores() {
+        let mut repo = MockBookmarkRepository::new(&[]);
+        let entries = vec![
+            entry("/a", None, Some(1499.6), Some(NOW - 10)),
+            entry("/b", None, Some(300.0), None),
+            entry("/c", None, Some(0.2), Some(NOW - 10)),
+            entry("/d", None, None, None),
+        ];
+
+        let options = ImportOptions {
+            with_scores: true,
+            ..Default::default()
+        };
+        import_bookmarks(&mut repo, &import_path_ops(), &clock(), &entries, options).unwrap();
+        let visits: Vec<(u32, Option<u64>)> = repo
+            .find_all()
+            .unwrap()
+            .iter()
+            .map(|b| (b.get_visits(), b.get_last_visited()))
+            .collect();
+        // 1500 + 300 を 900 に縮める
+        assert_eq!(
+            visits,
+            vec![
+                (750, Some(NOW - 10)),
+                (150, Some(NOW)),
+                (0, None),
+                (0, None)
+            ]
+        );
+    }
+
+    #[test]
+    // 書き出したブックマークはタグや訪問の記録、特徴をそのまま取り込み、存在しないディレクトリも残せること
+    fn test_import_bookmarks_keeps_exported_bookmarks() {
+        let mut repo = MockBookmarkRepository::new(&[]);
+        let mut exported = visited("/missing", 3, 0)
+            .with_name(Some("api".to_string()))
+            .with_fingerprint(Some(Fingerprint {
+                inode: Some((1, 2)),
+                git_remote: None,
+            }));
+        exported.set_tags(vec!["work".to_string()]);
+        let entries = vec![Entry {
+            bookmark: exported.clone(),
+            score: None,
+            last_accessed: None,
+        }];
+        let options = ImportOptions {
+            with_scores: true,
+            keep_missing: true,
+        };
+
+        let summary =
+            import_bookmarks(&mut repo, &import_path_ops(), &clock(), &entries, options).unwrap();
+        assert_eq!(summary.skipped, vec![]);
+        assert_eq!(repo.find_all().unwrap(), vec![exported]);
+    }
+
+    #[test]
+    // 全てのプロファイルのブックマークがプロファイル名とともに取得され、選択中のプロファイルは変わらないこと
+    fn test_list_bookmarks_in_all_profiles() {
+        let mut repo = MockBookmarkRepository::new(&[Bookmark::new("/default", vec![])]);
+        repo.create_profile("work").unwrap();
+        repo.select_profile("work").unwrap();
+        repo.save(&Bookmark::new("/work", vec![])).unwrap();
+
+        let result =
+            list_bookmarks_in_all_profiles(&mut repo, &clock(), SortOrder::Added, None).unwrap();
+        assert_eq!(
+            result,
+            vec![
+                ("default".to_string(), Bookmark::new("/default", vec![])),
+                ("work".to_string(), Bookmark::new("/work", vec![])),
+            ]
+        );
+        assert_eq!(repo.current_profile().unwrap(), "work");
+    }
+
+    #[test]
+    // プロファイル名を付けた候補から選択されること
+    fn test_search_bookmark_in_all_profiles() {
+        let mut repo = MockBookmarkRepository::new(&[Bookmark::new("/default", vec![])]);
+        repo.create_profile("work").unwrap();
+        repo.select_profile("work").unwrap();
+        repo.save(&Bookmark::new("/work", vec![])).unwrap();
+        let mut selector = MockBookmarkSelector::new();
+        selector
+            .expect_select_index()
+            .withf(|labels, _| labels == ["[default] /default", "[work] /work"])
+            .returning(|_, _| Ok(Some(1)));
+
+        let result = search_bookmark_in_all_profiles(&mut repo, &selector, &clock(), None).unwrap();
+        assert_eq!(
+            result,
+            Some(("work".to_string(), Bookmark::new("/work", vec![])))
+        );
+    }
+
+    #[test]
+    // デフォルトのプロファイルに印が付くこと
+    fn test_list_profiles() {
+        let mut repo = MockBookmarkRepository::new(&[]);
+        repo.create_profile("work").unwrap();
+        default_profile(&mut repo, Some("work")).unwrap();
+
+        assert_eq!(
+            list_profiles(&mut repo).unwrap(),
+            vec![("default".to_string(), false), ("work".to_string(), true)]
+        );
+        assert_eq!(default_profile(&mut repo, None).unwrap(), "work");
+    }
+
+    #[rstest]
+    // 別名が一致するブックマークが取得されること
+    #[case("api", Ok("/path/to/api"))]
+    // 見つからない場合は似た別名が候補に挙がること
+    #[case("apj", Err("No bookmark named 'apj' (did you mean: api, app?)"))]
+    #[case("we", Err("No bookmark named 'we' (did you mean: web?)"))]
+    #[case("docs", Err("No bookmark named 'docs'"))]
+    fn test_go_bookmark(#[case] name: &str, #[case] expected: Result<&str, &str>) {
+        let bookmarks = vec![
+            Bookmark::new("/path/to/api", vec![]).with_name(Some("api".to_string())),
+            Bookmark::new("/path/to/app", vec![]).with_name(Some("app".to_string())),
+            Bookmark::new("/path/to/web", vec![]).with_name(Some("web".to_string())),
+            Bookmark::new("/path/to/other", vec![]),
+        ];
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+
+        let result = go_bookmark(&mut repo, &clock(), name)
+            .map(|bookmark| bookmark.get_path().to_path_buf())
+            .map_err(|e| e.to_string());
+        assert_eq!(
+            result,
+            expected
+                .map(PathBuf::from)
+                .map_err(|message| message.to_string())
+        );
+    }
+
+    fn query_bookmarks() -> Vec<Bookmark> {
+        vec![
+            Bookmark::new("/src/cobweb", vec![]),
+            Bookmark::new("/src/web", vec![]),
+            Bookmark::new("/src/api", vec![]),
+        ]
+    }
+
+    #[rstest]
+    // 一致が1件だけなら選択されること
+    #[case("api", false, QueryResult::Selected("/src/api"))]
+    // 一致しない
+    #[case("docs", false, QueryResult::NoMatch)]
+    // 複数一致して対話的に選択できない場合は一致の良い順に返すこと
+    #[case("web", false, QueryResult::Ambiguous(vec!["/src/web", "/src/cobweb"]))]
+    // 複数一致して対話的に選択できる場合は一致したものから選択されること
+    #[case("web", true, QueryResult::Selected("/src/cobweb"))]
+    fn test_search_bookmark_by_query(
+        #[case] query: &str,
+        #[case] interactive: bool,
+        #[case] expected: QueryResult<&str>,
+    ) {
+        let mut repo = MockBookmarkRepository::new(&query_bookmarks());
+        let mut selector = MockBookmarkSelector::new();
+        selector
+            .expect_select_index()
+            .withf(|labels, _| labels == ["/src/web", "/src/cobweb"])
+            .returning(|_, _| Ok(Some(1)));
+
+        let result =
+            search_bookmark_by_query(&mut repo, &selector, &clock(), query, interactive, None)
+                .unwrap();
+        let expected = expected.map(|p| Bookmark::new(p, vec![]));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    // 選択が取り消された場合
+    fn test_search_bookmark_by_query_cancelled() {
+        let mut repo = MockBookmarkRepository::new(&query_bookmarks());
+        let mut selector = MockBookmarkSelector::new();
+        selector.expect_select_index().returning(|_, _| Ok(None));
+
+        let result =
+            search_bookmark_by_query(&mut repo, &selector, &clock(), "web", true, None).unwrap();
+        assert_eq!(result, QueryResult::Cancelled);
+    }
+
+    #[test]
+    // 全てのプロファイルから検索できること
+    fn test_search_bookmark_in_all_profiles_by_query() {
+        let mut repo = MockBookmarkRepository::new(&query_bookmarks());
+        repo.create_profile("work").unwrap();
+        repo.select_profile("work").unwrap();
+        repo.save(&Bookmark::new("/work/api", vec![])).unwrap();
+        let selector = MockBookmarkSelector::new();
+
+        let result = search_bookmark_in_all_profiles_by_query(
+            &mut repo,
+            &selector,
+            &clock(),
+            "work",
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            QueryResult::Selected(("work".to_string(), Bookmark::new("/work/api", vec![])))
+        );
+    }
+
+    fn file_bookmarks() -> Vec<Bookmark> {
+        vec![
+            Bookmark::new("/src/notes", vec![]),
+            Bookmark::new("/src/notes/todo.md", vec![]).with_kind(Kind::File),
+            visited("/src/notes/ideas.md", 3, 0).with_kind(Kind::File),
+        ]
+    }
+
+    #[rstest]
+    // ファイルのブックマークだけが対象になり、選ばれたファイルがエディタで開かれること
+    #[case(Some("notes"), Some("/src/notes/todo.md"))]
+    // 一致しなければ何も開かないこと
+    #[case(Some("readme"), None)]
+    // クエリがなければよく使うものから並べて選択させること
+    #[case(None, Some("/src/notes/ideas.md"))]
+    fn test_open_bookmark(#[case] query: Option<&str>, #[case] expected: Option<&str>) {
+        let mut repo = MockBookmarkRepository::new(&file_bookmarks());
+        let mut selector = MockBookmarkSelector::new();
+        selector
+            .expect_select_index()
+            .withf(|labels, _| labels == ["/src/notes/todo.md", "/src/notes/ideas.md"])
+            .returning(|_, _| Ok(Some(0)));
+        selector
+            .expect_select()
+            .withf(|bookmarks, _| bookmarks[0].get_path() == Path::new("/src/notes/ideas.md"))
+            .returning(|bookmarks, _| Ok(Some(bookmarks[0].clone())));
+        let mut editor = MockEditor::new();
+        let opened = expected.map(PathBuf::from);
+        editor
+            .expect_open()
+            .withf(move |path| Some(path) == opened.as_deref())
+            .times(expected.iter().count())
+            .returning(|_| Ok(()));
+
+        let result = open_bookmark(
+            &mut repo,
+            &selector,
+            &editor,
+            &MockPathOps::new(),
+            &clock(),
+            query,
+            true,
+            None,
+        )
+        .unwrap();
+        match expected {
+            Some(path) => {
+                let QueryResult::Selected(bookmark) = result else {
+                    panic!("a bookmark must be selected");
+                };
+                assert_eq!(bookmark.get_path(), Path::new(path));
+            }
+            None => assert_eq!(result, QueryResult::NoMatch),
+        }
+    }
+
+    #[test]
+    // ファイルのブックマークがなければエラーになること
+    fn test_open_bookmark_without_files() {
+        let mut repo = MockBookmarkRepository::new(&[Bookmark::new("/src/notes", vec![])]);
+        let selector = MockBookmarkSelector::new();
+        let editor = MockEditor::new();
+
+        assert!(open_bookmark(
+            &mut repo,
+            &selector,
+            &editor,
+            &MockPathOps::new(),
+            &clock(),
+            None,
+            true,
+            None
+        )
+        .is_err());
+    }
+
+    fn visited(path: &str, visits: u32, elapsed: u64) -> Bookmark {
+        let mut bookmark = Bookmark::new(path, vec![]);
+        for _ in 0..visits {
+            bookmark.visit(NOW - elapsed);
+        }
+        bookmark
+    }
+
+    #[test]
+    // 選択肢がよく使う順に並び、選ばれたブックマークの訪問が記録されること
+    fn test_search_bookmark_records_visit() {
+        let bookmarks = vec![
+            Bookmark::new("/never", vec![]),
+            visited("/stale", 5, 30 * 24 * 3600),
+            visited("/recent", 1, 60),
+        ];
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let mut selector = MockBookmarkSelector::new();
+        selector
+            .expect_select()
+            .withf(|items, _| {
+                let paths: Vec<_> = items.iter().map(|b| b.get_path()).collect();
+                paths == ["/recent", "/stale", "/never"].map(std::path::Path::new)
+            })
+            .returning(|items, _| Ok(Some(items[2].clone())));
+
+        search_bookmark(&mut repo, &selector, &clock(), None).unwrap();
+        let never = repo
+            .find_all()
+            .unwrap()
+            .into_iter()
+            .find(|b| b.get_path() == std::path::Path::new("/never"))
+            .unwrap();
+        assert_eq!(never.get_visits(), 1);
+        assert_eq!(never.get_last_visited(), Some(NOW));
+    }
+
+    #[test]
+    // bm goで選ばれたブックマークの訪問が記録されること
+    fn test_go_bookmark_records_visit() {
+        let bookmark = Bookmark::new("/path/to/api", vec![]).with_name(Some("api".to_string()));
+        let mut repo = MockBookmarkRepository::new(&[bookmark]);
+
+        go_bookmark(&mut repo, &clock(), "api").unwrap();
+        go_bookmark(&mut repo, &clock(), "api").unwrap();
+        assert_eq!(repo.find_all().unwrap()[0].get_visits(), 2);
+    }
+
+    #[test]
+    // 訪問回数の合計が上限を超えたら、全てのブックマークの訪問回数が縮められること
+    fn test_record_visit_ages_all_bookmarks() {
+        let bookmarks = vec![
+            visited("/a", frecency::MAX_TOTAL_VISITS - 10, 0),
+            visited("/b", 10, 0),
+        ];
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+
+        record_visit(&mut repo, &clock(), &bookmarks[1]).unwrap();
+        let visits: Vec<u32> = repo
+            .find_all()
+            .unwrap()
+            .iter()
+            .map(|b| b.get_visits())
+            .collect();
+        assert_eq!(visits, vec![890, 9]);
+    }
+
+    #[test]
+    // よく使う順に一覧できること
+    fn test_list_bookmarks_by_frecency() {
+        let bookmarks = vec![
+            Bookmark::new("/never", vec![]),
+            visited("/daily", 3, 3600),
+            visited("/recent", 1, 60),
+        ];
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+
+        let result = list_bookmarks(&mut repo, &clock(), SortOrder::Frecency, None).unwrap();
+        assert_eq!(
+            result,
+            vec![
+                bookmarks[1].clone(),
+                bookmarks[2].clone(),
+                bookmarks[0].clone()
+            ]
+        );
+    }
+
+    #[test]
+    // 訪問したことのあるブックマークだけがスコアの高い順に取得されること
+    fn test_bookmark_stats() {
+        let bookmarks = vec![
+            Bookmark::new("/never", vec![]),
+            visited("/weekly", 4, 2 * 24 * 3600),
+            visited("/recent", 1, 60),
+        ];
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+
+        let result = bookmark_stats(&mut repo, &clock(), 10).unwrap();
+        assert_eq!(
+            result,
+            vec![(4.0, bookmarks[2].clone()), (2.0, bookmarks[1].clone())]
+        );
+        assert_eq!(bookmark_stats(&mut repo, &clock(), 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    // 登録し直しても訪問の記録が残ること
+    fn test_add_bookmark_keeps_visits() {
+        let mut repo = MockBookmarkRepository::new(&[visited("/path/to/dir", 3, 60)]);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_exists().returning(|_| true);
+        path_ops.expect_is_dir().returning(|_| true);
+        path_ops.expect_fingerprint().returning(|_| None);
+
+        let path = Some(PathBuf::from("/path/to/dir"));
+        add_bookmark(
+            &mut repo,
+            &path_ops,
+            path,
+            None,
+            vec!["tag".to_string()],
+            Kind::Dir,
+            false,
+        )
+        .unwrap();
+        let bookmarks = repo.find_all().unwrap();
+        assert_eq!(bookmarks[0].get_tags(), ["tag"]);
+        assert_eq!(bookmarks[0].get_visits(), 3);
+    }
+
+    fn tagged_bookmarks() -> Vec<Bookmark> {
+        vec![
+            Bookmark::new("/nonexistent/rust", vec!["rust".to_string()]),
+            Bookmark::new(
+                "/nonexistent/old-rust",
+                vec!["rust".to_string(), "archived".to_string()],
+            ),
+            Bookmark::new("/nonexistent/infra", vec!["infra".to_string()]),
+        ]
+    }
+
+    #[test]
+    // タグの条件に合うブックマークだけが一覧されること
+    fn test_list_bookmarks_with_filter() {
+        let bookmarks = tagged_bookmarks();
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let filter = TagQuery::parse("rust & !archived").unwrap();
+
+        let result = list_bookmarks(&mut repo, &clock(), SortOrder::Added, Some(&filter));
+        assert_eq!(result.unwrap(), vec![bookmarks[0].clone()]);
+    }
+
+    #[test]
+    // タグの条件に合うブックマークだけが選択肢になること
+    fn test_delete_bookmark_with_filter() {
+        let bookmarks = tagged_bookmarks();
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let mut selector = MockBookmarkSelector::new();
+        selector
+            .expect_select_many()
+            .withf(|labels, _, _| labels.len() == 2)
+            .returning(|_, _, _| Ok(vec![1]));
+        let filter = TagQuery::parse("rust").unwrap();
+
+        delete_bookmark(&mut repo, &selector, Some(&filter)).unwrap();
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![bookmarks[0].clone(), bookmarks[2].clone()]
+        );
+    }
+
+    fn mounted() -> MockMountTable {
+        let mut mounts = MockMountTable::new();
+        mounts.expect_unmounted_mount_point().return_const(None);
+        mounts
     }
-    Ok(())
-}
 
-pub fn search_bookmark(
-    bookmark_repo: &mut dyn IBookmarkRepository,
-    selector: &dyn BookmarkSelector,
-) -> Result<Option<Bookmark>, Box<dyn std::error::Error>> {
-    let bookmarks = bookmark_repo.find_all()?;
-    let bookmark = select_bookmark(selector, &bookmarks)?;
-    Ok(bookmark)
-}
+    fn prune_options() -> PruneOptions {
+        PruneOptions {
+            dry_run: false,
+            interactive: false,
+            max_fraction: 1.0,
+            force: false,
+        }
+    }
 
-pub fn list_bookmarks(
-    bookmark_repo: &mut dyn IBookmarkRepository,
-) -> Result<Vec<Bookmark>, Box<dyn std::error::Error>> {
-    let bookmarks = bookmark_repo.find_all()?;
-    Ok(bookmarks)
-}
+    fn broken_bookmarks() -> Vec<Bookmark> {
+        vec![
+            Bookmark::new("/", vec![]),
+            Bookmark::new("/nonexistent/a", vec![]),
+            Bookmark::new("/media/usb/b", vec![]),
+            Bookmark::new("/nonexistent/c", vec![]),
+        ]
+    }
 
-pub fn prune_bookmarks(
-    bookmark_repo: &mut dyn IBookmarkRepository,
-) -> Result<Vec<Bookmark>, Box<dyn std::error::Error>> {
-    let bookmarks = bookmark_repo.find_all()?;
-    let mut deleted_bookmarks = Vec::new();
+    #[rstest]
+    // マウントされていないドライブの下のブックマークは残されること
+    #[case(false, 1.0, Ok(vec!["/nonexistent/a", "/nonexistent/c"]))]
+    // 削除する割合が上限を超えると中止されること
+    #[case(false, 0.4, Err(()))]
+    #[case(false, 0.5, Ok(vec!["/nonexistent/a", "/nonexistent/c"]))]
+    // 強制すれば全て削除されること
+    #[case(true, 0.4, Ok(vec!["/nonexistent/a", "/media/usb/b", "/nonexistent/c"]))]
+    fn test_prune_bookmarks_safeguards(
+        #[case] force: bool,
+        #[case] max_fraction: f64,
+        #[case] expected: Result<Vec<&str>, ()>,
+    ) {
+        let bookmarks = broken_bookmarks();
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let mut mounts = MockMountTable::new();
+        mounts.expect_unmounted_mount_point().returning(|path| {
+            path.starts_with("/media/usb")
+                .then(|| PathBuf::from("/media/usb"))
+        });
+        let options = PruneOptions {
+            max_fraction,
+            force,
+            ..prune_options()
+        };
 
-    for bookmark in bookmarks {
-        let is_broken = bookmark.is_broken()?;
-        if is_broken {
-            bookmark_repo.delete(&bookmark.get_path())?;
-            deleted_bookmarks.push(bookmark);
+        let result = prune_bookmarks(
+            &mut repo,
+            &MockPathOps::new(),
+            &mounts,
+            &MockBookmarkSelector::new(),
+            options,
+            None,
+        );
+        let deleted = result.as_ref().map_err(|_| ()).map(|report| {
+            report
+                .deleted
+                .iter()
+                .map(|b| b.get_path().to_str().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(deleted, expected);
+        let remaining = repo.find_all().unwrap();
+        match result {
+            Ok(report) => {
+                assert_eq!(remaining.len(), bookmarks.len() - report.deleted.len());
+                if !force {
+                    assert_eq!(
+                        report.skipped,
+                        vec![(bookmarks[2].clone(), PathBuf::from("/media/usb"))]
+                    );
+                }
+            }
+            Err(_) => assert_eq!(remaining, bookmarks),
         }
     }
-    Ok(deleted_bookmarks)
-}
 
-fn select_bookmark(
-    selector: &dyn BookmarkSelector,
-    bookmarks: &Vec<Bookmark>,
-) -> Result<Option<Bookmark>, dialoguer::Error> {
-    let prompt = format!("{} Select a bookmark (type to filter): ", Emoji("🔖", ""));
-    selector.select(bookmarks, prompt)
-}
+    #[rstest]
+    // 試すだけなら削除されないこと
+    #[case(1.0, Ok(3))]
+    // 削除する割合が上限を超えれば、実際に削除する場合と同じく中止されること
+    #[case(0.5, Err(()))]
+    fn test_prune_bookmarks_dry_run(
+        #[case] max_fraction: f64,
+        #[case] expected: Result<usize, ()>,
+    ) {
+        let bookmarks = broken_bookmarks();
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let options = PruneOptions {
+            dry_run: true,
+            max_fraction,
+            ..prune_options()
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::path::MockPathOps;
-    use crate::repository::MockBookmarkRepository;
-    use crate::selector::MockBookmarkSelector;
-    use rstest::rstest;
+        let result = prune_bookmarks(
+            &mut repo,
+            &MockPathOps::new(),
+            &mounted(),
+            &MockBookmarkSelector::new(),
+            options,
+            None,
+        );
+        assert_eq!(
+            result.map(|report| report.deleted.len()).map_err(|_| ()),
+            expected
+        );
+        assert_eq!(repo.find_all().unwrap(), bookmarks);
+    }
 
     #[test]
-    // ブックマークが登録されること
-    fn test_add_bookmark() {
-        let path = Some("/path/to/dir".to_string());
-        let tags = vec!["tag1".to_string(), "tag2".to_string()]; // Add tags
-
-        let mut repo = MockBookmarkRepository::new(&[]);
+    // `~`や`$NAME`で始まるパスは展開して確かめ、展開できないものは残されること
+    fn test_prune_bookmarks_expands_paths() {
+        let bookmarks = vec![
+            Bookmark::new("~", vec![]),
+            Bookmark::new("~/nonexistent", vec![]),
+            Bookmark::new("$UNSET/a", vec![]),
+        ];
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
         let mut path_ops = MockPathOps::new();
-        path_ops.expect_exists().returning(|_| true);
-        path_ops.expect_is_dir().returning(|_| true);
+        path_ops
+            .expect_expand()
+            .returning(|path| match path.strip_prefix("~") {
+                Ok(rest) => Ok(Path::new("/").join(rest)),
+                Err(_) => Err(Error::new(ErrorKind::NotFound, "$UNSET is not set")),
+            });
 
-        let result = add_bookmark(&mut repo, &path_ops, path, tags.clone()); // Pass tags
-        assert!(result.is_ok());
+        let report = prune_bookmarks(
+            &mut repo,
+            &path_ops,
+            &mounted(),
+            &MockBookmarkSelector::new(),
+            prune_options(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.deleted, vec![bookmarks[1].clone()]);
         assert_eq!(
             repo.find_all().unwrap(),
-            vec![Bookmark::new("/path/to/dir", tags)] // Check tags
+            vec![bookmarks[0].clone(), bookmarks[2].clone()]
         );
     }
 
-    #[rstest]
-    // パスが空文字列の場合、カレントディレクトリが登録されること
-    #[case(Some("".to_string()), "/current/dir")]
-    // パスがNoneの場合、カレントディレクトリが登録されること
-    #[case(None, "/current/dir")]
-    fn test_add_bookmark_with_empty_or_none_path(
-        #[case] path: Option<String>,
-        #[case] expected_path: &str,
-    ) {
-        let tags = vec!["tag1".to_string(), "tag2".to_string()]; // Add tags
-        let mut repo = MockBookmarkRepository::new(&[]);
+    #[test]
+    // 選ばれたブックマークだけが削除され、割合の上限は確認されないこと
+    fn test_prune_bookmarks_interactive() {
+        let bookmarks = broken_bookmarks();
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let mut selector = MockBookmarkSelector::new();
+        selector
+            .expect_select_many()
+            .withf(|labels, _, checked| labels.len() == 3 && *checked)
+            .returning(|_, _, _| Ok(vec![0, 2]));
+        let options = PruneOptions {
+            interactive: true,
+            max_fraction: 0.0,
+            ..prune_options()
+        };
+
+        let report = prune_bookmarks(
+            &mut repo,
+            &MockPathOps::new(),
+            &mounted(),
+            &selector,
+            options,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            report.deleted,
+            vec![bookmarks[1].clone(), bookmarks[3].clone()]
+        );
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![bookmarks[0].clone(), bookmarks[2].clone()]
+        );
+    }
+
+    #[test]
+    // タグの条件に合う壊れたブックマークだけが削除されること
+    fn test_prune_bookmarks_with_filter() {
+        let bookmarks = tagged_bookmarks();
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let filter = TagQuery::parse("archived | infra").unwrap();
+
+        let report = prune_bookmarks(
+            &mut repo,
+            &MockPathOps::new(),
+            &mounted(),
+            &MockBookmarkSelector::new(),
+            prune_options(),
+            Some(&filter),
+        )
+        .unwrap();
+        assert_eq!(
+            report.deleted,
+            vec![bookmarks[1].clone(), bookmarks[2].clone()]
+        );
+        assert_eq!(repo.find_all().unwrap(), vec![bookmarks[0].clone()]);
+    }
+
+    #[test]
+    // クエリによる検索でもタグの条件が適用されること
+    fn test_search_bookmark_by_query_with_filter() {
+        let bookmarks = tagged_bookmarks();
+        let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let selector = MockBookmarkSelector::new();
+        let filter = TagQuery::parse("!archived").unwrap();
+
+        let result =
+            search_bookmark_by_query(&mut repo, &selector, &clock(), "rust", false, Some(&filter))
+                .unwrap();
+        assert_eq!(result, QueryResult::Selected(bookmarks[0].clone()));
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    fn current_dir(dir: &str) -> MockPathOps {
+        let dir = PathBuf::from(dir);
         let mut path_ops = MockPathOps::new();
+        let base = dir.clone();
+        path_ops
+            .expect_canonicalize()
+            .returning(move |p| Ok(base.join(p)));
         path_ops
             .expect_get_current_dir()
-            .returning(|| Ok("/current/dir".to_string()));
-        path_ops.expect_exists().returning(|_| true);
-        path_ops.expect_is_dir().returning(|_| true);
+            .returning(move || Ok(dir.clone()));
+        path_ops
+    }
 
-        let result = add_bookmark(&mut repo, &path_ops, path, tags.clone()); // Pass tags
-        assert!(result.is_ok());
+    #[rstest]
+    // 別名で指定できること
+    #[case("api", vec!["rust", "web"])]
+    // 相対パスは現在のディレクトリから解決されること
+    #[case("web", vec!["web", "rust"])]
+    #[case("/home/user/web", vec!["web", "rust"])]
+    fn test_add_tags(#[case] target: &str, #[case] expected: Vec<&str>) {
+        let mut repo = MockBookmarkRepository::new(&[
+            Bookmark::new("/home/user/api", tags(&["rust"])).with_name(Some("api".to_string())),
+            Bookmark::new("/home/user/web", vec![]),
+        ]);
+        let path_ops = current_dir("/home/user");
+
+        let bookmark = add_tags(&mut repo, &path_ops, target, tags(&["web", "rust"])).unwrap();
+        assert_eq!(bookmark.get_tags(), tags(&expected));
+        let saved = repo.find_all().unwrap();
+        assert!(saved.contains(&bookmark));
+    }
+
+    #[test]
+    // 使えるホストを置き換えられ、空にすればどのホストでも使えるようになること
+    fn test_set_hosts() {
+        let mut repo = MockBookmarkRepository::new(&[
+            Bookmark::new("/home/user/api", vec![]).with_name(Some("api".to_string()))
+        ]);
+        let path_ops = current_dir("/home/user");
+
+        let hosts = tags(&["laptop", "devbox", "laptop"]);
+        let bookmark = set_hosts(&mut repo, &path_ops, "api", hosts).unwrap();
+        assert_eq!(bookmark.get_hosts(), tags(&["laptop", "devbox"]));
+        assert_eq!(repo.find_all().unwrap(), vec![bookmark]);
+        let bookmark = set_hosts(&mut repo, &path_ops, "api", vec![]).unwrap();
+        assert!(bookmark.get_hosts().is_empty());
+        assert!(set_hosts(&mut repo, &path_ops, "api", tags(&["my host"])).is_err());
+        assert!(set_hosts(&mut repo, &path_ops, "web", tags(&["laptop"])).is_err());
+    }
+
+    #[test]
+    // タグの削除と置き換えができ、存在しないブックマークや空のタグはエラーになること
+    fn test_remove_and_set_tags() {
+        let mut repo =
+            MockBookmarkRepository::new(&[Bookmark::new("/path", tags(&["a", "b", "c"]))]);
+        let path_ops = current_dir("/home/user");
+
+        let bookmark = remove_tags(&mut repo, &path_ops, "/path", tags(&["b", "x"])).unwrap();
+        assert_eq!(bookmark.get_tags(), tags(&["a", "c"]));
+        let bookmark = set_tags(&mut repo, &path_ops, "/path", tags(&["d", "d", "e"])).unwrap();
+        assert_eq!(bookmark.get_tags(), tags(&["d", "e"]));
+        let bookmark = set_tags(&mut repo, &path_ops, "/path", vec![]).unwrap();
+        assert!(bookmark.get_tags().is_empty());
+
+        assert!(add_tags(&mut repo, &path_ops, "/other", tags(&["a"])).is_err());
+        assert!(add_tags(&mut repo, &path_ops, "/path", tags(&[""])).is_err());
         assert_eq!(
             repo.find_all().unwrap(),
-            vec![Bookmark::new(expected_path, tags)] // Check tags
+            vec![Bookmark::new("/path", vec![])]
         );
     }
 
     #[test]
-    // パスが存在しない場合、エラーが返ること
-    fn test_add_bookmark_with_nonexistent_path() {
-        let path = Some("/nonexistent/path".to_string());
+    // 全てのブックマークでタグの名前が変わり、既存のタグとは衝突しないこと
+    fn test_rename_tag() {
+        let mut repo = MockBookmarkRepository::new(&[
+            Bookmark::new("/path/a", tags(&["js", "web"])),
+            Bookmark::new("/path/b", tags(&["js"])),
+            Bookmark::new("/path/c", tags(&["rust"])),
+        ]);
 
-        let mut repo = MockBookmarkRepository::new(&[]);
-        let mut path_ops = MockPathOps::new();
-        path_ops.expect_exists().returning(|_| false);
+        assert_eq!(rename_tag(&mut repo, "js", "javascript").unwrap(), 2);
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![
+                Bookmark::new("/path/a", tags(&["javascript", "web"])),
+                Bookmark::new("/path/b", tags(&["javascript"])),
+                Bookmark::new("/path/c", tags(&["rust"])),
+            ]
+        );
 
-        let result = add_bookmark(&mut repo, &path_ops, path, vec![]); // Pass empty tags
-        assert!(result.is_err());
+        let error = rename_tag(&mut repo, "javascript", "rust").unwrap_err();
+        assert!(error.to_string().contains("bm tag merge javascript rust"));
+        assert!(rename_tag(&mut repo, "js", "node").is_err());
     }
 
     #[test]
-    // パスがディレクトリでない場合、エラーが返ること
-    fn test_add_bookmark_with_non_dir_path() {
-        let path = Some("/file".to_string());
+    // タグがまとめられ、重複したタグは1つになること
+    fn test_merge_tags() {
+        let mut repo = MockBookmarkRepository::new(&[
+            Bookmark::new("/path/a", tags(&["js", "javascript"])),
+            Bookmark::new("/path/b", tags(&["js", "web"])),
+            Bookmark::new("/path/c", tags(&["rust"])),
+        ]);
 
-        let mut repo = MockBookmarkRepository::new(&[]);
-        let mut path_ops = MockPathOps::new();
-        path_ops.expect_exists().returning(|_| true);
-        path_ops.expect_is_dir().returning(|_| false);
+        assert_eq!(merge_tags(&mut repo, "js", "javascript").unwrap(), 2);
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![
+                Bookmark::new("/path/a", tags(&["javascript"])),
+                Bookmark::new("/path/b", tags(&["javascript", "web"])),
+                Bookmark::new("/path/c", tags(&["rust"])),
+            ]
+        );
+        assert!(merge_tags(&mut repo, "js", "javascript").is_err());
+        assert!(merge_tags(&mut repo, "rust", "rust").is_err());
+    }
 
-        let result = add_bookmark(&mut repo, &path_ops, path, vec![]); // Pass empty tags
-        assert!(result.is_err());
+    #[test]
+    // 名前の付いたブックマークの名前だけが名前順に取得されること
+    fn test_bookmark_names() {
+        let mut repo = MockBookmarkRepository::new(&[
+            Bookmark::new("/path/a", vec![]).with_name(Some("web".to_string())),
+            Bookmark::new("/path/b", vec![]),
+            Bookmark::new("/path/c", vec![]).with_name(Some("api".to_string())),
+        ]);
+        assert_eq!(bookmark_names(&mut repo).unwrap(), vec!["api", "web"]);
     }
 
     #[test]
-    // 正常にブックマークが削除されること
-    fn test_delete_bookmark() {
-        let bookmark = Bookmark::new("/path/to/dir", vec![]);
+    // タグが多い順、同数なら名前順に数えられること
+    fn test_count_tags() {
+        let mut repo = MockBookmarkRepository::new(&[
+            Bookmark::new("/path/a", tags(&["web", "rust"])),
+            Bookmark::new("/path/b", tags(&["rust"])),
+            Bookmark::new("/path/c", tags(&["cli"])),
+        ]);
+        assert_eq!(
+            count_tags(&mut repo).unwrap(),
+            vec![
+                ("rust".to_string(), 2),
+                ("cli".to_string(), 1),
+                ("web".to_string(), 1),
+            ]
+        );
+    }
 
-        let mut repo = MockBookmarkRepository::new(&[bookmark.clone()]);
-        let mut selector = MockBookmarkSelector::new();
-        selector
-            .expect_select()
-            .returning(|_, _| Ok(Some(Bookmark::new("/path/to/dir", vec![]))));
+    /// 受け取ったテキストを記録し、順に決まった編集結果を返すエディタ
+    fn editor(responses: Vec<&str>) -> (MockEditor, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut responses = responses.into_iter().map(String::from).collect::<Vec<_>>();
+        responses.reverse();
+        let mut editor = MockEditor::new();
+        let recorder = seen.clone();
+        editor.expect_edit().returning(move |text| {
+            recorder.lock().unwrap().push(text.to_string());
+            Ok(responses.pop().expect("editor is opened too many times"))
+        });
+        (editor, seen)
+    }
 
-        let result = delete_bookmark(&mut repo, &selector);
-        assert!(result.is_ok());
-        assert!(repo.find_all().unwrap().is_empty());
+    fn existing_dirs() -> MockPathOps {
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_exists().return_const(true);
+        path_ops.expect_is_dir().return_const(true);
+        path_ops.expect_fingerprint().return_const(None);
+        path_ops
     }
 
     #[test]
-    // 該当するブックマークが存在しない場合は何もせずに正常終了
-    fn test_delete_bookmark_no_match() {
-        let bookmark = Bookmark::new("/path/to/dir", vec![]);
+    // 編集の差分が追加・更新・削除として反映され、訪問の記録は残ること
+    fn test_edit_bookmarks() {
+        let mut repo = MockBookmarkRepository::new(&[
+            visited("/path/a", 3, 0),
+            Bookmark::new("/path/b", tags(&["tag"])),
+            Bookmark::new("/path/c", vec![]),
+        ]);
+        let (editor, seen) = editor(vec![
+            "/path/a @a #new\n# comment\n\n/path/c\n/path/d #tag\n",
+        ]);
 
-        let mut repo = MockBookmarkRepository::new(&[bookmark.clone()]);
-        let mut selector = MockBookmarkSelector::new();
-        selector.expect_select().returning(|_, _| Ok(None));
+        let summary = edit_bookmarks(&mut repo, &editor, &existing_dirs(), None).unwrap();
+        assert_eq!(
+            summary,
+            Some(EditSummary {
+                added: 1,
+                updated: 1,
+                deleted: 1
+            })
+        );
+        assert!(seen.lock().unwrap()[0].ends_with("/path/a\n/path/b #tag\n/path/c\n"));
+        let bookmarks = repo.find_all().unwrap();
+        assert_eq!(
+            bookmarks.iter().map(|b| b.to_string()).collect::<Vec<_>>(),
+            vec!["/path/a @a #new", "/path/c", "/path/d #tag"]
+        );
+        assert_eq!(bookmarks[0].get_visits(), 3);
+    }
 
-        let result = delete_bookmark(&mut repo, &selector);
-        assert!(result.is_ok());
-        assert_eq!(repo.find_all().unwrap(), vec![bookmark]);
+    #[test]
+    // 誤りがあればコメントを付けて開き直され、直されたら反映されること
+    fn test_edit_bookmarks_with_errors() {
+        let mut repo = MockBookmarkRepository::new(&[Bookmark::new("/path/a", vec![])]);
+        let (editor, seen) = editor(vec![
+            "/path/a @x\n/path/a\nrelative\n/path/b \\q\n",
+            "/path/a @x\n# error: stale\n/path/b\n",
+        ]);
+
+        let summary = edit_bookmarks(&mut repo, &editor, &existing_dirs(), None).unwrap();
+        assert_eq!(
+            summary,
+            Some(EditSummary {
+                added: 1,
+                updated: 1,
+                deleted: 0
+            })
+        );
+        assert_eq!(
+            seen.lock().unwrap()[1],
+            "/path/a @x\n\
+             /path/a\n\
+             # error: duplicate bookmark (also on line 1)\n\
+             relative\n\
+             # error: path must be absolute or start with ~ or $NAME: relative\n\
+             /path/b \\q\n\
+             # error: invalid escape: \\q\n"
+        );
+    }
+
+    #[rstest]
+    // 空にされたら中止されること
+    #[case(vec![""], Ok(None))]
+    #[case(vec!["\n  \n"], Ok(None))]
+    // 誤りを直さずに保存されたら中止されること
+    #[case(vec!["/nonexistent\n", "/nonexistent\n# error: path does not exist: /nonexistent\n"], Err(()))]
+    fn test_edit_bookmarks_aborted(
+        #[case] responses: Vec<&str>,
+        #[case] expected: Result<Option<EditSummary>, ()>,
+    ) {
+        let init_bookmarks = vec![Bookmark::new("/path/a", vec![])];
+        let mut repo = MockBookmarkRepository::new(&init_bookmarks);
+        let (editor, _) = editor(responses);
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_exists().return_const(false);
+
+        let result = edit_bookmarks(&mut repo, &editor, &path_ops, None).map_err(|_| ());
+        assert_eq!(result, expected);
+        assert_eq!(repo.find_all().unwrap(), init_bookmarks);
     }
 
     #[test]
-    // 正常にブックマークが取得できること
-    fn test_search_bookmark() {
-        let bookmarks = vec![Bookmark::new("/path/to/dir", vec![])];
+    // 条件に合わないブックマークは変更されず、その別名とは重複できないこと
+    fn test_edit_bookmarks_with_filter() {
+        let mut repo = MockBookmarkRepository::new(&[
+            Bookmark::new("/path/a", tags(&["rust"])),
+            Bookmark::new("/path/b", vec![]).with_name(Some("b".to_string())),
+        ]);
+        let (editor, seen) = editor(vec!["/path/a @b #rust\n", "/path/a @a #rust\n"]);
+        let filter = TagQuery::parse("rust").unwrap();
 
-        let mut repo = MockBookmarkRepository::new(&bookmarks);
-        let mut selector = MockBookmarkSelector::new();
-        selector
-            .expect_select()
-            .returning(|_, _| Ok(Some(Bookmark::new("/path/to/dir", vec![]))));
+        edit_bookmarks(&mut repo, &editor, &existing_dirs(), Some(&filter)).unwrap();
+        assert!(seen.lock().unwrap()[0].contains("\n/path/a #rust\n"));
+        assert!(!seen.lock().unwrap()[0].contains("/path/b"));
+        assert!(
+            seen.lock().unwrap()[1].contains("# error: bookmark name already in use: b (/path/b)")
+        );
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![
+                Bookmark::new("/path/a", tags(&["rust"])).with_name(Some("a".to_string())),
+                Bookmark::new("/path/b", vec![]).with_name(Some("b".to_string())),
+            ]
+        );
+    }
 
-        let result = search_bookmark(&mut repo, &selector);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), Some(Bookmark::new("/path/to/dir", vec![])));
+    fn dedupe_path_ops() -> MockPathOps {
+        let mut path_ops = MockPathOps::new();
+        path_ops.expect_canonicalize().returning(|p| {
+            Ok(match p.to_str().unwrap() {
+                "/srv/app/" | "/srv/./app" | "/link/app" => PathBuf::from("/srv/app"),
+                "/web/" => PathBuf::from("/web"),
+                p => PathBuf::from(p),
+            })
+        });
+        path_ops
+    }
+
+    #[rstest]
+    // 同じ場所を指すものは最初のものにまとめ、正規化されていないパスは直すこと
+    #[case(false)]
+    // dry_runなら対象を返すだけで保存しないこと
+    #[case(true)]
+    fn test_dedupe_bookmarks(#[case] dry_run: bool) {
+        let original = vec![
+            visited("/srv/app/", 1, 100).with_name(Some("app".to_string())),
+            Bookmark::new("/other", tags(&["x"])),
+            Bookmark::new("/srv/./app", tags(&["a", "b"])),
+            Bookmark::new("/web/", vec![]),
+            visited("/link/app", 2, 0).with_fingerprint(Some(Fingerprint {
+                inode: Some((1, 2)),
+                git_remote: None,
+            })),
+        ];
+        let mut repo = MockBookmarkRepository::new(&original);
+
+        let merged = dedupe_bookmarks(&mut repo, &dedupe_path_ops(), dry_run).unwrap();
+        let mut app = visited("/srv/app", 3, 0).with_name(Some("app".to_string()));
+        app.set_tags(tags(&["a", "b"]));
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(
+            merged,
+            vec![
+                (
+                    app.clone(),
+                    paths(&["/srv/app/", "/srv/./app", "/link/app"])
+                ),
+                (Bookmark::new("/web", vec![]), paths(&["/web/"])),
+            ]
+        );
+        let expected = if dry_run {
+            original
+        } else {
+            vec![
+                app,
+                Bookmark::new("/other", tags(&["x"])),
+                Bookmark::new("/web", vec![]),
+            ]
+        };
+        let saved = repo.find_all().unwrap();
+        assert_eq!(saved, expected);
+        assert_eq!(
+            saved
+                .iter()
+                .map(|b| b.get_path().as_os_str())
+                .collect::<Vec<_>>(),
+            expected
+                .iter()
+                .map(|b| b.get_path().as_os_str())
+                .collect::<Vec<_>>()
+        );
     }
 
     #[test]
-    // ブックマークが１件もない場合はNoneを返すこと
-    fn test_search_bookmark_no_bookmarks() {
-        let mut repo = MockBookmarkRepository::new(&[]);
-        let mut selector = MockBookmarkSelector::new();
-        selector.expect_select().returning(|_, _| Ok(None));
+    // 別名やタグ、訪問の記録を残したままパスが変わること
+    fn test_move_bookmark() {
+        let bookmark = visited("/path/old", 2, 0).with_name(Some("api".to_string()));
+        let mut repo =
+            MockBookmarkRepository::new(&[bookmark, Bookmark::new("/path/other", vec![])]);
+        let mut path_ops = current_dir("/path");
+        path_ops.expect_exists().return_const(true);
+        path_ops.expect_is_dir().return_const(true);
+        path_ops
+            .expect_fingerprint()
+            .return_const(Some(Fingerprint {
+                inode: Some((1, 2)),
+                git_remote: None,
+            }));
 
-        let result = search_bookmark(&mut repo, &selector);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), None);
+        let moved = move_bookmark(&mut repo, &path_ops, "api", PathBuf::from("new")).unwrap();
+        assert_eq!(moved.get_path(), Path::new("/path/new"));
+        assert_eq!(moved.get_name(), Some("api"));
+        assert_eq!(moved.get_visits(), 2);
+        assert!(moved.get_fingerprint().is_some());
+        assert_eq!(repo.find_all().unwrap()[0], moved);
+
+        // 既にブックマークされている場所には移せない
+        assert!(move_bookmark(&mut repo, &path_ops, "api", PathBuf::from("other")).is_err());
+        assert!(move_bookmark(&mut repo, &path_ops, "web", PathBuf::from("/path/web")).is_err());
+        assert_eq!(repo.find_all().unwrap()[0], moved);
     }
 
     #[test]
-    // 全てのブックマークが取得されること
-    fn test_list_bookmarks() {
-        let bookmarks = vec![
-            Bookmark::new("/path/to/dir1", vec![]),
-            Bookmark::new("/path/to/dir2", vec![]),
-        ];
+    // 選ばれた移動先に直され、削除が選ばれたものは候補がなくても削除されること
+    fn test_relocate_bookmarks() {
+        let mut repo = MockBookmarkRepository::new(&[
+            Bookmark::new("/nonexistent/a", tags(&["a"])),
+            Bookmark::new("/nonexistent/b", vec![]),
+            Bookmark::new("/nonexistent/c", vec![]),
+            Bookmark::new("/nonexistent/d", vec![]),
+            Bookmark::new("/", vec![]),
+        ]);
+        let mut relocator = MockRelocator::new();
+        relocator.expect_find_candidates().returning(|bookmark| {
+            let candidate = |path: &str, reason| Candidate {
+                path: PathBuf::from(path),
+                reason,
+            };
+            match bookmark.get_path().to_str().unwrap() {
+                // 既にブックマークされている場所は候補から除かれる
+                "/nonexistent/a" => vec![
+                    candidate("/", Reason::Inode),
+                    candidate("/new/a", Reason::Name),
+                ],
+                "/nonexistent/b" => vec![],
+                _ => vec![candidate("/new/cd", Reason::Name)],
+            }
+        });
+        let mut selector = MockBookmarkSelector::new();
+        selector.expect_select_index().returning(|labels, prompt| {
+            if prompt.contains("/nonexistent/a") {
+                assert_eq!(
+                    labels,
+                    [
+                        "/new/a (same name)",
+                        "Keep the bookmark as it is",
+                        "Delete the bookmark"
+                    ]
+                );
+                Ok(Some(0))
+            } else if prompt.contains("/nonexistent/b") {
+                assert!(prompt.contains("was not found elsewhere"));
+                assert_eq!(
+                    labels,
+                    ["Keep the bookmark as it is", "Delete the bookmark"]
+                );
+                Ok(Some(1))
+            } else if prompt.contains("/nonexistent/c") {
+                Ok(Some(1))
+            } else {
+                Ok(Some(2))
+            }
+        });
+        let mut path_ops = MockPathOps::new();
+        path_ops
+            .expect_canonicalize()
+            .returning(|p| Ok(p.to_path_buf()));
+        path_ops.expect_fingerprint().return_const(None);
 
+        let summary = relocate_bookmarks(
+            &mut repo,
+            &path_ops,
+            &mounted(),
+            &selector,
+            &relocator,
+            prune_options(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            RelocateSummary {
+                deleted: vec![
+                    Bookmark::new("/nonexistent/b", vec![]),
+                    Bookmark::new("/nonexistent/d", vec![]),
+                ],
+                relocated: vec![(
+                    Bookmark::new("/nonexistent/a", tags(&["a"])),
+                    PathBuf::from("/new/a")
+                )],
+                skipped: vec![],
+            }
+        );
+        assert_eq!(
+            repo.find_all().unwrap(),
+            vec![
+                Bookmark::new("/new/a", tags(&["a"])),
+                Bookmark::new("/nonexistent/c", vec![]),
+                Bookmark::new("/", vec![]),
+            ]
+        );
+    }
+
+    #[rstest]
+    // 削除する割合が上限を超えると、移動先を直すものも含めて何も変更せずに中止されること
+    #[case(false, Err(()))]
+    // 強制すれば削除されること
+    #[case(true, Ok(3))]
+    fn test_relocate_bookmarks_safeguards(
+        #[case] force: bool,
+        #[case] expected: Result<usize, ()>,
+    ) {
+        let bookmarks = broken_bookmarks();
         let mut repo = MockBookmarkRepository::new(&bookmarks);
+        let mut relocator = MockRelocator::new();
+        relocator.expect_find_candidates().returning(|_| vec![]);
+        let mut selector = MockBookmarkSelector::new();
+        selector.expect_select_index().returning(|_, _| Ok(Some(1)));
+        let mut path_ops = MockPathOps::new();
+        path_ops.expect_fingerprint().return_const(None);
+        let options = PruneOptions {
+            max_fraction: 0.5,
+            force,
+            ..prune_options()
+        };
 
-        let result = list_bookmarks(&mut repo);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), bookmarks);
+        let result = relocate_bookmarks(
+            &mut repo,
+            &path_ops,
+            &mounted(),
+            &selector,
+            &relocator,
+            options,
+            None,
+        );
+        let deleted = result.map_err(|_| ()).map(|summary| summary.deleted.len());
+        assert_eq!(deleted, expected);
+        let remaining = repo.find_all().unwrap();
+        match expected {
+            Ok(_) => assert_eq!(remaining, vec![Bookmark::new("/", vec![])]),
+            Err(_) => assert_eq!(remaining, bookmarks),
+        }
+    }
+
+    fn operation(id: u64) -> Operation {
+        Operation {
+            id,
+            time: 0,
+            command: format!("cmd{}", id),
+            changes: vec![],
+        }
     }
 
     #[test]
-    // ブックマークがない場合は空のベクタが取得されること
-    fn test_list_bookmarks_empty() {
-        let mut repo = MockBookmarkRepository::new(&[]);
+    // 取り消せる操作もやり直せる操作もなければエラーになること
+    fn test_undo_redo_nothing() {
+        let mut history = MockHistory::new();
+        history.expect_undo().returning(|| Ok(None));
+        history.expect_redo().returning(|| Ok(None));
 
-        let result = list_bookmarks(&mut repo);
-        assert!(result.is_ok());
-        assert!(result.unwrap().is_empty());
+        assert_eq!(
+            undo_operation(&mut history).unwrap_err().to_string(),
+            "Nothing to undo"
+        );
+        assert_eq!(
+            redo_operation(&mut history).unwrap_err().to_string(),
+            "Nothing to redo"
+        );
+    }
+
+    #[rstest]
+    #[case(10, vec![3, 2, 1])]
+    // 件数を絞ると新しいものが残ること
+    #[case(2, vec![3, 2])]
+    fn test_list_history(#[case] limit: usize, #[case] expected: Vec<u64>) {
+        let mut history = MockHistory::new();
+        history.expect_history().returning(|| {
+            Ok(vec![
+                (operation(1), false),
+                (operation(2), false),
+                (operation(3), true),
+            ])
+        });
+
+        let ids: Vec<u64> = list_history(&mut history, limit)
+            .unwrap()
+            .into_iter()
+            .map(|(o, _)| o.id)
+            .collect();
+        assert_eq!(ids, expected);
     }
 }
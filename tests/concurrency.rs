@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::thread;
+
+const PROCESSES: usize = 8;
+const BOOKMARKS_PER_PROCESS: usize = 5;
+
+fn bm(home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bm"))
+        .args(args)
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join(".config"))
+        .env("BM_FILE", home.join("bookmarks.json"))
+        .output()
+        .unwrap()
+}
+
+fn listed_paths(home: &Path) -> BTreeSet<String> {
+    let output = bm(home, &["list"]);
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[test]
+// 複数のプロセスから同時に追加・削除しても、ブックマークが失われないこと
+fn test_concurrent_add_and_prune() {
+    let home = tempfile::tempdir().unwrap();
+    let home = home.path();
+
+    // pruneで消える壊れたブックマーク
+    let broken: Vec<PathBuf> = (0..PROCESSES)
+        .map(|i| home.join(format!("broken{}", i)))
+        .collect();
+    for dir in &broken {
+        fs::create_dir(dir).unwrap();
+        assert!(bm(home, &["add", dir.to_str().unwrap()]).status.success());
+        fs::remove_dir(dir).unwrap();
+    }
+
+    let dirs: Vec<Vec<PathBuf>> = (0..PROCESSES)
+        .map(|i| {
+            (0..BOOKMARKS_PER_PROCESS)
+                .map(|j| {
+                    let dir = home.join(format!("dir{}-{}", i, j));
+                    fs::create_dir(&dir).unwrap();
+                    dir
+                })
+                .collect()
+        })
+        .collect();
+
+    thread::scope(|s| {
+        for process_dirs in &dirs {
+            s.spawn(move || {
+                for dir in process_dirs {
+                    let output = bm(home, &["add", dir.to_str().unwrap()]);
+                    assert!(output.status.success(), "{:?}", output);
+                }
+                // 途中では壊れたブックマークの割合が大きいため、安全のための上限を外す
+                let output = bm(home, &["prune", "--force"]);
+                assert!(output.status.success(), "{:?}", output);
+            });
+        }
+    });
+
+    let expected: BTreeSet<String> = dirs
+        .iter()
+        .flatten()
+        .map(|dir| dir.to_str().unwrap().to_string())
+        .collect();
+    assert_eq!(listed_paths(home), expected);
+}
+
+#[test]
+// 複数のプロセスから同時に同じ別名で追加しても、別名は1つのブックマークにしか付かないこと
+fn test_concurrent_add_with_same_name() {
+    let home = tempfile::tempdir().unwrap();
+    let home = home.path();
+    let dirs: Vec<PathBuf> = (0..PROCESSES)
+        .map(|i| {
+            let dir = home.join(format!("dir{}", i));
+            fs::create_dir(&dir).unwrap();
+            dir
+        })
+        .collect();
+
+    let succeeded = thread::scope(|s| {
+        let handles: Vec<_> = dirs
+            .iter()
+            .map(|dir| {
+                s.spawn(move || {
+                    bm(home, &["add", dir.to_str().unwrap(), "--name", "api"])
+                        .status
+                        .success()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|succeeded| *succeeded)
+            .count()
+    });
+
+    assert_eq!(succeeded, 1);
+    let output = bm(home, &["list", "--format", "porcelain", "--fields", "name"]);
+    let names = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(names.lines().filter(|name| *name == "api").count(), 1);
+}